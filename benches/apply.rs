@@ -0,0 +1,65 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use markings::{Args, Opts, Template};
+
+// a template with `keys` keys, separated by some literal text
+fn input(keys: usize) -> String {
+    (0..keys)
+        .map(|i| format!("key {} is ${{k{}}}. ", i, i))
+        .collect()
+}
+
+fn args(keys: usize) -> Args<'static> {
+    (0..keys)
+        .map(|i| (format!("k{}", i), "some value"))
+        .collect()
+}
+
+// replacing each key in turn, which reallocates the output for every key
+fn replace_each(input: &str, args: &[(String, &str)]) -> String {
+    let mut output = input.to_string();
+    for (key, val) in args {
+        output = output.replace(&format!("${{{}}}", key), val);
+    }
+    output
+}
+
+fn apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply");
+    for &keys in &[4, 16, 64, 256] {
+        let input = input(keys);
+        let template = Template::parse(&input, Opts::default()).unwrap();
+        let args = args(keys);
+        group.bench_with_input(BenchmarkId::new("template", keys), &keys, |b, _| {
+            b.iter(|| template.apply(&args).unwrap())
+        });
+
+        let pairs = (0..keys)
+            .map(|i| (format!("k{}", i), "some value"))
+            .collect::<Vec<_>>();
+        group.bench_with_input(BenchmarkId::new("replace_each", keys), &keys, |b, _| {
+            b.iter(|| replace_each(&input, &pairs))
+        });
+    }
+    group.finish();
+}
+
+// templates with a few keys, compare with and without the `smallvec` feature
+fn small(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small");
+    let input = "hello ${name}, you have ${count} new ${kind}";
+    let args = Args::new()
+        .with("name", "bob")
+        .with("count", 3)
+        .with("kind", "messages");
+
+    group.bench_function("parse", |b| {
+        b.iter(|| Template::parse(input, Opts::default()).unwrap())
+    });
+
+    let template = Template::parse(input, Opts::default()).unwrap();
+    group.bench_function("apply", |b| b.iter(|| template.apply(&args).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, apply, small);
+criterion_main!(benches);
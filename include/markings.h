@@ -0,0 +1,80 @@
+#ifndef MARKINGS_H
+#define MARKINGS_H
+
+/* The C interface of the markings crate, built with the `ffi` feature */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MarkingsTemplate MarkingsTemplate;
+
+#define MARKINGS_OK 0
+#define MARKINGS_INVALID_ARGUMENT (-1)
+
+/* error codes, see `markings::ffi::error_code` */
+#define MARKINGS_ERROR_MISMATCHED_BRACES 1
+#define MARKINGS_ERROR_EXPECTED_CLOSING 2
+#define MARKINGS_ERROR_EXPECTED_OPENING 3
+#define MARKINGS_ERROR_NESTED_TEMPLATE 4
+#define MARKINGS_ERROR_DUPLICATE_KEYS 5
+#define MARKINGS_ERROR_EMPTY_TEMPLATE 6
+#define MARKINGS_ERROR_OPTIONAL_KEYS 7
+#define MARKINGS_ERROR_OUTPUT_TOO_LARGE 8
+#define MARKINGS_ERROR_TEMPLATE_TOO_LONG 9
+#define MARKINGS_ERROR_TOO_MANY_KEYS 10
+#define MARKINGS_ERROR_KEY_TOO_LONG 11
+#define MARKINGS_ERROR_UNSUPPORTED_SYNTAX 12
+#define MARKINGS_ERROR_WRONG_VALUE_COUNT 13
+#define MARKINGS_ERROR_ARG_USE_COUNT 14
+#define MARKINGS_ERROR_UNCLOSED_BLOCK 15
+#define MARKINGS_ERROR_UNMATCHED_BLOCK_END 16
+#define MARKINGS_ERROR_MISPLACED_ELSE 17
+#define MARKINGS_ERROR_UNKNOWN_TEMPLATE 18
+#define MARKINGS_ERROR_UNKNOWN_FILTER 19
+#define MARKINGS_ERROR_INVALID_FILTER 20
+#define MARKINGS_ERROR_FILTER 21
+#define MARKINGS_ERROR_NO_MATCH 22
+#define MARKINGS_ERROR_IO 23
+#define MARKINGS_ERROR_UNSUPPORTED_VERSION 24
+#define MARKINGS_ERROR_UNKNOWN_PRESET 25
+#define MARKINGS_ERROR_MISSING_KEYS 26
+#define MARKINGS_ERROR_INVALID_EDIT 27
+#define MARKINGS_ERROR_INVALID_VALUE 28
+#define MARKINGS_ERROR_SCHEMA_VIOLATIONS 29
+#define MARKINGS_ERROR_ARITHMETIC 30
+#define MARKINGS_ERROR_FORBIDDEN_FILTER 31
+#define MARKINGS_ERROR_FORBIDDEN_KEY 32
+#define MARKINGS_ERROR_SANDBOXED 33
+#define MARKINGS_ERROR_DUPLICATE_ARG 34
+#define MARKINGS_ERROR_INVALID_ARG 35
+#define MARKINGS_ERROR_EXTENDS_CYCLE 36
+#define MARKINGS_ERROR_UNKNOWN_SPEC 37
+
+/* options for `markings_parse` */
+#define MARKINGS_OPTIONAL_KEYS (1u << 0)
+#define MARKINGS_DUPLICATE_KEYS (1u << 1)
+#define MARKINGS_EMPTY_TEMPLATE (1u << 2)
+#define MARKINGS_CASE_INSENSITIVE_KEYS (1u << 3)
+
+/* parses `input`, the template is freed with `markings_free` */
+int markings_parse(const char *input, uint32_t options, MarkingsTemplate **out);
+
+/* applies `len` keys and values, the output is freed with `markings_string_free` */
+int markings_apply(const MarkingsTemplate *tmpl,
+                   const char *const *keys,
+                   const char *const *values,
+                   size_t len,
+                   char **out);
+
+void markings_free(MarkingsTemplate *tmpl);
+void markings_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,308 @@
+use crate::{
+    ArgKey, ArgValues, Args, Error, ErrorKind, Opts, RedactedArgs, Result, Segment, Template,
+};
+use std::borrow::Cow;
+
+/// A template of bytes, for inputs that aren't UTF-8, like binary protocols and files
+///
+/// It uses the same `${key}` markers as a [`Template`](./struct.Template.html), and the same options, but only
+/// plain keys: blocks, filters, fallbacks, annotations and expressions are an `UnsupportedSyntax` error. The names
+/// of keys have to be UTF-8, while everything around them can be any bytes
+///
+/// With [`Opts::escape`](./struct.Opts.html#method.escape), values have to be UTF-8 to be escaped, so other values
+/// are an `InvalidValue` error
+/// ```
+/// # use markings::{ByteTemplate, ByteArgs, Opts};
+/// let template = ByteTemplate::parse(b"\x02${id}\x1f${payload}\x03", Opts::default()).unwrap();
+/// let args = ByteArgs::new().with("id", 7).with_bytes("payload", &b"\xff\x00"[..]);
+/// assert_eq!(template.apply(&args).unwrap(), b"\x027\x1f\xff\x00\x03");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ByteTemplate<'a> {
+    template: Template<'a>,
+    // where the literal bytes that aren't UTF-8 are in the template, which has a `?` for each of their bytes
+    bytes: Vec<(usize, Cow<'a, [u8]>)>,
+}
+
+impl<'a> ByteTemplate<'a> {
+    /// Parses a new template from bytes, with the options
+    ///
+    /// A key whose name isn't UTF-8 is an `UnsupportedSyntax` error
+    pub fn parse(input: &'a [u8], opts: Opts) -> Result<Self> {
+        if let Some(limit) = opts.max_template_len {
+            if input.len() > limit {
+                return Err(ErrorKind::TemplateTooLong { limit }.into());
+            }
+        }
+
+        let mut template = Template::empty(opts);
+        let mut bytes = vec![];
+        let mut literal = |template: &mut Template<'a>, literal: &'a [u8]| {
+            let mut pos = literal.as_ptr() as usize - input.as_ptr() as usize;
+            for chunk in literal.utf8_chunks() {
+                if !chunk.valid().is_empty() {
+                    template
+                        .segments
+                        .push(Segment::Literal(chunk.valid().into()));
+                }
+                pos += chunk.valid().len();
+                if !chunk.invalid().is_empty() {
+                    let placeholder = "?".repeat(chunk.invalid().len());
+                    template.segments.push(Segment::Literal(placeholder.into()));
+                    bytes.push((pos, chunk.invalid().into()));
+                }
+                pos += chunk.invalid().len();
+            }
+        };
+
+        let mut last = 0;
+        for (marker, key) in Template::find_markers(input, &template.opts)? {
+            let unsupported = || {
+                let err = ErrorKind::UnsupportedSyntax { pos: marker.start };
+                Error::from(err).with_span(marker.clone())
+            };
+            let name = std::str::from_utf8(&input[key]).map_err(|_| unsupported())?;
+            let raw = std::str::from_utf8(&input[marker.clone()]).map_err(|_| unsupported())?;
+            let segment = Segment::marker(name, raw, template.opts.trim_key_whitespace);
+            match &segment {
+                Segment::Key {
+                    filters,
+                    spec: None,
+                    fallbacks,
+                    ..
+                } if filters.is_empty() && fallbacks.is_empty() => {}
+                _ => return Err(unsupported()),
+            }
+
+            literal(&mut template, &input[last..marker.start]);
+            last = marker.end;
+            template.segments.push(segment);
+        }
+        literal(&mut template, &input[last..]);
+
+        template.renumber();
+        template.validate()?;
+        Ok(Self { template, bytes })
+    }
+
+    /// Convert the template into one that owns its input
+    pub fn into_owned(self) -> ByteTemplate<'static> {
+        let bytes = self.bytes.into_iter();
+        ByteTemplate {
+            template: self.template.into_owned(),
+            bytes: bytes
+                .map(|(pos, bytes)| (pos, bytes.into_owned().into()))
+                .collect(),
+        }
+    }
+
+    /// The keys of the template, in order
+    pub fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.template.keys()
+    }
+
+    /// Apply the arguments to the template, returning the output
+    ///
+    /// Like [`Template::apply`](./struct.Template.html#method.apply), keys without an argument are a `MissingKeys`
+    /// error and arguments without a key are an `OptionalKeys` error, unless keys are optional. Optional keys
+    /// without an argument are written as their markers
+    pub fn apply(&self, byte_args: &ByteArgs<'_>) -> Result<Vec<u8>> {
+        // the template is rendered with the values as text, and the bytes that aren't UTF-8 are put back after
+        let values = byte_args
+            .values
+            .convert(|val| placeholder(val).into_owned());
+        let args = Args { values };
+
+        let (pieces, len) = self.template.pieces(&args)?;
+        let mut output = Vec::with_capacity(len);
+        let mut bytes = self.bytes.iter().peekable();
+        for (piece, key, span) in pieces {
+            if let Some((_, bytes)) = bytes.next_if(|&&(pos, _)| pos == span.start) {
+                output.extend_from_slice(bytes);
+                continue;
+            }
+
+            let opts = &self.template.opts;
+            let val = key
+                .and_then(|key| self.template.lookup_arg(&args, key))
+                .and_then(|(arg, _)| byte_args.values.value(arg, opts.duplicate_args))
+                .filter(|val| std::str::from_utf8(val).is_err());
+            match val {
+                Some(..) if opts.escapes() => {
+                    let (key, spec) = (key.unwrap_or_default().to_string(), "utf-8".to_string());
+                    let err = Error::from(ErrorKind::InvalidValue { key, spec });
+                    return Err(err.with_span(span));
+                }
+                Some(val) => output.extend_from_slice(val),
+                None => output.extend_from_slice(piece.as_bytes()),
+            }
+        }
+        Ok(output)
+    }
+}
+
+// the bytes as text of the same length, with a `?` for each byte that isn't UTF-8, so limits count the bytes
+fn placeholder(bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        text.extend(chunk.invalid().iter().map(|_| '?'));
+    }
+    Cow::Owned(text)
+}
+
+/// Arguments for a [`ByteTemplate`](./struct.ByteTemplate.html), whose values are bytes
+#[derive(Clone, Default, PartialEq)]
+pub struct ByteArgs<'k> {
+    values: ArgValues<'k, Cow<'k, [u8]>>,
+}
+
+impl<'k> ByteArgs<'k> {
+    /// Create a new, empty args
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps a key to a value, which is formatted as UTF-8 with `Display`
+    pub fn with(self, key: impl Into<Cow<'k, str>>, val: impl std::fmt::Display) -> Self {
+        self.with_bytes(key, val.to_string().into_bytes())
+    }
+
+    /// Maps a key to a value of bytes
+    pub fn with_bytes(
+        mut self,
+        key: impl Into<Cow<'k, str>>,
+        val: impl Into<Cow<'k, [u8]>>,
+    ) -> Self {
+        self.values
+            .insert(ArgKey::from(key.into()), val.into(), false);
+        self
+    }
+
+    /// Get the number of arguments
+    pub fn len(&self) -> usize {
+        self.values.mapping.len()
+    }
+
+    /// Whether there are no arguments
+    pub fn is_empty(&self) -> bool {
+        self.values.mapping.is_empty()
+    }
+}
+
+impl std::fmt::Debug for ByteArgs<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let values = &self.values;
+        RedactedArgs { values, keys: &[] }.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DuplicateArgs;
+
+    #[test]
+    fn bytes() {
+        let opts = Opts::default().case_insensitive_keys();
+        let input = b"\xff${a}\x00${} ${B}\xfe";
+        let template = ByteTemplate::parse(input, opts.clone()).unwrap();
+        assert_eq!(template.keys().collect::<Vec<_>>(), vec!["a", "0", "B"]);
+
+        let args = ByteArgs::new()
+            .with("a", 1)
+            .with("0", "zero")
+            .with_bytes("b", vec![0x80]);
+        assert_eq!(template.apply(&args).unwrap(), b"\xff1\x00zero \x80\xfe");
+        let owned = template.clone().into_owned();
+        assert_eq!(owned.apply(&args).unwrap(), template.apply(&args).unwrap());
+
+        let err = template.apply(&args.clone().with("c", 2)).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::OptionalKeys);
+        let err = template.apply(&ByteArgs::new().with("a", 1)).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MissingKeys {
+                keys: vec!["0".into(), "B".into()]
+            }
+        );
+        let template = ByteTemplate::parse(input, opts.optional_keys()).unwrap();
+        let output = template.apply(&ByteArgs::new()).unwrap();
+        assert_eq!(output, input);
+
+        for input in [&b"a ${x|upper}"[..], b"a ${?x}${/}", b"a ${\xff}"] {
+            let err = ByteTemplate::parse(input, Opts::default()).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::UnsupportedSyntax { .. }));
+            assert_eq!(err.span().unwrap().start, 2);
+        }
+
+        let opts = Opts::default().percent_keys();
+        let template = ByteTemplate::parse(b"%%%a%\xff%%", opts).unwrap();
+        let output = template.apply(&ByteArgs::new().with("a", 1)).unwrap();
+        assert_eq!(output, b"%1\xff%");
+
+        let err = ByteTemplate::parse(b"${a} ${a}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+        assert_eq!(err.span(), Some(5..9));
+        let err = ByteTemplate::parse(b"\xff", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::EmptyTemplate);
+    }
+
+    #[test]
+    fn opts() {
+        let opts = Opts::default().allowed_keys(&["a"]);
+        let err = ByteTemplate::parse(b"${a} ${b}", opts).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::ForbiddenKey {
+                key: "b".into(),
+                pos: 5
+            }
+        );
+        assert_eq!(err.span(), Some(5..9));
+
+        let opts = Opts::default().duplicate_keys().arg_uses(1);
+        let template = ByteTemplate::parse(b"${a} ${a}", opts).unwrap();
+        let err = template.apply(&ByteArgs::new().with("a", 1)).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::ArgUseCount { found: 2, .. }
+        ));
+
+        let args = ByteArgs::new().with("a", 1).with("a", 2);
+        let apply = |policy| {
+            let template = ByteTemplate::parse(b"${a}", Opts::default().duplicate_args(policy));
+            template.unwrap().apply(&args)
+        };
+        assert_eq!(apply(DuplicateArgs::LastWins).unwrap(), b"2");
+        assert_eq!(apply(DuplicateArgs::FirstWins).unwrap(), b"1");
+        let err = apply(DuplicateArgs::Error).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateArg { key: "a".into() });
+
+        // limits count the bytes that aren't UTF-8 as they are
+        let opts = Opts::default().max_output_len(3);
+        let template = ByteTemplate::parse(b"\xff${a}", opts).unwrap();
+        let args = ByteArgs::new().with_bytes("a", &b"\x80\x81"[..]);
+        assert_eq!(template.apply(&args).unwrap(), b"\xff\x80\x81");
+
+        let opts = Opts::default().escape(crate::Escape::Shell);
+        let template = ByteTemplate::parse(b"rm ${a}", opts).unwrap();
+        let output = template
+            .apply(&ByteArgs::new().with("a", "my file"))
+            .unwrap();
+        assert_eq!(output, b"rm 'my file'");
+        let err = template
+            .apply(&ByteArgs::new().with_bytes("a", &b"\xff"[..]))
+            .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::InvalidValue {
+                key: "a".into(),
+                spec: "utf-8".into()
+            }
+        );
+    }
+}
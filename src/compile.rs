@@ -0,0 +1,172 @@
+use crate::{filters::Chain, Args, ErrorKind, Opts, Result, Segment, Template};
+use std::fmt::Write as _;
+
+/// A template with its keys bound to *slots*, created by [`Template::compile`](./struct.Template.html#method.compile)
+///
+/// Rendering takes the values in slot order, so no keys are looked up per render
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledTemplate {
+    pieces: Vec<Piece>,
+    slots: Vec<String>,
+    opts: Opts,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Piece {
+    Literal(String),
+    /// A slot, and the filters applied to its value
+    Slot(usize, Chain),
+}
+
+impl CompiledTemplate {
+    /// The keys bound to each slot, in slot order
+    pub fn slots(&self) -> &[String] {
+        &self.slots
+    }
+
+    /// Render the template with the values for each slot, in slot order
+    ///
+    /// This fails if the number of values doesn't match the number of slots
+    pub fn render(&self, values: &[impl std::fmt::Display]) -> Result<String> {
+        if values.len() != self.slots.len() {
+            return Err(ErrorKind::WrongValueCount {
+                expected: self.slots.len(),
+                found: values.len(),
+            }
+            .into());
+        }
+
+        let mut data = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(literal) => data.push_str(literal),
+                Piece::Slot(slot, chain) if chain.is_empty() && !self.opts.escapes() => {
+                    let _ = write!(data, "{}", values[*slot]);
+                }
+                Piece::Slot(slot, chain) => {
+                    let val = values[*slot].to_string();
+                    let key = &self.slots[*slot];
+                    data.push_str(&crate::filters::apply(key, chain, &val, &self.opts)?);
+                }
+            }
+
+            if let Some(limit) = self.opts.max_output_len {
+                if data.len() > limit {
+                    return Err(ErrorKind::OutputTooLarge { limit }.into());
+                }
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Bind the keys of the `args` to slots, for rendering many times with different values
+    ///
+    /// The slots are the keys of the `args`, in the order of [`Args::iter`](./struct.Args.html#method.iter).
+    /// The values of the `args` are not used. This fails in the same way that [`Template::apply`](./struct.Template.html#method.apply) would.
+    ///
+    /// Blocks, expressions and fallbacks depend on the values, so templates with them can't be compiled. This fails with `UnsupportedSyntax` for them.
+    ///
+    /// ```
+    /// # use markings::{Args, Template, Opts};
+    /// let template = Template::parse("${greeting}, ${name}!", Opts::default()).unwrap();
+    /// let args = Args::new().with("name", "").with("greeting", "");
+    ///
+    /// let compiled = template.compile(&args).unwrap();
+    /// assert_eq!(compiled.slots(), &["greeting", "name"]);
+    /// assert_eq!(compiled.render(&["hello", "world"]).unwrap(), "hello, world!");
+    /// assert_eq!(compiled.render(&["bye", "bob"]).unwrap(), "bye, bob!");
+    /// ```
+    pub fn compile(&self, args: &Args<'_>) -> Result<CompiledTemplate> {
+        self.check_args(args)?;
+
+        let slots = args
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .collect::<Vec<_>>();
+
+        let mut pieces = vec![];
+        for (segment, span) in self.spans() {
+            let piece = match segment {
+                Segment::Literal(literal) => {
+                    Piece::Literal(self.opts.unescape(literal).into_owned())
+                }
+                Segment::Key {
+                    name,
+                    chain,
+                    fallbacks,
+                    raw,
+                    ..
+                } if fallbacks.is_empty() => {
+                    let slot = slots
+                        .iter()
+                        .position(|slot| slot == name)
+                        .or_else(|| slots.iter().position(|slot| self.opts.key_eq(slot, name)));
+                    match slot {
+                        Some(slot) => Piece::Slot(slot, chain.clone()),
+                        None => Piece::Literal(raw.to_string()),
+                    }
+                }
+                _ => return Err(ErrorKind::UnsupportedSyntax { pos: span.start }.into()),
+            };
+
+            match (pieces.last_mut(), piece) {
+                (Some(Piece::Literal(left)), Piece::Literal(right)) => left.push_str(&right),
+                (_, piece) => pieces.push(piece),
+            }
+        }
+
+        Ok(CompiledTemplate {
+            pieces,
+            slots,
+            opts: self.opts.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn compile() {
+        let opts = Opts::default()
+            .optional_keys()
+            .duplicate_keys()
+            .case_insensitive_keys()
+            .build();
+        let template = Template::parse("${a} ${B} ${a} ${c}", opts.clone()).unwrap();
+        let args = Args::new().with("b", "").with("a", "");
+
+        let compiled = template.compile(&args).unwrap();
+        assert_eq!(compiled.slots(), &["a", "b"]);
+        assert_eq!(compiled.pieces.len(), 6);
+
+        let values = [1, 2];
+        assert_eq!(compiled.render(&values).unwrap(), "1 2 1 ${c}");
+        assert_eq!(
+            template
+                .apply(&Args::new().with("a", 1).with("b", 2))
+                .unwrap(),
+            compiled.render(&values).unwrap()
+        );
+
+        let err = compiled.render(&[1]).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::WrongValueCount {
+                expected: 2,
+                found: 1
+            }
+        );
+
+        let template = Template::parse("${a}", Opts::default()).unwrap();
+        template.compile(&Args::new().with("b", "")).unwrap_err();
+
+        let template = Template::parse("${a}${?a}!${/}", opts).unwrap();
+        let err = template.compile(&Args::new()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 4 });
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{Args, Opts, Result, Template};
+
+/// Apply the args to every string in a TOML value, with the `toml` feature
+///
+/// Each string is parsed as a template with the options, and replaced with its output, so strings without
+/// any keys are kept as they are. Table keys aren't changed. The span of an error is in the string that failed
+/// ```
+/// # use markings::{Args, Opts, interpolate_toml};
+/// let config: toml::Value = toml::from_str(r#"
+///     [server]
+///     url = "https://${host}:${port}"
+///     workers = 4
+/// "#).unwrap();
+/// let args = Args::new().with("host", "example.com").with("port", 443);
+///
+/// let config = interpolate_toml(config, &args, Opts::default()).unwrap();
+/// assert_eq!(config["server"]["url"].as_str(), Some("https://example.com:443"));
+/// assert_eq!(config["server"]["workers"].as_integer(), Some(4));
+/// ```
+#[cfg(feature = "toml")]
+pub fn interpolate_toml(
+    mut value: toml::Value,
+    args: &Args<'_>,
+    opts: Opts,
+) -> Result<toml::Value> {
+    fn walk(value: &mut toml::Value, args: &Args<'_>, opts: &Opts) -> Result<()> {
+        match value {
+            toml::Value::String(s) => interpolate(s, args, opts),
+            toml::Value::Array(values) => values.iter_mut().try_for_each(|v| walk(v, args, opts)),
+            toml::Value::Table(table) => {
+                table.iter_mut().try_for_each(|(_, v)| walk(v, args, opts))
+            }
+            _ => Ok(()),
+        }
+    }
+    walk(&mut value, args, &opts)?;
+    Ok(value)
+}
+
+/// Apply the args to every string in a YAML value, with the `serde_yaml` feature
+///
+/// Each string is parsed as a template with the options, and replaced with its output, so strings without
+/// any keys are kept as they are. Mapping keys aren't changed. The span of an error is in the string that failed
+/// ```
+/// # use markings::{Args, Opts, interpolate_yaml};
+/// let config: serde_yaml::Value = serde_yaml::from_str(r#"
+///     paths: ["${home}/bin", "/usr/bin"]
+///     debug: true
+/// "#).unwrap();
+/// let args = Args::new().with("home", "/home/bob");
+///
+/// let config = interpolate_yaml(config, &args, Opts::default()).unwrap();
+/// assert_eq!(config["paths"][0].as_str(), Some("/home/bob/bin"));
+/// assert_eq!(config["debug"].as_bool(), Some(true));
+/// ```
+#[cfg(feature = "serde_yaml")]
+pub fn interpolate_yaml(
+    mut value: serde_yaml::Value,
+    args: &Args<'_>,
+    opts: Opts,
+) -> Result<serde_yaml::Value> {
+    use serde_yaml::Value;
+    fn walk(value: &mut Value, args: &Args<'_>, opts: &Opts) -> Result<()> {
+        match value {
+            Value::String(s) => interpolate(s, args, opts),
+            Value::Sequence(values) => values.iter_mut().try_for_each(|v| walk(v, args, opts)),
+            Value::Mapping(mapping) => mapping.values_mut().try_for_each(|v| walk(v, args, opts)),
+            Value::Tagged(tagged) => walk(&mut tagged.value, args, opts),
+            _ => Ok(()),
+        }
+    }
+    walk(&mut value, args, &opts)?;
+    Ok(value)
+}
+
+// replaces the string with its output
+fn interpolate(s: &mut String, args: &Args<'_>, opts: &Opts) -> Result<()> {
+    let mut opts = opts.clone();
+    opts.empty_template = true;
+    *s = Template::parse(s, opts)?.apply(args)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml() {
+        let config: toml::Value = toml::from_str(
+            r#"
+            name = "${name}"
+            plain = "no keys"
+            list = [1, "${n}", { nested = "${name}-${n}" }]
+        "#,
+        )
+        .unwrap();
+        let args = Args::new().with("name", "app").with("n", 2);
+
+        let config = interpolate_toml(config, &args, Opts::default()).unwrap();
+        let expected: toml::Value = toml::from_str(
+            r#"
+            name = "app"
+            plain = "no keys"
+            list = [1, "2", { nested = "app-2" }]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+
+        let config: toml::Value = toml::from_str(r#"a = "${missing}""#).unwrap();
+        assert!(interpolate_toml(config, &args, Opts::default()).is_err());
+    }
+
+    #[cfg(feature = "serde_yaml")]
+    #[test]
+    fn yaml() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            name: ${name}
+            plain: no keys
+            list: [1, "${n}", { nested: "${name}-${n}" }]
+            tagged: !env "${name}"
+        "#,
+        )
+        .unwrap();
+        let args = Args::new().with("name", "app").with("n", 2);
+
+        let config = interpolate_yaml(config, &args, Opts::default()).unwrap();
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            name: app
+            plain: no keys
+            list: [1, "2", { nested: "app-2" }]
+            tagged: !env app
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+
+        let config: serde_yaml::Value = serde_yaml::from_str("a: ${missing}").unwrap();
+        assert!(interpolate_yaml(config, &args, Opts::default()).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Conversions to and from other template syntaxes
+use crate::{ErrorKind, Opts, Result, Segment, Template};
+
+impl<'a> Template<'a> {
+    /// Convert the template into a `format!`-style string
+    ///
+    /// Keys become `{key}`, anonymous keys become `{}` and literal braces are escaped as `{{` and `}}`.
+    /// Blocks and expressions can't be expressed in a format string, so they are written as literal text, and filters are dropped.
+    ///
+    /// The keys are also returned in the order they appear.
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("{${name}} is ${age}", Opts::default()).unwrap();
+    /// let (fmt, keys) = template.to_format_string();
+    /// assert_eq!(fmt, "{{{name}}} is {age}");
+    /// assert_eq!(keys, vec!["name", "age"]);
+    /// ```
+    pub fn to_format_string(&self) -> (String, Vec<&str>) {
+        let mut out = String::new();
+        let mut keys = vec![];
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal)
+                | Segment::Block { raw: literal, .. }
+                | Segment::Expr { raw: literal, .. } => {
+                    for ch in literal.chars() {
+                        match ch {
+                            '{' => out.push_str("{{"),
+                            '}' => out.push_str("}}"),
+                            ch => out.push(ch),
+                        }
+                    }
+                }
+                Segment::Key { name, raw, .. } => {
+                    if raw == crate::ANONYMOUS {
+                        out.push_str("{}");
+                    } else {
+                        out.push('{');
+                        out.push_str(name);
+                        out.push('}');
+                    }
+                    keys.push(&**name);
+                }
+            }
+        }
+        (out, keys)
+    }
+
+    /// Parse a template from a `format!`-style string
+    ///
+    /// `{key}` becomes `${key}`, `{}` becomes an anonymous key and `{{` and `}}` are literal braces.
+    /// Format specs, like the `:>5` in `{key:>5}`, are ignored. Literal text can't contain `${`, it would be read back as
+    /// a marker, so that is an `UnsupportedSyntax` error.
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::from_format_string("{{{name}}} is {age:>3}", Opts::default()).unwrap();
+    /// assert_eq!(template.to_string(), "{${name}} is ${age}");
+    /// ```
+    pub fn from_format_string(input: &str, opts: Opts) -> Result<Template<'a>> {
+        let mut template = Template::empty(opts);
+        let mut literal = Literal::default();
+
+        let mut iter = input.char_indices().peekable();
+        while let Some((pos, ch)) = iter.next() {
+            match ch {
+                '{' | '}' if iter.peek().map(|&(_, next)| next) == Some(ch) => {
+                    iter.next();
+                    literal.push(ch.encode_utf8(&mut [0; 4]), pos)?;
+                }
+                '{' => {
+                    let end = loop {
+                        match iter.next() {
+                            Some((end, '}')) => break end,
+                            Some((pos, '{')) => {
+                                return Err(ErrorKind::NestedTemplate { pos }.into())
+                            }
+                            Some(..) => continue,
+                            None => return Err(ErrorKind::ExpectedClosing { head: pos }.into()),
+                        }
+                    };
+
+                    literal.flush(&mut template);
+                    let name = input[pos + 1..end].split(':').next().unwrap_or_default();
+                    template.push(Segment::Key {
+                        filters: vec![],
+                        chain: Default::default(),
+                        spec: None,
+                        fallbacks: vec![],
+                        name: name.to_string().into(),
+                        raw: format!("${{{}}}", name).into(),
+                    });
+                }
+                '}' => return Err(ErrorKind::ExpectedOpening { tail: pos }.into()),
+                ch => literal.push(ch.encode_utf8(&mut [0; 4]), pos)?,
+            }
+        }
+        literal.flush(&mut template);
+
+        template.renumber();
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Parse a template from a C-style `printf` string
+    ///
+    /// * `%s`, `%d`, etc. become anonymous keys, numbered in order of appearance
+    /// * `%1$s`, `%2$d`, etc. become positional keys. These are numbered from 1, so `%1$s` becomes `${0}`
+    /// * `%%` is a literal `%`
+    ///
+    /// Flags, width, precision and length modifiers are ignored. A `*` width or precision,
+    /// or an unknown conversion, is an `UnsupportedSyntax` error, as is a `${` in the literal text.
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::from_printf("%s has %d%% of %2$s", Opts::default().duplicate_keys().build()).unwrap();
+    /// assert_eq!(template.to_string(), "${} has ${}% of ${1}");
+    /// assert_eq!(template.apply_positional(&["bob", "50"]).unwrap(), "bob has 50% of 50");
+    /// ```
+    pub fn from_printf(input: &str, opts: Opts) -> Result<Template<'a>> {
+        let mut template = Template::empty(opts);
+        let mut literal = Literal::default();
+
+        let bytes = input.as_bytes();
+        let mut last = 0;
+        while let Some(offset) = input[last..].find('%') {
+            let head = last + offset;
+            literal.push(&input[last..head], last)?;
+
+            let mut pos = head + 1;
+            if bytes.get(pos) == Some(&b'%') {
+                literal.push("%", head)?;
+                last = pos + 1;
+                continue;
+            }
+
+            let digits = |pos: usize| {
+                bytes[pos..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_digit())
+                    .count()
+            };
+
+            // an explicit position, like the 1 in %1$s
+            let mut position = None;
+            let len = digits(pos);
+            if len > 0 && bytes.get(pos + len) == Some(&b'$') {
+                let n = input[pos..pos + len].parse::<usize>().unwrap_or_default();
+                position = Some(
+                    n.checked_sub(1)
+                        .ok_or(ErrorKind::UnsupportedSyntax { pos: head })?,
+                );
+                pos += len + 1;
+            }
+
+            pos += bytes[pos..]
+                .iter()
+                .take_while(|b| b"-+ #0'".contains(b))
+                .count();
+            pos += digits(pos);
+            if bytes.get(pos) == Some(&b'.') {
+                pos += 1 + digits(pos + 1);
+            }
+            pos += bytes[pos..]
+                .iter()
+                .take_while(|b| b"hlLqjzt".contains(b))
+                .count();
+
+            match bytes.get(pos) {
+                Some(b) if b"diouxXeEfFgGaAcsp".contains(b) => {}
+                _ => return Err(ErrorKind::UnsupportedSyntax { pos: head }.into()),
+            }
+            last = pos + 1;
+
+            literal.flush(&mut template);
+            let name = position.map(|n| n.to_string()).unwrap_or_default();
+            template.push(Segment::Key {
+                filters: vec![],
+                chain: Default::default(),
+                spec: None,
+                fallbacks: vec![],
+                raw: format!("${{{}}}", name).into(),
+                name: name.into(),
+            });
+        }
+        literal.push(&input[last..], last)?;
+        literal.flush(&mut template);
+
+        template.renumber();
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Parse a template from a subset of [mustache](https://mustache.github.io/mustache.5.html)
+    ///
+    /// * `{{key}}`, `{{{key}}}` and `{{& key}}` become `${key}`
+    /// * `{{! comment}}` is removed
+    ///
+    /// Whitespace around the key is trimmed. Values are not HTML escaped.
+    /// Sections, partials and delimiter changes are not supported, and are an `UnsupportedSyntax` error, as is a `${` in
+    /// the literal text.
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let input = "{{! greeting }}hello {{ name }}, {{{html}}}";
+    /// let template = Template::parse_mustache(input, Opts::default()).unwrap();
+    /// assert_eq!(template.to_string(), "hello ${name}, ${html}");
+    /// ```
+    pub fn parse_mustache(input: &str, opts: Opts) -> Result<Template<'a>> {
+        let mut template = Template::empty(opts);
+        let mut literal = Literal::default();
+
+        let mut pos = 0;
+        while let Some(offset) = input[pos..].find("{{") {
+            let head = pos + offset;
+            literal.push(&input[pos..head], pos)?;
+
+            let (open, close) = if input[head..].starts_with("{{{") {
+                ("{{{", "}}}")
+            } else {
+                ("{{", "}}")
+            };
+            let start = head + open.len();
+            let end = input[start..]
+                .find(close)
+                .map(|end| start + end)
+                .ok_or(ErrorKind::ExpectedClosing { head })?;
+            pos = end + close.len();
+
+            let tag = &input[start..end];
+            let name = match tag.chars().next() {
+                Some('!') => continue,
+                Some('&') if open == "{{" => tag[1..].trim(),
+                Some('#') | Some('^') | Some('/') | Some('>') | Some('<') | Some('=')
+                | Some('$') => return Err(ErrorKind::UnsupportedSyntax { pos: head }.into()),
+                _ => tag.trim(),
+            };
+            literal.flush(&mut template);
+            template.push(Segment::Key {
+                filters: vec![],
+                chain: Default::default(),
+                spec: None,
+                fallbacks: vec![],
+                name: name.to_string().into(),
+                raw: format!("${{{}}}", name).into(),
+            });
+        }
+        literal.push(&input[pos..], pos)?;
+        literal.flush(&mut template);
+
+        template.renumber();
+        template.validate()?;
+        Ok(template)
+    }
+}
+
+// literal text for a converted template
+#[derive(Default)]
+struct Literal {
+    text: String,
+    // where the `$` that ends the text is in the input
+    dollar: Option<usize>,
+}
+
+impl Literal {
+    // a `${` in the text would be parsed back as a marker, and there is no way to escape it
+    fn push(&mut self, text: &str, at: usize) -> Result<()> {
+        let pos = match self.dollar.filter(|_| text.starts_with('{')) {
+            Some(pos) => Some(pos),
+            None => text.find("${").map(|pos| at + pos),
+        };
+        if let Some(pos) = pos {
+            return Err(ErrorKind::UnsupportedSyntax { pos }.into());
+        }
+        if !text.is_empty() {
+            self.dollar = Some(at + text.len() - 1).filter(|_| text.ends_with('$'));
+        }
+        self.text.push_str(text);
+        Ok(())
+    }
+
+    fn flush(&mut self, template: &mut Template<'_>) {
+        if !self.text.is_empty() {
+            template.push(Segment::Literal(std::mem::take(&mut self.text).into()));
+        }
+        self.dollar = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+
+    #[test]
+    fn format_string_round_trip() {
+        let opts = Opts::default().duplicate_keys().build();
+        let template = Template::parse("${} {${a}} ${} ${a}", opts.clone()).unwrap();
+        let (fmt, keys) = template.to_format_string();
+        assert_eq!(fmt, "{} {{{a}}} {} {a}");
+        assert_eq!(keys, vec!["0", "a", "1", "a"]);
+
+        let parsed = Template::from_format_string(&fmt, opts).unwrap();
+        assert_eq!(parsed.to_string(), template.to_string());
+
+        let args = Args::new().with("0", 0).with("1", 1).with("a", "a");
+        assert_eq!(parsed.apply(&args).unwrap(), "0 {a} 1 a");
+
+        // a `$` before a key or a brace is still literal text when it is displayed and parsed again
+        let parsed = Template::from_format_string("$ {{ ${a} $}}", Opts::default()).unwrap();
+        let display = parsed.to_string();
+        let reparsed = Template::parse(&display, Opts::default()).unwrap();
+        assert_eq!(reparsed, parsed);
+        assert_eq!(
+            reparsed.apply(&Args::new().with("a", 1)).unwrap(),
+            "$ { $1 $}"
+        );
+    }
+
+    #[test]
+    fn parse_mustache() {
+        let input = "{{a}} {{{ b }}} {{& c}}{{! ignore this }}!";
+        let template = Template::parse_mustache(input, Opts::default()).unwrap();
+        assert_eq!(template.to_string(), "${a} ${b} ${c}!");
+
+        let args = Args::new().with("a", 1).with("b", "<b>").with("c", 3);
+        assert_eq!(template.apply(&args).unwrap(), "1 <b> 3!");
+
+        let err = Template::parse_mustache("{{#list}}{{/list}}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 0 });
+
+        let err = Template::parse_mustache("x {{a}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::ExpectedClosing { head: 2 });
+
+        for &(input, pos) in &[("{{a}} ${b}", 6), ("${{! comment }}{ {{a}}", 0)] {
+            let err = Template::parse_mustache(input, Opts::default()).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos });
+        }
+    }
+
+    #[test]
+    fn from_printf() {
+        let template = Template::from_printf("%-5s|%05.2f|%lld|%%|%c", Opts::default()).unwrap();
+        assert_eq!(template.to_string(), "${}|${}|${}|%|${}");
+        assert_eq!(
+            template.apply_ordered(vec!["a", "1.00", "2", "c"]).unwrap(),
+            "a|1.00|2|%|c"
+        );
+
+        let template = Template::from_printf("%2$s %1$s", Opts::default()).unwrap();
+        assert_eq!(template.apply_positional(&["a", "b"]).unwrap(), "b a");
+
+        for input in &["%*d", "%y", "trailing %", "%0$s", "%s ${a}"] {
+            let err = Template::from_printf(input, Opts::default()).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::UnsupportedSyntax { .. }));
+        }
+    }
+
+    #[test]
+    fn from_format_string_errors() {
+        let err = Template::from_format_string("{a", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::ExpectedClosing { head: 0 });
+
+        let err = Template::from_format_string("a}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::ExpectedOpening { tail: 1 });
+
+        let err = Template::from_format_string("{a{b}}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::NestedTemplate { pos: 2 });
+
+        let err = Template::from_format_string("{a}{a}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+
+        // `${` can't be escaped, so it can't be literal text
+        let err = Template::from_format_string("{a} ${{b}}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 4 });
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{Error, ErrorKind};
+use miette::{Diagnostic, LabeledSpan};
+
+/// Errors can be rendered with [`miette`](https://docs.rs/miette).
+///
+/// The labels point into the template source, which has to be attached to the report:
+/// ```
+/// # use markings::{Template, Opts};
+/// let input = "hello ${name";
+/// let err = Template::parse(input, Opts::default()).unwrap_err();
+/// let report = miette::Report::new(err).with_source_code(input.to_string());
+/// ```
+impl Diagnostic for Error {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        use ErrorKind::*;
+        let code = match self.kind() {
+            MismatchedBraces { .. } => "markings::mismatched_braces",
+            ExpectedClosing { .. } => "markings::expected_closing",
+            ExpectedOpening { .. } => "markings::expected_opening",
+            NestedTemplate { .. } => "markings::nested_template",
+            DuplicateKeys => "markings::duplicate_keys",
+            EmptyTemplate => "markings::empty_template",
+            OptionalKeys => "markings::optional_keys",
+            OutputTooLarge { .. } => "markings::output_too_large",
+            TemplateTooLong { .. } => "markings::template_too_long",
+            TooManyKeys { .. } => "markings::too_many_keys",
+            KeyTooLong { .. } => "markings::key_too_long",
+            UnsupportedSyntax { .. } => "markings::unsupported_syntax",
+            WrongValueCount { .. } => "markings::wrong_value_count",
+            ArgUseCount { .. } => "markings::arg_use_count",
+            UnclosedBlock { .. } => "markings::unclosed_block",
+            UnmatchedBlockEnd { .. } => "markings::unmatched_block_end",
+            MisplacedElse { .. } => "markings::misplaced_else",
+            UnknownTemplate { .. } => "markings::unknown_template",
+            UnknownFilter { .. } => "markings::unknown_filter",
+            InvalidFilter { .. } => "markings::invalid_filter",
+            Filter { .. } => "markings::filter",
+            NoMatch => "markings::no_match",
+            Io => "markings::io",
+            UnsupportedVersion { .. } => "markings::unsupported_version",
+            UnknownPreset { .. } => "markings::unknown_preset",
+            MissingKeys { .. } => "markings::missing_keys",
+            InvalidEdit { .. } => "markings::invalid_edit",
+            InvalidValue { .. } => "markings::invalid_value",
+            SchemaViolations { .. } => "markings::schema_violations",
+            Arithmetic { .. } => "markings::arithmetic",
+            ForbiddenFilter { .. } => "markings::forbidden_filter",
+            ForbiddenKey { .. } => "markings::forbidden_key",
+            Sandboxed { .. } => "markings::sandboxed",
+            DuplicateArg { .. } => "markings::duplicate_arg",
+            InvalidArg { .. } => "markings::invalid_arg",
+            ExtendsCycle { .. } => "markings::extends_cycle",
+            UnknownSpec { .. } => "markings::unknown_spec",
+        };
+        Some(Box::new(code))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        use ErrorKind::*;
+        let help = match self.kind() {
+            DuplicateKeys => {
+                "enable `Opts::duplicate_keys` to allow a key to be used more than once"
+            }
+            EmptyTemplate => "enable `Opts::empty_template` to allow templates without any keys",
+            OptionalKeys => {
+                "enable `Opts::optional_keys` to allow args that aren't in the template"
+            }
+            OutputTooLarge { .. } => "increase `Opts::max_output_len`, or use shorter values",
+            NestedTemplate { .. } => "templates cannot contain braces inside of a `${key}`",
+            _ => return None,
+        };
+        Some(Box::new(help))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        use ErrorKind::*;
+        let label = match self.kind() {
+            ExpectedClosing { .. } => "this is never closed",
+            ExpectedOpening { .. } => "this was never opened",
+            NestedTemplate { .. } => "this brace is nested",
+            DuplicateKeys => "this key was already used",
+            KeyTooLong { .. } => "this key is too long",
+            UnsupportedSyntax { .. } => "this isn't supported",
+            UnclosedBlock { .. } => "this block is never closed",
+            UnmatchedBlockEnd { .. } => "this doesn't close an open block",
+            MisplacedElse { .. } => "this isn't inside of a block",
+            UnknownFilter { .. } => "this uses an unknown filter",
+            InvalidFilter { .. } => "this filter has invalid arguments",
+            ForbiddenFilter { .. } => "this filter isn't allowed",
+            ForbiddenKey { .. } => "this key isn't allowed",
+            UnknownSpec { .. } => "this annotation can't be checked",
+            _ => return None,
+        };
+        let span = self.span()?;
+        Some(Box::new(std::iter::once(LabeledSpan::at(span, label))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Opts, Template};
+
+    #[test]
+    fn labels() {
+        let err = Template::parse("hello ${na{me}", Opts::default()).unwrap_err();
+        let labels = err.labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].offset(), 10);
+        assert_eq!(labels[0].label(), Some("this brace is nested"));
+
+        let err = Template::parse("hello", Opts::default()).unwrap_err();
+        assert!(err.labels().is_none());
+        assert!(err.help().is_some());
+        assert_eq!(
+            Diagnostic::code(&err).unwrap().to_string(),
+            "markings::empty_template"
+        );
+    }
+}
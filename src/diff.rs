@@ -0,0 +1,170 @@
+use crate::{Args, Result, Secret, Template};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// The keys whose output changed between two renders of a template, from
+/// [`Template::render_diff`](./struct.Template.html#method.render_diff)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderDiff {
+    changes: Vec<KeyChange>,
+}
+
+impl RenderDiff {
+    /// The changed keys, in the order of the template
+    pub fn changes(&self) -> &[KeyChange] {
+        &self.changes
+    }
+
+    /// Whether no key changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// A key whose output changed, in a [`RenderDiff`](./struct.RenderDiff.html)
+#[derive(Clone, PartialEq)]
+pub struct KeyChange {
+    key: String,
+    source: Range<usize>,
+    before: Option<String>,
+    after: Option<String>,
+    secret: bool,
+}
+
+impl KeyChange {
+    /// The name of the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The byte range of the key's marker in the template source
+    pub fn source(&self) -> Range<usize> {
+        self.source.clone()
+    }
+
+    /// What was written for the key by the old args, after its filters
+    ///
+    /// This is `None` when the key wasn't rendered, like when it had no value or was in a block that wasn't taken
+    pub fn before(&self) -> Option<&str> {
+        self.before.as_deref()
+    }
+
+    /// What was written for the key by the new args, after its filters
+    pub fn after(&self) -> Option<&str> {
+        self.after.as_deref()
+    }
+}
+
+/// Values from [`Args::with_secret`](./struct.Args.html#method.with_secret) are redacted
+impl std::fmt::Debug for KeyChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("KeyChange");
+        debug.field("key", &self.key).field("source", &self.source);
+        match self.secret {
+            true => debug
+                .field("before", &self.before.as_ref().map(Secret))
+                .field("after", &self.after.as_ref().map(Secret)),
+            false => debug
+                .field("before", &self.before)
+                .field("after", &self.after),
+        };
+        debug.finish()
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Render the template with two sets of args, returning the keys whose output changed
+    ///
+    /// Each key of the template is compared by what was written for it, so a value that changed but was
+    /// formatted the same by its filters isn't a change. This is useful for audit logs of generated files
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("host = ${host}\nport = ${port}", Opts::default()).unwrap();
+    /// let old = Args::new().with("host", "localhost").with("port", 80);
+    /// let new = Args::new().with("host", "localhost").with("port", 8080);
+    ///
+    /// let diff = template.render_diff(&old, &new).unwrap();
+    /// let change = &diff.changes()[0];
+    /// assert_eq!(diff.changes().len(), 1);
+    /// assert_eq!(change.key(), "port");
+    /// assert_eq!(change.before(), Some("80"));
+    /// assert_eq!(change.after(), Some("8080"));
+    /// ```
+    pub fn render_diff(&self, old_args: &Args<'_>, new_args: &Args<'_>) -> Result<RenderDiff> {
+        let is_secret = |args: &Args<'_>, key: &str| {
+            self.lookup_arg(args, key)
+                .is_some_and(|(arg, _)| args.is_secret(arg))
+        };
+
+        // the keys that were rendered, by where they are in the template
+        let mut keys = BTreeMap::<usize, (&str, Range<usize>, [Option<String>; 2])>::new();
+        for (side, args) in [old_args, new_args].iter().enumerate() {
+            let (pieces, _) = self.pieces(args)?;
+            for (piece, key, source) in pieces {
+                if let Some(key) = key {
+                    let (_, _, values) = keys
+                        .entry(source.start)
+                        .or_insert_with(|| (key, source, Default::default()));
+                    values[side] = Some(piece.into_owned());
+                }
+            }
+        }
+
+        let changes = keys
+            .into_values()
+            .filter(|(_, _, [before, after])| before != after)
+            .map(|(key, source, [before, after])| KeyChange {
+                key: key.to_string(),
+                source,
+                before,
+                after,
+                secret: is_secret(old_args, key) || is_secret(new_args, key),
+            })
+            .collect();
+        Ok(RenderDiff { changes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn diff() {
+        let opts = Opts::default().optional_keys();
+        let template = Template::parse("${a|title} ${?b}${b}${/} ${c}", opts).unwrap();
+        let old = Args::new().with("a", "x").with("b", "y").with("c", 1);
+        let new = Args::new().with("a", "X").with_secret("c", 2);
+
+        assert!(template.render_diff(&old, &old).unwrap().is_empty());
+
+        let diff = template.render_diff(&old, &new).unwrap();
+        let changes = diff
+            .changes()
+            .iter()
+            .map(|change| {
+                (
+                    change.key(),
+                    change.source(),
+                    change.before(),
+                    change.after(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                ("b", 16..20, Some("y"), None),
+                ("c", 25..29, Some("1"), Some("2")),
+            ]
+        );
+        assert_eq!(
+            format!("{:?}", diff.changes()[1]),
+            r#"KeyChange { key: "c", source: 25..29, before: Some(<redacted>), after: Some(<redacted>) }"#
+        );
+
+        let template = Template::parse("${a}", Opts::default()).unwrap();
+        assert!(template.render_diff(&old, &Args::new()).is_err());
+    }
+}
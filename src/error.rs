@@ -0,0 +1,228 @@
+//! Errors for a single step of using a template: [`ParseError`] and [`ApplyError`]
+use crate::{Args, Error, ErrorKind, Opts, Template, Violation};
+use std::convert::TryFrom;
+use std::ops::Range;
+
+// defines an error for a step, with a kind that has a subset of the variants of `ErrorKind`
+macro_rules! step_error {
+    (
+        $(#[$meta:meta])*
+        $error:ident,
+        $(#[$kind_meta:meta])*
+        $kind:ident {
+            $($variant:ident $({ $($field:ident: $ty:ty),* $(,)? })?),* $(,)?
+        }
+    ) => {
+        $(#[$kind_meta])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum $kind {
+            $($variant $({ $($field: $ty),* })?,)*
+            /// Any other kind of error. The step isn't expected to fail with these, but it won't panic if it does
+            Other { kind: ErrorKind },
+        }
+
+        impl From<$kind> for ErrorKind {
+            fn from(kind: $kind) -> Self {
+                match kind {
+                    $($kind::$variant $({ $($field),* })? => ErrorKind::$variant $({ $($field),* })?,)*
+                    $kind::Other { kind } => kind,
+                }
+            }
+        }
+
+        impl std::fmt::Display for $kind {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                ErrorKind::from(self.clone()).fmt(f)
+            }
+        }
+
+        $(#[$meta])*
+        #[derive(Debug)]
+        pub struct $error {
+            kind: $kind,
+            span: Option<Range<usize>>,
+            source: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
+        }
+
+        impl $error {
+            /// The kind of error this is
+            pub fn kind(&self) -> &$kind {
+                &self.kind
+            }
+
+            /// The byte range in the template source this error refers to, if any
+            pub fn span(&self) -> Option<Range<usize>> {
+                self.span.clone()
+            }
+
+            // an error of a kind that isn't one of this step
+            fn other(err: Error) -> Self {
+                let Error { kind, span, source } = err;
+                let kind = $kind::Other { kind };
+                Self { kind, span, source }
+            }
+        }
+
+        impl std::fmt::Display for $error {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.kind.fmt(f)
+            }
+        }
+
+        impl std::error::Error for $error {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                self.source.as_ref().map(|err| &**err as _)
+            }
+        }
+
+        impl From<$error> for Error {
+            fn from(err: $error) -> Self {
+                Self {
+                    kind: err.kind.into(),
+                    span: err.span,
+                    source: err.source,
+                }
+            }
+        }
+
+        /// Fails with the error, if its kind isn't one of this step
+        impl TryFrom<Error> for $error {
+            type Error = Error;
+
+            fn try_from(err: Error) -> Result<Self, Error> {
+                let Error { kind, span, source } = err;
+                let kind = match kind {
+                    $(ErrorKind::$variant $({ $($field),* })? => $kind::$variant $({ $($field),* })?,)*
+                    kind => return Err(Error { kind, span, source }),
+                };
+                Ok(Self { kind, span, source })
+            }
+        }
+    };
+}
+
+step_error! {
+    /// An error from parsing a template, see [`Template::try_parse`](./struct.Template.html#method.try_parse)
+    ///
+    /// It converts into an [`Error`](./struct.Error.html), so it can be used with `?` where an `Error` is expected
+    ParseError,
+    /// The kind of a [`ParseError`](./struct.ParseError.html)
+    ///
+    /// These are the variants of [`ErrorKind`](./enum.ErrorKind.html) that parsing can fail with, and are documented there
+    ParseErrorKind {
+        MismatchedBraces { open: usize, close: usize },
+        ExpectedClosing { head: usize },
+        ExpectedOpening { tail: usize },
+        NestedTemplate { pos: usize },
+        DuplicateKeys,
+        EmptyTemplate,
+        TemplateTooLong { limit: usize },
+        TooManyKeys { limit: usize },
+        KeyTooLong { limit: usize },
+        UnsupportedSyntax { pos: usize },
+        UnclosedBlock { pos: usize },
+        UnmatchedBlockEnd { pos: usize },
+        MisplacedElse { pos: usize },
+        UnknownFilter { name: String },
+        InvalidFilter { filter: String },
+        ForbiddenFilter { name: String },
+        ForbiddenKey { key: String, pos: usize },
+        UnknownSpec { spec: String },
+    }
+}
+
+step_error! {
+    /// An error from applying args to a template, see [`Template::try_apply`](./struct.Template.html#method.try_apply)
+    ///
+    /// It converts into an [`Error`](./struct.Error.html), so it can be used with `?` where an `Error` is expected
+    ApplyError,
+    /// The kind of an [`ApplyError`](./struct.ApplyError.html)
+    ///
+    /// These are the variants of [`ErrorKind`](./enum.ErrorKind.html) that applying can fail with, and are documented there
+    ApplyErrorKind {
+        OptionalKeys,
+        OutputTooLarge { limit: usize },
+        WrongValueCount { expected: usize, found: usize },
+        ArgUseCount { key: String, expected: usize, found: usize },
+        Filter { key: String, filter: String },
+        Io,
+        MissingKeys { keys: Vec<String> },
+        InvalidValue { key: String, spec: String },
+        SchemaViolations { violations: Vec<Violation> },
+        Arithmetic { expr: String },
+        Sandboxed { feature: String },
+        DuplicateArg { key: String },
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Parse a template, like [`Template::parse`](#method.parse), failing with a [`ParseError`](./struct.ParseError.html)
+    ///
+    /// The error only has the kinds of errors that parsing can fail with, anything else is `ParseErrorKind::Other`
+    /// ```
+    /// # use markings::{Template, Opts, ParseErrorKind};
+    /// let err = Template::try_parse("${a} ${a}", Opts::default()).unwrap_err();
+    /// assert_eq!(err.kind(), &ParseErrorKind::DuplicateKeys);
+    /// ```
+    pub fn try_parse(input: &'a str, opts: Opts) -> Result<Self, ParseError> {
+        Self::parse(input, opts)
+            .map_err(|err| ParseError::try_from(err).unwrap_or_else(ParseError::other))
+    }
+
+    /// Apply the arguments to the template, like [`Template::apply`](#method.apply), failing with an [`ApplyError`](./struct.ApplyError.html)
+    ///
+    /// The error only has the kinds of errors that applying can fail with, anything else is `ApplyErrorKind::Other`
+    /// ```
+    /// # use markings::{Template, Args, Opts, ApplyErrorKind};
+    /// let template = Template::parse("${a} ${b}", Opts::default()).unwrap();
+    /// let err = template.try_apply(&Args::new().with("a", 1)).unwrap_err();
+    /// match err.kind() {
+    ///     ApplyErrorKind::MissingKeys { keys } => assert_eq!(keys, &["b"]),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn try_apply(&self, args: &Args<'_>) -> Result<String, ApplyError> {
+        self.apply(args)
+            .map_err(|err| ApplyError::try_from(err).unwrap_or_else(ApplyError::other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let err = Template::try_parse("${a{}}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ParseErrorKind::NestedTemplate { pos: 3 });
+        assert_eq!(err.span(), Some(3..4));
+
+        let display = err.to_string();
+        let err = Error::from(err);
+        assert_eq!(err.kind(), &ErrorKind::NestedTemplate { pos: 3 });
+        assert_eq!(err.span(), Some(3..4));
+        assert_eq!(err.to_string(), display);
+
+        // a parse error isn't an apply error
+        let err = ApplyError::try_from(err).unwrap_err();
+        assert!(ParseError::try_from(err).is_ok());
+
+        // unless it is wrapped as some other kind of error
+        let err = ApplyError::other(Error::from(ErrorKind::NoMatch).with_span(0..1));
+        let kind = ErrorKind::NoMatch;
+        assert_eq!(err.kind(), &ApplyErrorKind::Other { kind: kind.clone() });
+        assert_eq!(err.to_string(), kind.to_string());
+        let err = Error::from(err);
+        assert_eq!((err.kind(), err.span()), (&kind, Some(0..1)));
+
+        let opts = Opts::default().expressions();
+        let template = Template::parse("${a / 0}", opts).unwrap();
+        let err = template.try_apply(&Args::new().with("a", 1)).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ApplyErrorKind::Arithmetic {
+                expr: "a / 0".into()
+            }
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Arithmetic in markers, like `${count + 1}`, with [`Opts::expressions`](../struct.Opts.html#method.expressions)
+use crate::{ErrorKind, Result};
+
+// the characters that make a marker an expression, rather than a key
+const OPERATORS: &[char] = &['+', '-', '*', '/', '%', '(', ')'];
+
+// a parsed expression, and the text it was parsed from
+//
+// expressions compare by their text without whitespace, so `${a+1}` is equal to `${a + 1}`
+#[derive(Clone, Debug)]
+pub(crate) struct Expr {
+    text: String,
+    node: Node,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Int(i64),
+    Float(f64),
+    Key(String),
+    Neg(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Num {
+    Int(i64),
+    Float(f64),
+}
+
+impl Expr {
+    // whether the text of a marker is meant as an expression. keys with filters, annotations or fallbacks never are
+    pub(crate) fn is_expr(text: &str) -> bool {
+        text.contains(OPERATORS) && !text.contains(['|', '!', '"']) && !text.contains("??")
+    }
+
+    // `None` if the text isn't an expression, or it nests deeper than `max_depth`
+    pub(crate) fn parse(text: &str, max_depth: usize) -> Option<Self> {
+        let text = text.trim();
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            depth: 0,
+            max_depth,
+        };
+        let (node, _) = parser.expr()?;
+        parser.skip_whitespace();
+        if parser.pos != text.len() {
+            return None;
+        }
+        Some(Self {
+            text: text.to_string(),
+            node,
+        })
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    // the keys used by the expression, in order
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        let mut keys = vec![];
+        self.node.keys(&mut keys);
+        keys.into_iter()
+    }
+
+    // evaluates the expression, with the values of its keys from `lookup`
+    pub(crate) fn eval<'v>(&self, lookup: impl Fn(&str) -> &'v str) -> Result<String> {
+        let value = self
+            .node
+            .eval(&lookup)
+            .ok_or_else(|| ErrorKind::Arithmetic {
+                expr: self.text.clone(),
+            })??;
+        Ok(match value {
+            Num::Int(value) => value.to_string(),
+            Num::Float(value) => value.to_string(),
+        })
+    }
+
+    fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.text.chars().filter(|c| !c.is_whitespace())
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.chars().eq(other.chars())
+    }
+}
+
+impl Eq for Expr {}
+
+impl std::hash::Hash for Expr {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.chars().for_each(|c| c.hash(state))
+    }
+}
+
+impl Node {
+    fn keys<'a>(&'a self, keys: &mut Vec<&'a str>) {
+        match self {
+            Node::Int(..) | Node::Float(..) => {}
+            Node::Key(key) => keys.push(key),
+            Node::Neg(node) => node.keys(keys),
+            Node::Binary(_, left, right) => {
+                left.keys(keys);
+                right.keys(keys);
+            }
+        }
+    }
+
+    // `None` if the arithmetic failed, like dividing by zero or overflowing
+    fn eval<'v>(&self, lookup: &impl Fn(&str) -> &'v str) -> Option<Result<Num>> {
+        let num = match self {
+            Node::Int(value) => Num::Int(*value),
+            Node::Float(value) => Num::Float(*value),
+            Node::Key(key) => return Some(Num::parse(key, lookup(key))),
+            Node::Neg(node) => match node.eval(lookup)? {
+                Ok(Num::Int(value)) => Num::Int(value.checked_neg()?),
+                Ok(Num::Float(value)) => Num::Float(-value),
+                Err(err) => return Some(Err(err)),
+            },
+            Node::Binary(op, left, right) => {
+                let left = match left.eval(lookup)? {
+                    Ok(left) => left,
+                    err => return Some(err),
+                };
+                let right = match right.eval(lookup)? {
+                    Ok(right) => right,
+                    err => return Some(err),
+                };
+                op.apply(left, right)?
+            }
+        };
+        Some(Ok(num))
+    }
+}
+
+impl Op {
+    // whole numbers stay whole, unless they don't divide evenly
+    fn apply(self, left: Num, right: Num) -> Option<Num> {
+        if let (Num::Int(left), Num::Int(right)) = (left, right) {
+            let value = match self {
+                Op::Add => left.checked_add(right)?,
+                Op::Sub => left.checked_sub(right)?,
+                Op::Mul => left.checked_mul(right)?,
+                Op::Rem => left.checked_rem(right)?,
+                Op::Div if left.checked_rem(right)? == 0 => left.checked_div(right)?,
+                Op::Div => return Some(Num::Float(left as f64 / right as f64)),
+            };
+            return Some(Num::Int(value));
+        }
+
+        let (left, right) = (left.to_f64(), right.to_f64());
+        let value = match self {
+            Op::Add => left + right,
+            Op::Sub => left - right,
+            Op::Mul => left * right,
+            Op::Div if right == 0.0 => return None,
+            Op::Div => left / right,
+            Op::Rem if right == 0.0 => return None,
+            Op::Rem => left % right,
+        };
+        Some(Num::Float(value)).filter(|_| value.is_finite())
+    }
+}
+
+impl Num {
+    fn parse(key: &str, value: &str) -> Result<Self> {
+        if let Ok(value) = value.parse() {
+            return Ok(Num::Int(value));
+        }
+        match value.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(Num::Float(value)),
+            _ => Err(ErrorKind::InvalidValue {
+                key: key.to_string(),
+                spec: "number".to_string(),
+            }
+            .into()),
+        }
+    }
+
+    fn to_f64(self) -> f64 {
+        match self {
+            Num::Int(value) => value as f64,
+            Num::Float(value) => value,
+        }
+    }
+}
+
+// expr   = term (('+' | '-') term)*
+// term   = unary (('*' | '/' | '%') unary)*
+// unary  = '-' unary | atom
+// atom   = number | key | '(' expr ')'
+//
+// nodes are parsed with their height, so no expression is deeper than `max_depth`, and `depth` counts the
+// negations and parentheses the parser is in, so it doesn't recurse deeper than that either
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn expr(&mut self) -> Option<(Node, usize)> {
+        let mut node = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Op::Add,
+                Some('-') => Op::Sub,
+                _ => return Some(node),
+            };
+            self.pos += 1;
+            let right = self.term()?;
+            node = self.binary(op, node, right)?;
+        }
+    }
+
+    fn term(&mut self) -> Option<(Node, usize)> {
+        let mut node = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => Op::Mul,
+                Some('/') => Op::Div,
+                Some('%') => Op::Rem,
+                _ => return Some(node),
+            };
+            self.pos += 1;
+            let right = self.unary()?;
+            node = self.binary(op, node, right)?;
+        }
+    }
+
+    fn unary(&mut self) -> Option<(Node, usize)> {
+        match self.peek()? {
+            '-' => {
+                self.pos += 1;
+                let (node, height) = self.nested(Self::unary)?;
+                self.node(Node::Neg(Box::new(node)), height + 1)
+            }
+            '(' => {
+                self.pos += 1;
+                let node = self.nested(Self::expr)?;
+                if self.peek()? != ')' {
+                    return None;
+                }
+                self.pos += 1;
+                Some(node)
+            }
+            c if c.is_ascii_digit() => {
+                let number = self.take(|c| c.is_ascii_digit() || c == '.');
+                let node = match number.parse() {
+                    Ok(value) => Node::Int(value),
+                    Err(..) => Node::Float(number.parse().ok()?),
+                };
+                Some((node, 0))
+            }
+            c if c == '_' || c.is_alphabetic() => {
+                let key = self.take(|c| c == '_' || c == '.' || c.is_alphanumeric());
+                Some((Node::Key(key.to_string()), 0))
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &self,
+        op: Op,
+        (left, l): (Node, usize),
+        (right, r): (Node, usize),
+    ) -> Option<(Node, usize)> {
+        self.node(
+            Node::Binary(op, Box::new(left), Box::new(right)),
+            l.max(r) + 1,
+        )
+    }
+
+    fn node(&self, node: Node, height: usize) -> Option<(Node, usize)> {
+        Some((node, height)).filter(|_| height <= self.max_depth)
+    }
+
+    fn nested(
+        &mut self,
+        parse: impl Fn(&mut Self) -> Option<(Node, usize)>,
+    ) -> Option<(Node, usize)> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return None;
+        }
+        let node = parse(self)?;
+        self.depth -= 1;
+        Some(node)
+    }
+
+    // the next character that isn't whitespace
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn take(&mut self, accept: impl Fn(char) -> bool) -> &'a str {
+        let rest = &self.text[self.pos..];
+        let len = rest.find(|c| !accept(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, values: &[(&str, &'static str)]) -> Result<String> {
+        let expr = Expr::parse(text, 64).unwrap();
+        expr.eval(|key| {
+            values
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| *value)
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn arithmetic() {
+        let values = [("a", "7"), ("b", "2"), ("price", "2.5"), ("x.y", "-3")];
+        let cases = [
+            ("a + 1", "8"),
+            ("a - b * 3", "1"),
+            ("(a - b) * 3", "15"),
+            ("a / b", "3.5"),
+            ("8 / b", "4"),
+            ("a % b", "1"),
+            ("-a", "-7"),
+            ("--a", "7"),
+            ("price * b", "5"),
+            ("price * 3", "7.5"),
+            ("x.y*x.y", "9"),
+            ("1.5 + 1", "2.5"),
+        ];
+        for (text, expected) in &cases {
+            assert_eq!(eval(text, &values).unwrap(), *expected, "{}", text);
+        }
+
+        let expr = Expr::parse("(a + b) * a", 64).unwrap();
+        assert_eq!(expr.keys().collect::<Vec<_>>(), vec!["a", "b", "a"]);
+        assert_eq!(expr, Expr::parse("(a+b)*a", 64).unwrap());
+    }
+
+    #[test]
+    fn invalid() {
+        for text in &["", "a +", "(a", "a)", "a b", "1.2.3", "a * / b", "$a"] {
+            assert!(Expr::parse(text, 64).is_none(), "{}", text);
+        }
+
+        // too deep, whether by nesting or by a long chain of operators
+        let nested = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+        let chain = vec!["a"; 100_000].join(" + ");
+        for text in &[nested, chain, "-".repeat(100_000) + "a"] {
+            assert!(Expr::parse(text, 64).is_none());
+        }
+        assert!(Expr::parse("-(-a)", 3).is_some());
+        assert!(Expr::parse("-(-(-a))", 3).is_none());
+        assert!(Expr::parse("a + a + a", 2).is_some());
+        assert!(Expr::parse("a + a + a + a", 2).is_none());
+
+        let values = [("a", "1"), ("zero", "0"), ("word", "one")];
+        assert!(matches!(
+            eval("a / zero", &values).unwrap_err().kind(),
+            ErrorKind::Arithmetic { expr } if expr == "a / zero"
+        ));
+        assert!(matches!(
+            eval("9223372036854775807 + a", &values).unwrap_err().kind(),
+            ErrorKind::Arithmetic { .. }
+        ));
+        assert!(matches!(
+            eval("word + a", &values).unwrap_err().kind(),
+            ErrorKind::InvalidValue { key, .. } if key == "word"
+        ));
+    }
+}
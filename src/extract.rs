@@ -0,0 +1,329 @@
+//! Matching rendered strings back against a template
+use crate::{Args, BlockKind, ErrorKind, Opts, Result, Segment, Template};
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+// a template without blocks, as its literal text and keys
+enum Part<'t> {
+    Literal(Cow<'t, str>),
+    Key(&'t str),
+}
+
+impl<'a> Template<'a> {
+    /// Recover the values of the keys from a string rendered by this template
+    ///
+    /// Values are matched like `scanf`: each key matches the shortest text that lets the rest of the
+    /// template match. A key that is used more than once has to match the same text each time.
+    ///
+    /// Filters can't be undone, so the values are the filtered text. Blocks depend on the values,
+    /// so templates with blocks fail with `UnsupportedSyntax`. This fails with `NoMatch` if the
+    /// string couldn't have been rendered by this template.
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("[${level}] ${user}: ${message}", Opts::default()).unwrap();
+    /// let args = template.extract("[warn] bob: disk is 90% full").unwrap();
+    /// assert_eq!(
+    ///     args,
+    ///     Args::new()
+    ///         .with("level", "warn")
+    ///         .with("user", "bob")
+    ///         .with("message", "disk is 90% full")
+    /// );
+    /// ```
+    pub fn extract(&self, rendered: &str) -> Result<Args<'static>> {
+        let parts = self.parts()?;
+        let mut matcher = Matcher {
+            parts: &parts,
+            input: rendered,
+            opts: &self.opts,
+            values: vec![],
+            failed: HashSet::new(),
+        };
+        if !matcher.matches(0, 0) {
+            return Err(ErrorKind::NoMatch.into());
+        }
+        Ok(matcher
+            .values
+            .into_iter()
+            .map(|(key, val)| (key.to_string(), val))
+            .collect())
+    }
+
+    /// Check if a string could have been rendered by this template
+    ///
+    /// Keys act like `*` in a glob, so this is faster than [`Template::extract`](#method.extract),
+    /// but doesn't check that a key used more than once has the same value each time.
+    /// Templates with blocks never match, see [`Template::to_regex`](#method.to_regex) for those.
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("!ban ${user} for ${reason}", Opts::default()).unwrap();
+    /// assert!(template.matches("!ban bob for spamming"));
+    /// assert!(!template.matches("!kick bob for spamming"));
+    /// ```
+    pub fn matches(&self, candidate: &str) -> bool {
+        let parts = match self.parts() {
+            Ok(parts) => parts,
+            Err(..) => return false,
+        };
+
+        // the literal text between each key
+        let mut literals = vec![String::new()];
+        for part in parts {
+            match part {
+                Part::Literal(literal) => literals.last_mut().unwrap().push_str(&literal),
+                Part::Key(..) => literals.push(String::new()),
+            }
+        }
+
+        let (first, rest) = literals.split_first().unwrap();
+        let mut candidate = match candidate.strip_prefix(&**first) {
+            Some(candidate) => candidate,
+            None => return false,
+        };
+        let (last, middle) = match rest.split_last() {
+            Some(split) => split,
+            None => return candidate.is_empty(),
+        };
+
+        // the leftmost match of each literal leaves the most room for the rest
+        for literal in middle {
+            match candidate.find(&**literal) {
+                Some(pos) => candidate = &candidate[pos + literal.len()..],
+                None => return false,
+            }
+        }
+        candidate.ends_with(&**last)
+    }
+
+    /// Convert the template into a regular expression that matches its output
+    ///
+    /// Literal text is escaped and each key becomes a named capture group, which matches any text. The dots
+    /// of nested keys are underscores in the group names, so `${user.name}` is captured as `user_name`.
+    /// Keys that aren't valid group names, like anonymous keys, and keys that were already captured,
+    /// are unnamed groups, like expressions. The branches of a block become alternatives that can also match nothing.
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("${user.name} has ${} (${?admin}admin${/})", Opts::default()).unwrap();
+    /// assert_eq!(template.to_regex(), r"(?s)^(?P<user_name>.*?) has (.*?) \((?:admin|)\)$");
+    /// ```
+    pub fn to_regex(&self) -> String {
+        let mut out = String::from("(?s)^");
+        let mut captured: Vec<String> = vec![];
+        let mut open = vec![];
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => escape_regex(&self.opts.unescape(literal), &mut out),
+                Segment::Key { name, .. } => {
+                    let group = name.replace('.', "_");
+                    if is_group_name(&group)
+                        && !captured.iter().any(|seen| self.opts.key_eq(seen, &group))
+                    {
+                        out.push_str("(?P<");
+                        out.push_str(&group);
+                        out.push_str(">.*?)");
+                        captured.push(group);
+                    } else {
+                        out.push_str("(.*?)");
+                    }
+                }
+                Segment::Expr { .. } => out.push_str("(.*?)"),
+                Segment::Block { kind, .. } => match kind {
+                    BlockKind::If | BlockKind::With | BlockKind::Region => {
+                        open.push(*kind);
+                        out.push_str("(?:");
+                    }
+                    BlockKind::ElseIf | BlockKind::Else => out.push('|'),
+                    // a conditional block renders nothing if none of its branches were taken
+                    BlockKind::End if open.pop() == Some(BlockKind::If) => out.push_str("|)"),
+                    BlockKind::End => out.push(')'),
+                },
+            }
+        }
+        out.push('$');
+        out
+    }
+
+    /// Compile the template into a [`regex::Regex`](https://docs.rs/regex) that matches its output
+    ///
+    /// See [`Template::to_regex`](#method.to_regex)
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("[${level}] ${message}", Opts::default()).unwrap();
+    /// let regex = template.compile_regex().unwrap();
+    /// let captures = regex.captures("[warn] disk is full").unwrap();
+    /// assert_eq!(&captures["level"], "warn");
+    /// assert_eq!(&captures["message"], "disk is full");
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn compile_regex(&self) -> std::result::Result<regex::Regex, regex::Error> {
+        regex::Regex::new(&self.to_regex())
+    }
+
+    fn parts(&self) -> Result<Vec<Part<'_>>> {
+        self.spans()
+            .map(|(segment, span)| match segment {
+                Segment::Literal(literal) => Ok(Part::Literal(self.opts.unescape(literal))),
+                Segment::Key { name, .. } => Ok(Part::Key(name)),
+                Segment::Block { .. } | Segment::Expr { .. } => {
+                    Err(ErrorKind::UnsupportedSyntax { pos: span.start }.into())
+                }
+            })
+            .collect()
+    }
+}
+
+// whether the key can be used as the name of a capture group
+fn is_group_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch == '_' || ch.is_ascii_alphabetic())
+        && chars.all(|ch| ch == '_' || ch.is_ascii_alphanumeric())
+}
+
+fn escape_regex(literal: &str, out: &mut String) {
+    for ch in literal.chars() {
+        if r"\.+*?()|[]{}^$#&-~".contains(ch) {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+}
+
+// matches the input against the parts, binding the keys to the text they matched
+struct Matcher<'p, 't, 'i> {
+    parts: &'p [Part<'t>],
+    input: &'i str,
+    opts: &'p Opts,
+    values: Vec<(&'t str, &'i str)>,
+    // the states that are known not to match, so each is only tried once: the part, the position in the input,
+    // and the values of the keys that are used again after that part
+    failed: HashSet<(usize, usize, Vec<&'i str>)>,
+}
+
+impl<'t, 'i> Matcher<'_, 't, 'i> {
+    fn matches(&mut self, part: usize, pos: usize) -> bool {
+        let input = &self.input[pos..];
+        let key = match self.parts.get(part) {
+            None => return input.is_empty(),
+            Some(Part::Literal(literal)) => {
+                return input.starts_with(&**literal) && self.matches(part + 1, pos + literal.len())
+            }
+            Some(Part::Key(key)) => *key,
+        };
+
+        // a key that was already matched has to match the same text again
+        let opts = self.opts;
+        if let Some(&(_, val)) = self.values.iter().find(|(seen, _)| opts.key_eq(seen, key)) {
+            return input.starts_with(val) && self.matches(part + 1, pos + val.len());
+        }
+
+        let state = (part, pos, self.bound_after(part));
+        if self.failed.contains(&state) {
+            return false;
+        }
+
+        // only the places where the next literal starts can end the key
+        let next = match self.parts.get(part + 1) {
+            Some(Part::Literal(literal)) => literal,
+            _ => "",
+        };
+        let ends = (0..=input.len())
+            .filter(|&end| input.is_char_boundary(end) && input[end..].starts_with(next));
+        for end in ends {
+            self.values.push((key, &input[..end]));
+            if self.matches(part + 1, pos + end) {
+                return true;
+            }
+            self.values.pop();
+        }
+        self.failed.insert(state);
+        false
+    }
+
+    // the values of the keys that were matched, and are used again from `part` on
+    fn bound_after(&self, part: usize) -> Vec<&'i str> {
+        let used = |key: &str| {
+            self.parts[part..]
+                .iter()
+                .any(|part| matches!(part, Part::Key(seen) if self.opts.key_eq(seen, key)))
+        };
+        self.values
+            .iter()
+            .filter(|(key, _)| used(key))
+            .map(|&(_, val)| val)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract() {
+        let opts = Opts::default().duplicate_keys();
+        let template = Template::parse("${a}-${b}-${a}.${c}", opts.clone()).unwrap();
+        let args = template.extract("x-y-z-x-y.").unwrap();
+        let expected = Args::new().with("a", "x-y").with("b", "z").with("c", "");
+        assert_eq!(args, expected);
+        assert_eq!(template.apply(&args).unwrap(), "x-y-z-x-y.");
+
+        let template = Template::parse("${}${} (${})", Opts::default()).unwrap();
+        let args = template.extract("ab (c)").unwrap();
+        assert_eq!(
+            args,
+            Args::new().with("0", "").with("1", "ab").with("2", "c")
+        );
+
+        let err = template.extract("ab c").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::NoMatch);
+
+        let template = Template::parse("${?a}${b}${/}", Opts::default()).unwrap();
+        let err = template.extract("b").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 0 });
+
+        // every way of splitting the input between the keys is only tried once
+        let input = (0..24).map(|i| format!("${{k{}}}", i)).collect::<String>() + "!";
+        let template = Template::parse(&input, Opts::default()).unwrap();
+        let err = template.extract(&"a".repeat(64)).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::NoMatch);
+
+        let template = Template::parse("${a}${b}${a}${b}!", opts).unwrap();
+        let err = template.extract(&"a".repeat(64)).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::NoMatch);
+        let args = template.extract("xyzxyz!").unwrap();
+        assert_eq!(args, Args::new().with("a", "").with("b", "xyz"));
+    }
+
+    #[test]
+    fn matches() {
+        let opts = Opts::default().duplicate_keys();
+        let template = Template::parse("${a}:${b}${c}:${a}", opts.clone()).unwrap();
+        assert!(template.matches("::"));
+        assert!(template.matches("x:y:z:x"));
+        assert!(template.matches("x:y:z"));
+        assert!(!template.matches("x"));
+
+        let template = Template::parse("ab${a}ba", opts.clone()).unwrap();
+        assert!(template.matches("aba-ba"));
+        assert!(!template.matches("aba"));
+
+        let template = Template::parse("${?a}b${/}", opts).unwrap();
+        assert!(!template.matches("b"));
+    }
+
+    #[test]
+    fn to_regex() {
+        let opts = Opts::default().duplicate_keys();
+        let input = "${a} ${a} ${b c}${@x}${?d}1${:?e}2${:else}3${/}${/}.";
+        let template = Template::parse(input, opts.clone()).unwrap();
+        assert_eq!(
+            template.to_regex(),
+            r"(?s)^(?P<a>.*?) (.*?) (.*?)(?:(?:1|2|3|))\.$"
+        );
+
+        let template = Template::parse("${a.b} ${a_b} ${a.b}", opts).unwrap();
+        assert_eq!(template.to_regex(), r"(?s)^(?P<a_b>.*?) (.*?) (.*?)$");
+    }
+}
@@ -0,0 +1,231 @@
+//! A C interface, with the `ffi` feature
+//!
+//! Templates are parsed with [`markings_parse`], applied with [`markings_apply`] and freed with
+//! [`markings_free`]. Strings returned by the library are freed with [`markings_string_free`].
+//! The declarations are in `include/markings.h`.
+//!
+//! Every function returns [`MARKINGS_OK`], [`MARKINGS_INVALID_ARGUMENT`] if a pointer is null or a
+//! string isn't valid UTF-8, or the code of the error, see [`error_code`]. These codes never change.
+//!
+//! Build a library to link against with `cargo rustc --release --features ffi --crate-type staticlib`
+//! (or `cdylib`)
+use crate::{Args, ErrorKind, Opts, Template};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+
+/// A parsed template
+pub struct MarkingsTemplate(Template<'static>);
+
+/// The function succeeded
+pub const MARKINGS_OK: c_int = 0;
+/// A pointer was null, or a string wasn't valid UTF-8
+pub const MARKINGS_INVALID_ARGUMENT: c_int = -1;
+
+/// Allow optional keys, see [`Opts::optional_keys`](../struct.Opts.html#method.optional_keys)
+pub const MARKINGS_OPTIONAL_KEYS: u32 = 1;
+/// Allow duplicate keys, see [`Opts::duplicate_keys`](../struct.Opts.html#method.duplicate_keys)
+pub const MARKINGS_DUPLICATE_KEYS: u32 = 1 << 1;
+/// Allow empty templates, see [`Opts::empty_template`](../struct.Opts.html#method.empty_template)
+pub const MARKINGS_EMPTY_TEMPLATE: u32 = 1 << 2;
+/// Match keys case insensitively, see [`Opts::case_insensitive_keys`](../struct.Opts.html#method.case_insensitive_keys)
+pub const MARKINGS_CASE_INSENSITIVE_KEYS: u32 = 1 << 3;
+
+/// The stable code for an error
+///
+/// This is the [`ErrorKind::number`](../enum.ErrorKind.html#method.number) of the error, so codes are assigned in the
+/// order the kinds were added, starting at 1
+pub fn error_code(kind: &ErrorKind) -> c_int {
+    kind.number().into()
+}
+
+unsafe fn to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    CStr::from_ptr(ptr).to_str().ok()
+}
+
+/// Parse a template with the `options` flags, like `MARKINGS_OPTIONAL_KEYS | MARKINGS_DUPLICATE_KEYS`
+///
+/// On success, `out` is set to the template, which has to be freed with [`markings_free`]
+///
+/// # Safety
+/// `input` has to be a null terminated string, and `out` has to be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn markings_parse(
+    input: *const c_char,
+    options: u32,
+    out: *mut *mut MarkingsTemplate,
+) -> c_int {
+    let input = match to_str(input) {
+        Some(input) if !out.is_null() => input,
+        _ => return MARKINGS_INVALID_ARGUMENT,
+    };
+
+    let mut opts = Opts::default();
+    let flags = [
+        (
+            MARKINGS_OPTIONAL_KEYS,
+            Opts::optional_keys as fn(Opts) -> Opts,
+        ),
+        (MARKINGS_DUPLICATE_KEYS, Opts::duplicate_keys),
+        (MARKINGS_EMPTY_TEMPLATE, Opts::empty_template),
+        (MARKINGS_CASE_INSENSITIVE_KEYS, Opts::case_insensitive_keys),
+    ];
+    for (flag, set) in &flags {
+        if options & flag != 0 {
+            opts = set(opts);
+        }
+    }
+
+    match Template::parse(input, opts) {
+        Ok(template) => {
+            let template = MarkingsTemplate(template.into_owned());
+            *out = Box::into_raw(Box::new(template));
+            MARKINGS_OK
+        }
+        Err(err) => error_code(err.kind()),
+    }
+}
+
+/// Apply `len` keys and values to a template
+///
+/// On success, `out` is set to the output, which has to be freed with [`markings_string_free`]
+///
+/// # Safety
+/// `template` has to be from [`markings_parse`], `keys` and `values` have to point to `len` null
+/// terminated strings, and `out` has to be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn markings_apply(
+    template: *const MarkingsTemplate,
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    len: usize,
+    out: *mut *mut c_char,
+) -> c_int {
+    if template.is_null() || out.is_null() || (len > 0 && (keys.is_null() || values.is_null())) {
+        return MARKINGS_INVALID_ARGUMENT;
+    }
+
+    let mut args = Args::new();
+    for i in 0..len {
+        match (to_str(*keys.add(i)), to_str(*values.add(i))) {
+            (Some(key), Some(val)) => args = args.with(key, val),
+            _ => return MARKINGS_INVALID_ARGUMENT,
+        }
+    }
+
+    let output = match (*template).0.apply(&args) {
+        Ok(output) => output,
+        Err(err) => return error_code(err.kind()),
+    };
+    match CString::new(output) {
+        Ok(output) => {
+            *out = output.into_raw();
+            MARKINGS_OK
+        }
+        Err(..) => MARKINGS_INVALID_ARGUMENT,
+    }
+}
+
+/// Free a template from [`markings_parse`]. Null is ignored
+///
+/// # Safety
+/// `template` has to be from [`markings_parse`], and can't be used after this
+#[no_mangle]
+pub unsafe extern "C" fn markings_free(template: *mut MarkingsTemplate) {
+    if !template.is_null() {
+        drop(Box::from_raw(template))
+    }
+}
+
+/// Free a string from [`markings_apply`]. Null is ignored
+///
+/// # Safety
+/// `string` has to be from [`markings_apply`], and can't be used after this
+#[no_mangle]
+pub unsafe extern "C" fn markings_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::null_mut;
+
+    #[test]
+    fn parse_and_apply() {
+        unsafe {
+            let mut template = null_mut();
+            let input = CString::new("${greeting}, ${name}!").unwrap();
+            assert_eq!(
+                markings_parse(input.as_ptr(), 0, &mut template),
+                MARKINGS_OK
+            );
+
+            let keys = ["greeting", "name"].map(|s| CString::new(s).unwrap());
+            let values = ["hello", "bob"].map(|s| CString::new(s).unwrap());
+            let keys = [keys[0].as_ptr(), keys[1].as_ptr()];
+            let values = [values[0].as_ptr(), values[1].as_ptr()];
+            let mut out = null_mut();
+            let code = markings_apply(template, keys.as_ptr(), values.as_ptr(), 2, &mut out);
+            assert_eq!(code, MARKINGS_OK);
+            assert_eq!(CStr::from_ptr(out).to_str().unwrap(), "hello, bob!");
+            markings_string_free(out);
+
+            let code = markings_apply(template, keys.as_ptr(), values.as_ptr(), 1, &mut out);
+            assert_eq!(
+                code,
+                error_code(&ErrorKind::MissingKeys {
+                    keys: vec!["name".into()]
+                })
+            );
+            markings_free(template);
+
+            let code = markings_parse(input.as_ptr(), MARKINGS_OPTIONAL_KEYS, &mut template);
+            assert_eq!(code, MARKINGS_OK);
+            let code = markings_apply(template, keys.as_ptr(), values.as_ptr(), 1, &mut out);
+            assert_eq!(code, MARKINGS_OK);
+            assert_eq!(CStr::from_ptr(out).to_str().unwrap(), "hello, ${name}!");
+            markings_string_free(out);
+            markings_free(template);
+
+            let input = CString::new("${a}${a}").unwrap();
+            let code = markings_parse(input.as_ptr(), 0, &mut template);
+            assert_eq!(code, error_code(&ErrorKind::DuplicateKeys));
+            let code = markings_parse(input.as_ptr(), MARKINGS_DUPLICATE_KEYS, &mut template);
+            assert_eq!(code, MARKINGS_OK);
+            markings_free(template);
+
+            let code = markings_parse(std::ptr::null(), 0, &mut template);
+            assert_eq!(code, MARKINGS_INVALID_ARGUMENT);
+        }
+    }
+
+    #[test]
+    fn codes() {
+        let kinds = [
+            ErrorKind::MismatchedBraces { open: 1, close: 0 },
+            ErrorKind::NoMatch,
+            ErrorKind::Sandboxed {
+                feature: "extends".into(),
+            },
+            ErrorKind::DuplicateArg { key: "a".into() },
+            ErrorKind::InvalidArg {
+                arg: "a".into(),
+                pos: 1,
+            },
+            ErrorKind::ExtendsCycle {
+                names: vec!["a".into(), "a".into()],
+            },
+            ErrorKind::UnknownSpec {
+                spec: "money".into(),
+            },
+        ];
+        for kind in &kinds {
+            assert_eq!(kind.code(), format!("M{:04}", error_code(kind)));
+        }
+    }
+}
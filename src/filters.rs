@@ -0,0 +1,992 @@
+//! Filters that transform values before they are written, like `${name|upper}`
+use crate::{Error, ErrorKind, Opts, Result};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::IsTerminal as _;
+
+/// How values are escaped when they are written
+///
+/// An escape mode can be applied to every value with [`Opts::escape`](./struct.Opts.html#method.escape),
+/// or to a single key with its filter, like `${name|md}`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Escape {
+    /// Values are written as is
+    #[default]
+    None,
+    /// Markdown punctuation, like `*`, `_` and `[`, is escaped with a backslash. The filter is `md`
+    Markdown,
+    /// The value is quoted as a single POSIX shell word. The filter is `sh`
+    Shell,
+    /// The value is quoted as a single PowerShell string. The filter is `ps`
+    PowerShell,
+    /// The value is quoted as a SQL identifier, like a table or column name. The filter is `sql_ident`
+    SqlIdent(SqlDialect),
+    /// The value is quoted as a SQL string literal. The filter is `sql_str`
+    SqlString(SqlDialect),
+}
+
+impl Escape {
+    /// Escape a value
+    /// ```
+    /// # use markings::{Escape, SqlDialect};
+    /// assert_eq!(Escape::Markdown.escape("**hi** [x](y)"), r"\*\*hi\*\* \[x\]\(y\)");
+    /// assert_eq!(Escape::Shell.escape("it's $HOME"), r"'it'\''s $HOME'");
+    /// assert_eq!(Escape::PowerShell.escape("it's $HOME"), "'it''s $HOME'");
+    ///
+    /// assert_eq!(Escape::SqlIdent(SqlDialect::Ansi).escape(r#"my "table""#), r#""my ""table""""#);
+    /// assert_eq!(Escape::SqlString(SqlDialect::MySql).escape(r"it's C:\"), r"'it''s C:\\'");
+    /// ```
+    pub fn escape(self, value: &str) -> Cow<'_, str> {
+        match self {
+            Escape::None => Cow::Borrowed(value),
+            Escape::Markdown => escape_with(value, |ch| {
+                const SPECIAL: &str = "\\`*_{}[]()#+-.!|<>~";
+                SPECIAL.contains(ch).then_some("\\")
+            }),
+            Escape::Shell => Cow::Owned(format!("'{}'", value.replace('\'', r"'\''"))),
+            Escape::PowerShell => Cow::Owned(format!("'{}'", quote_powershell(value))),
+            Escape::SqlIdent(dialect) => {
+                let (open, close) = match dialect {
+                    SqlDialect::Ansi => ('"', '"'),
+                    SqlDialect::MySql => ('`', '`'),
+                    SqlDialect::SqlServer => ('[', ']'),
+                };
+                let escaped = value.replace(close, &format!("{0}{0}", close));
+                Cow::Owned(format!("{}{}{}", open, escaped, close))
+            }
+            Escape::SqlString(dialect) => {
+                let mut escaped = value.replace('\'', "''");
+                if dialect == SqlDialect::MySql {
+                    escaped = escaped.replace('\\', r"\\");
+                }
+                Cow::Owned(format!("'{}'", escaped))
+            }
+        }
+    }
+}
+
+/// The SQL dialect used by the `sql_ident` and `sql_str` filters
+///
+/// See [`Opts::sql_dialect`](./struct.Opts.html#method.sql_dialect)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SqlDialect {
+    /// Standard SQL, as used by PostgreSQL and SQLite. Identifiers are quoted with `"`
+    #[default]
+    Ansi,
+    /// MySQL and MariaDB. Identifiers are quoted with `` ` `` and backslashes in strings are escaped
+    MySql,
+    /// Microsoft SQL Server. Identifiers are quoted with `[` and `]`
+    SqlServer,
+}
+
+// powershell treats the unicode single quotes as quotes as well, so they're doubled too
+fn quote_powershell(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            out.push(ch);
+        }
+        out.push(ch);
+    }
+    out
+}
+
+// prefixes each character that needs to be escaped
+fn escape_with(value: &str, prefix: impl Fn(char) -> Option<&'static str>) -> Cow<'_, str> {
+    if !value.chars().any(|ch| prefix(ch).is_some()) {
+        return Cow::Borrowed(value);
+    }
+    let mut out = String::with_capacity(value.len() + 8);
+    for ch in value.chars() {
+        if let Some(prefix) = prefix(ch) {
+            out.push_str(prefix);
+        }
+        out.push(ch);
+    }
+    Cow::Owned(out)
+}
+
+/// When to use ANSI colors for the color filters, like `${name|red}`
+///
+/// See [`Opts::color`](./struct.Opts.html#method.color)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorChoice {
+    /// Use colors if stdout is a terminal, and the `NO_COLOR` environment variable isn't set
+    #[default]
+    Auto,
+    /// Always use colors
+    Always,
+    /// Never use colors, the color filters leave the value as is
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+// the SGR code for each color filter
+const COLORS: &[(&str, u8)] = &[
+    ("bold", 1),
+    ("dim", 2),
+    ("italic", 3),
+    ("underline", 4),
+    ("black", 30),
+    ("red", 31),
+    ("green", 32),
+    ("yellow", 33),
+    ("blue", 34),
+    ("magenta", 35),
+    ("cyan", 36),
+    ("white", 37),
+];
+
+/// A custom filter, registered with [`Filters::register`](./struct.Filters.html#method.register)
+///
+/// It is called with the value and the arguments of the filter, and returns the new value
+pub type FilterFn = fn(&str, &[FilterArg]) -> Result<String, FilterError>;
+
+/// A registry of custom filters, used with [`Opts::filters`](./struct.Opts.html#method.filters)
+///
+/// Custom filters can't replace the built-in filters
+/// ```
+/// # use markings::{Template, Args, Opts, Filters, FilterArg, FilterError};
+/// fn round(value: &str, args: &[FilterArg]) -> Result<String, FilterError> {
+///     let places = args.first().and_then(FilterArg::as_int).unwrap_or(0) as usize;
+///     let value = value.parse::<f64>().map_err(FilterError::new)?;
+///     Ok(format!("{:.*}", places, value))
+/// }
+///
+/// let opts = Opts::default().filters(Filters::new().register("round", round));
+/// let template = Template::parse("${price|round:2}", opts).unwrap();
+/// assert_eq!(template.apply(&Args::new().with("price", 1.005_1)).unwrap(), "1.01");
+///
+/// let err = template.apply(&Args::new().with("price", "free")).unwrap_err();
+/// assert_eq!(err.to_string(), "filter 'round:2' failed for key 'price'");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    filters: BTreeMap<String, FilterFn>,
+}
+
+impl Filters {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a filter under `name`, replacing any filter already registered with it
+    #[must_use]
+    pub fn register(mut self, name: impl Into<String>, filter: FilterFn) -> Self {
+        self.filters.insert(name.into(), filter);
+        self
+    }
+
+    /// Get the filter registered under `name`
+    pub fn get(&self, name: &str) -> Option<FilterFn> {
+        self.filters.get(name).copied()
+    }
+}
+
+// registries compare by their names, and the addresses of their functions
+impl PartialEq for Filters {
+    fn eq(&self, other: &Self) -> bool {
+        self.filters.len() == other.filters.len()
+            && self
+                .filters
+                .iter()
+                .zip(&other.filters)
+                .all(|((left, f), (right, g))| left == right && std::ptr::fn_addr_eq(*f, *g))
+    }
+}
+
+impl Eq for Filters {}
+
+impl std::hash::Hash for Filters {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        for (name, filter) in &self.filters {
+            name.hash(state);
+            (*filter as usize).hash(state);
+        }
+    }
+}
+
+/// An argument to a filter, like the `2` in `${price|round:2}`
+///
+/// Quoted arguments are always strings. Other arguments are parsed as an integer, a float
+/// or a boolean, in that order, and are strings if they are none of those
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterArg {
+    /// A string, like `"a b"` or `abc`
+    Str(String),
+    /// An integer, like `-2`
+    Int(i64),
+    /// A float, like `1.5`
+    Float(f64),
+    /// `true` or `false`
+    Bool(bool),
+}
+
+impl FilterArg {
+    /// The argument, if it is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FilterArg::Str(arg) => Some(arg),
+            _ => None,
+        }
+    }
+
+    /// The argument, if it is an integer
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            FilterArg::Int(arg) => Some(arg),
+            _ => None,
+        }
+    }
+
+    /// The argument, if it is a float or an integer
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            FilterArg::Float(arg) => Some(arg),
+            FilterArg::Int(arg) => Some(arg as f64),
+            _ => None,
+        }
+    }
+
+    /// The argument, if it is a boolean
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            FilterArg::Bool(arg) => Some(arg),
+            _ => None,
+        }
+    }
+
+    // a quoted argument, or bare text that is parsed into a type
+    fn parse(arg: &str) -> Option<Self> {
+        if arg.starts_with('"') || arg.contains('"') {
+            return unquote(arg).map(FilterArg::Str);
+        }
+        let arg = match arg {
+            "true" => FilterArg::Bool(true),
+            "false" => FilterArg::Bool(false),
+            arg => match (arg.parse(), arg.parse()) {
+                (Ok(arg), _) => FilterArg::Int(arg),
+                (_, Ok(arg)) => FilterArg::Float(arg),
+                _ => FilterArg::Str(arg.to_string()),
+            },
+        };
+        Some(arg)
+    }
+}
+
+impl std::fmt::Display for FilterArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterArg::Str(arg) => f.write_str(arg),
+            FilterArg::Int(arg) => arg.fmt(f),
+            FilterArg::Float(arg) => arg.fmt(f),
+            FilterArg::Bool(arg) => arg.fmt(f),
+        }
+    }
+}
+
+/// An error returned by a custom filter
+///
+/// This is the `source` of the [`ErrorKind::Filter`](./enum.ErrorKind.html#variant.Filter) error
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterError {
+    message: String,
+}
+
+impl FilterError {
+    /// Create an error with a message
+    pub fn new(message: impl ToString) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+/// What happens when a custom filter fails while rendering
+///
+/// See [`Opts::on_filter_error`](./struct.Opts.html#method.on_filter_error)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OnFilterError {
+    /// The render fails with an [`ErrorKind::Filter`](./enum.ErrorKind.html#variant.Filter) error
+    #[default]
+    Fail,
+    /// The value is written without any of its filters, except the ones that escape it like `md` or `sh`. It is
+    /// still escaped by [`Opts::escape`](./struct.Opts.html#method.escape)
+    Unfiltered,
+    /// The placeholder is written instead of the value, like `#ERR`
+    Placeholder(&'static str),
+}
+
+#[derive(Clone, Debug)]
+enum Filter {
+    Color(u8),
+    Escape(Escape),
+    Text(TextFilter),
+    #[cfg(feature = "regex")]
+    Replace(regex::Regex, String),
+    Custom(FilterFn, Vec<FilterArg>),
+}
+
+impl Filter {
+    // parses a filter and its arguments, like `red` or `re:"\s+":" "`
+    fn parse(filter: &str, opts: &Opts) -> Result<Self, ErrorKind> {
+        let invalid = || ErrorKind::InvalidFilter {
+            filter: filter.to_string(),
+        };
+
+        let mut parts = split(filter, ":").into_iter();
+        let name = parts.next().unwrap_or_default();
+        if opts
+            .allowed_filters
+            .is_some_and(|allowed| !allowed.contains(&name))
+        {
+            return Err(ErrorKind::ForbiddenFilter {
+                name: name.to_string(),
+            });
+        }
+
+        let args = parts.map(FilterArg::parse).collect::<Option<Vec<_>>>();
+        let args = args.ok_or_else(invalid)?;
+
+        #[cfg(feature = "regex")]
+        if name == "re" {
+            return match &*args {
+                [pattern, replacement] => {
+                    let pattern = regex::Regex::new(&pattern.to_string()).map_err(|_| invalid())?;
+                    Ok(Filter::Replace(pattern, replacement.to_string()))
+                }
+                _ => Err(invalid()),
+            };
+        }
+
+        if let Some(filter) = TextFilter::parse(name, &args, opts) {
+            return filter.map(Filter::Text).ok_or_else(invalid);
+        }
+
+        let custom = opts.filters.as_ref().and_then(|filters| filters.get(name));
+        match (Self::builtin(name, opts), custom) {
+            (Some(filter), _) if args.is_empty() => Ok(filter),
+            (Some(..), _) => Err(invalid()),
+            (None, Some(filter)) => Ok(Filter::Custom(filter, args)),
+            (None, None) => Err(ErrorKind::UnknownFilter {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    fn builtin(name: &str, opts: &Opts) -> Option<Self> {
+        let filter = match name {
+            "sql_ident" => Filter::Escape(Escape::SqlIdent(opts.sql_dialect)),
+            "sql_str" => Filter::Escape(Escape::SqlString(opts.sql_dialect)),
+            "md" => Filter::Escape(Escape::Markdown),
+            "sh" => Filter::Escape(Escape::Shell),
+            "ps" => Filter::Escape(Escape::PowerShell),
+            name => {
+                let &(_, code) = COLORS.iter().find(|&&(color, _)| color == name)?;
+                Filter::Color(code)
+            }
+        };
+        Some(filter)
+    }
+}
+
+// the built-in filters that change text, like `truncate:10` or `slug`
+#[derive(Clone, Debug)]
+enum TextFilter {
+    Truncate(usize),
+    PadLeft(usize),
+    PadRight(usize),
+    Replace(String, String, usize),
+    Capitalize,
+    Title,
+    Slug,
+    Reverse,
+}
+
+// the widest a text filter can be without a limit on the output
+const MAX_WIDTH: usize = 1 << 20;
+
+impl TextFilter {
+    // `None` if there is no filter with this name, and `Some(None)` if its arguments are invalid
+    //
+    // lengths can't be more than the limit of the output, so a filter can't allocate more than that
+    fn parse(name: &str, args: &[FilterArg], opts: &Opts) -> Option<Option<Self>> {
+        let max = opts.max_output_len.unwrap_or(MAX_WIDTH);
+        let len = || match args {
+            [len] => len
+                .as_int()
+                .and_then(|len| std::convert::TryFrom::try_from(len).ok())
+                .filter(|&len| len <= max),
+            _ => None,
+        };
+        let none = |filter| args.is_empty().then_some(filter);
+        let filter = match name {
+            "truncate" => len().map(TextFilter::Truncate),
+            "pad_left" => len().map(TextFilter::PadLeft),
+            "pad_right" => len().map(TextFilter::PadRight),
+            "replace" => match args {
+                [from, to] => Some(TextFilter::Replace(from.to_string(), to.to_string(), max)),
+                _ => None,
+            },
+            "capitalize" => none(TextFilter::Capitalize),
+            "title" => none(TextFilter::Title),
+            "slug" => none(TextFilter::Slug),
+            "reverse" => none(TextFilter::Reverse),
+            _ => return None,
+        };
+        Some(filter)
+    }
+
+    // lengths are counted in characters
+    //
+    // a replacement is counted before it is made, so it fails with `OutputTooLarge` rather than growing past the limit
+    fn apply(&self, value: &str) -> Result<String> {
+        let pad = |len: usize| " ".repeat(len.saturating_sub(value.chars().count()));
+        let value = match self {
+            TextFilter::Truncate(len) => value.chars().take(*len).collect(),
+            TextFilter::PadLeft(len) => pad(*len) + value,
+            TextFilter::PadRight(len) => value.to_string() + &pad(*len),
+            TextFilter::Replace(from, ..) if from.is_empty() => value.to_string(),
+            TextFilter::Replace(from, to, max) => {
+                let count = value.matches(&**from).count();
+                let len = (count.checked_mul(to.len()))
+                    .and_then(|len| len.checked_add(value.len() - count * from.len()));
+                if len.is_none_or(|len| len > *max) {
+                    return Err(ErrorKind::OutputTooLarge { limit: *max }.into());
+                }
+                value.replace(&**from, to)
+            }
+            TextFilter::Capitalize => capitalize(value),
+            TextFilter::Title => {
+                let mut out = String::with_capacity(value.len());
+                let mut start = true;
+                for ch in value.chars() {
+                    match start {
+                        true => out.extend(ch.to_uppercase()),
+                        false => out.extend(ch.to_lowercase()),
+                    }
+                    start = ch.is_whitespace();
+                }
+                out
+            }
+            TextFilter::Slug => {
+                let mut out = String::with_capacity(value.len());
+                for ch in value.chars() {
+                    if ch.is_alphanumeric() {
+                        out.extend(ch.to_lowercase());
+                    } else if !out.is_empty() && !out.ends_with('-') {
+                        out.push('-');
+                    }
+                }
+                let len = out.trim_end_matches('-').len();
+                out.truncate(len);
+                out
+            }
+            TextFilter::Reverse => value.chars().rev().collect(),
+        };
+        Ok(value)
+    }
+}
+
+// uppercases the first character, and leaves the rest as is
+fn capitalize(value: &str) -> String {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// The filters of a key, parsed once when its template is parsed
+///
+/// Chains compare by the text of their filters
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Chain {
+    filters: Vec<(String, Filter)>,
+}
+
+impl Chain {
+    // parses each filter, checking that it exists and that its arguments are valid
+    pub(crate) fn parse<'f>(
+        filters: impl IntoIterator<Item = &'f str>,
+        opts: &Opts,
+    ) -> Result<Self, ErrorKind> {
+        let filters = filters
+            .into_iter()
+            .map(|filter| Ok((filter.to_string(), Filter::parse(filter, opts)?)))
+            .collect::<Result<_, ErrorKind>>()?;
+        Ok(Self { filters })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl PartialEq for Chain {
+    fn eq(&self, other: &Self) -> bool {
+        self.filters.len() == other.filters.len()
+            && (self.filters.iter())
+                .zip(&other.filters)
+                .all(|((left, _), (right, _))| left == right)
+    }
+}
+
+// splits the text on `sep`, unless it is inside of double quotes
+pub(crate) fn split<'i>(input: &'i str, sep: &str) -> Vec<&'i str> {
+    let mut parts = vec![];
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (pos, ch) in input.char_indices() {
+        match ch {
+            // the rest of a separator
+            _ if pos < start => {}
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if !quoted && input[pos..].starts_with(sep) => {
+                parts.push(&input[start..pos]);
+                start = pos + sep.len();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+// a filter argument is either bare text, or quoted with `\"` and `\\` escapes
+pub(crate) fn unquote(arg: &str) -> Option<String> {
+    let inner = match arg.strip_prefix('"') {
+        Some(inner) => inner.strip_suffix('"')?,
+        None if arg.contains('"') => return None,
+        None => return Some(arg.to_string()),
+    };
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next()? {
+                ch @ ('"' | '\\') => out.push(ch),
+                ch => {
+                    out.push('\\');
+                    out.push(ch)
+                }
+            },
+            '"' => return None,
+            ch => out.push(ch),
+        }
+    }
+    Some(out)
+}
+
+// applies the filters of the chain, in order, to the value of `key`, and the escape mode
+//
+// the escape mode is applied after the last filter that changes the text, so no filter can undo it, and colors
+// are added around the escaped value. values whose last filter escapes them aren't escaped by the escape mode as well
+pub(crate) fn apply<'v>(
+    key: &str,
+    chain: &Chain,
+    value: &'v str,
+    opts: &Opts,
+) -> Result<Cow<'v, str>> {
+    if chain.is_empty() && !opts.escapes() {
+        return Ok(Cow::Borrowed(value));
+    }
+
+    let last = (chain.filters.iter()).rposition(|(_, filter)| !matches!(filter, Filter::Color(..)));
+    let escape = match last.map(|last| &chain.filters[last].1) {
+        Some(Filter::Escape(..)) => Escape::None,
+        _ => opts.escape,
+    };
+    let escape_at = last.map_or(0, |last| last + 1);
+
+    let original = value;
+    let mut value = Cow::Borrowed(value);
+    let colored = opts.color.enabled();
+    for (i, (name, filter)) in chain.filters.iter().enumerate() {
+        if i == escape_at {
+            value = escape_value(escape, value);
+        }
+        value = match filter {
+            Filter::Color(code) if colored => format!("\x1b[{}m{}\x1b[0m", code, value).into(),
+            Filter::Color(..) => value,
+            Filter::Escape(escape) => escape.escape(&value).into_owned().into(),
+            Filter::Text(filter) => filter.apply(&value)?.into(),
+            #[cfg(feature = "regex")]
+            Filter::Replace(pattern, replacement) => pattern
+                .replace_all(&value, &**replacement)
+                .into_owned()
+                .into(),
+            Filter::Custom(filter, args) => match filter(&value, args) {
+                Ok(value) => value.into(),
+                Err(err) => {
+                    return match opts.on_filter_error {
+                        OnFilterError::Fail => Err(Error {
+                            kind: ErrorKind::Filter {
+                                key: key.to_string(),
+                                filter: name.to_string(),
+                            },
+                            span: None,
+                            source: Some(Box::new(err)),
+                        }),
+                        OnFilterError::Unfiltered => Ok(unfiltered(chain, original, escape)),
+                        OnFilterError::Placeholder(placeholder) => Ok(placeholder.into()),
+                    }
+                }
+            },
+        }
+    }
+    if escape_at == chain.filters.len() {
+        value = escape_value(escape, value);
+    }
+    Ok(value)
+}
+
+// the value with only the escape filters of the chain, so a failing filter can't leave a value unescaped
+fn unfiltered<'v>(chain: &Chain, value: &'v str, escape: Escape) -> Cow<'v, str> {
+    let value =
+        (chain.filters.iter()).fold(Cow::Borrowed(value), |value, (_, filter)| match filter {
+            Filter::Escape(escape) => escape.escape(&value).into_owned().into(),
+            _ => value,
+        });
+    escape_value(escape, value)
+}
+
+fn escape_value(escape: Escape, value: Cow<'_, str>) -> Cow<'_, str> {
+    match value {
+        _ if escape == Escape::None => value,
+        Cow::Borrowed(value) => escape.escape(value),
+        Cow::Owned(value) => escape.escape(&value).into_owned().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, ColorChoice, ErrorKind, Escape, Opts, SqlDialect, Template};
+
+    #[test]
+    fn shell() {
+        let template = Template::parse("rm -- ${file|sh}", Opts::default()).unwrap();
+        let args = Args::new().with("file", "a b'; rm -rf /");
+        assert_eq!(template.apply(&args).unwrap(), r"rm -- 'a b'\''; rm -rf /'");
+        let args = Args::new().with("file", "");
+        assert_eq!(template.apply(&args).unwrap(), "rm -- ''");
+
+        let template = Template::parse("Remove-Item ${file|ps}", Opts::default()).unwrap();
+        let args = Args::new().with("file", "a b'; $x \u{2019}");
+        assert_eq!(
+            template.apply(&args).unwrap(),
+            "Remove-Item 'a b''; $x \u{2019}\u{2019}'"
+        );
+    }
+
+    #[test]
+    fn escape_last() {
+        let opts = Opts::default()
+            .escape(Escape::Shell)
+            .sandbox(crate::Sandbox::new());
+        let template = Template::parse(r#"echo ${a|replace:"'":""}"#, opts.clone()).unwrap();
+        let args = Args::new().with("a", "x'; touch /tmp/pwned; echo '");
+        assert_eq!(
+            template.apply(&args).unwrap(),
+            "echo 'x; touch /tmp/pwned; echo '"
+        );
+
+        // an escaping filter that isn't last doesn't skip the escape mode
+        let template = Template::parse(r#"echo ${a|sh|replace:"'":""}"#, opts.clone()).unwrap();
+        assert_eq!(
+            template.apply(&args).unwrap(),
+            r"echo 'x\; touch /tmp/pwned; echo \'"
+        );
+
+        // colors are added around the escaped value
+        let opts = opts.color(ColorChoice::Always);
+        let template = Template::parse("echo ${a|truncate:2|red}", opts).unwrap();
+        assert_eq!(
+            template.apply(&args).unwrap(),
+            "echo \x1b[31m'x'\\'''\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn sql() {
+        let input = "SELECT ${col|sql_ident} FROM t WHERE name = ${name|sql_str}";
+        let template = Template::parse(input, Opts::default()).unwrap();
+        let args = Args::new().with("col", "a\"b").with("name", r"o'neil\");
+        assert_eq!(
+            template.apply(&args).unwrap(),
+            r#"SELECT "a""b" FROM t WHERE name = 'o''neil\'"#
+        );
+
+        let opts = Opts::default().sql_dialect(SqlDialect::MySql);
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(
+            template.apply(&args).unwrap(),
+            r#"SELECT `a"b` FROM t WHERE name = 'o''neil\\'"#
+        );
+
+        let opts = Opts::default().sql_dialect(SqlDialect::SqlServer);
+        let template = Template::parse(input, opts).unwrap();
+        let args = Args::new().with("col", "a]b").with("name", "");
+        assert_eq!(
+            template.apply(&args).unwrap(),
+            "SELECT [a]]b] FROM t WHERE name = ''"
+        );
+    }
+
+    #[test]
+    fn markdown() {
+        let template = Template::parse("**${name|md}** said: ${msg}", Opts::default()).unwrap();
+        let args = Args::new().with("name", "_bob_").with("msg", "*hi*");
+        assert_eq!(template.apply(&args).unwrap(), r"**\_bob\_** said: *hi*");
+
+        // keys with their own escaping filter aren't escaped twice
+        let opts = Opts::default().escape(Escape::Markdown);
+        let template = Template::parse("**${name|md}** said: ${msg}", opts).unwrap();
+        assert_eq!(template.apply(&args).unwrap(), r"**\_bob\_** said: \*hi\*");
+
+        let compiled = template.compile(&args).unwrap();
+        assert_eq!(
+            compiled.render(&["*hi*", "_bob_"]).unwrap(),
+            r"**\_bob\_** said: \*hi\*"
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let opts = Opts::default().color(ColorChoice::Always);
+        let template = Template::parse("${name|red|bold}!", opts).unwrap();
+        let output = template.apply(&Args::new().with("name", "bob")).unwrap();
+        assert_eq!(output, "\x1b[1m\x1b[31mbob\x1b[0m\x1b[0m!");
+
+        let opts = Opts::default().color(ColorChoice::Never);
+        let template = Template::parse("${name|red|bold}!", opts.clone()).unwrap();
+        let output = template.apply(&Args::new().with("name", "bob")).unwrap();
+        assert_eq!(output, "bob!");
+
+        let err = Template::parse("hi ${name|sparkly}", opts).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::UnknownFilter {
+                name: "sparkly".into()
+            }
+        );
+        assert_eq!(err.span(), Some(3..18));
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(
+            super::split(r#"a|re:"|\"|":b|c"#, "|"),
+            vec!["a", r#"re:"|\"|":b"#, "c"]
+        );
+        assert_eq!(
+            super::split(r#"a ?? "??" ??b"#, "??"),
+            vec!["a ", r#" "??" "#, "b"]
+        );
+        assert_eq!(super::unquote(r#""a\"\\\s""#).unwrap(), r#"a"\\s"#);
+        assert_eq!(FilterArg::parse("-2"), Some(FilterArg::Int(-2)));
+        assert_eq!(FilterArg::parse("1.5"), Some(FilterArg::Float(1.5)));
+        assert_eq!(FilterArg::parse("true"), Some(FilterArg::Bool(true)));
+        assert_eq!(FilterArg::parse(r#""2""#), Some(FilterArg::Str("2".into())));
+        assert_eq!(FilterArg::parse("a"), Some(FilterArg::Str("a".into())));
+        assert_eq!(super::unquote("a"), Some("a".to_string()));
+        assert_eq!(super::unquote(r#""a"#), None);
+        assert_eq!(super::unquote(r#"a"b"#), None);
+
+        let err = Template::parse("${name|red:1}", Opts::default()).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::InvalidFilter {
+                filter: "red:1".into()
+            }
+        );
+    }
+
+    #[test]
+    fn text() {
+        let cases = [
+            ("truncate:3", "héllo", "hél"),
+            ("truncate:10", "hello", "hello"),
+            ("pad_left:5", "ab", "   ab"),
+            ("pad_right:5", "ab", "ab   "),
+            ("pad_right:1", "ab", "ab"),
+            (r#"replace:"l":"L""#, "hello", "heLLo"),
+            (r#"replace:"":"x""#, "hello", "hello"),
+            ("capitalize", "élan vital", "Élan vital"),
+            ("title", "hello  WIDE world", "Hello  Wide World"),
+            ("slug", "  Hello, World! 2024 ", "hello-world-2024"),
+            ("reverse", "abc", "cba"),
+        ];
+        for (filter, value, expected) in &cases {
+            let input = format!("${{v|{}}}", filter);
+            let template = Template::parse(&input, Opts::default()).unwrap();
+            let output = template.apply(&Args::new().with("v", value)).unwrap();
+            assert_eq!(output, *expected, "{}", filter);
+        }
+
+        for filter in &[
+            "truncate",
+            "truncate:-1",
+            "pad_left:a",
+            "pad_left:100000000000",
+            "replace:a",
+            "slug:1",
+        ] {
+            let input = format!("${{v|{}}}", filter);
+            let err = Template::parse(&input, Opts::default()).unwrap_err();
+            assert!(
+                matches!(err.kind(), ErrorKind::InvalidFilter { .. }),
+                "{}",
+                filter
+            );
+        }
+
+        // widths can't be more than the limit of the output
+        let opts = Opts::default().sandbox(crate::Sandbox::new().max_output_len(100));
+        assert!(Template::parse("${v|pad_left:100}", opts.clone()).is_ok());
+        let err = Template::parse("${v|pad_left:101}", opts.clone()).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::InvalidFilter {
+                filter: "pad_left:101".into()
+            }
+        );
+
+        // and neither can a replacement, even if a later filter would make it short again
+        let source = r#"${v|replace:"a":"aaaaaaaaaa"|truncate:5}"#;
+        let template = Template::parse(source, opts.clone()).unwrap();
+        let args = Args::new().with("v", "a".repeat(10));
+        assert_eq!(template.apply(&args).unwrap(), "aaaaa");
+        let err = template.apply(&args.with("v", "a".repeat(11))).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::OutputTooLarge { limit: 100 });
+
+        let opts = Opts::default().allowed_filters(&["slug"]);
+        assert!(Template::parse("${v|slug}", opts.clone()).is_ok());
+        let err = Template::parse("${v|slug|reverse}", opts).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::ForbiddenFilter {
+                name: "reverse".into()
+            }
+        );
+        assert_eq!(err.span(), Some(0..17));
+    }
+
+    #[test]
+    fn custom() {
+        fn repeat(value: &str, args: &[FilterArg]) -> Result<String, FilterError> {
+            match args {
+                [count] => Ok(value.repeat(count.as_int().unwrap_or(1) as _)),
+                [count, sep] => {
+                    let values = vec![value; count.as_int().unwrap_or(1) as _];
+                    Ok(values.join(sep.as_str().unwrap_or_default()))
+                }
+                _ => Err(FilterError::new("expected a count")),
+            }
+        }
+
+        let opts = Opts::default().filters(Filters::new().register("repeat", repeat));
+
+        let template =
+            Template::parse(r#"${a|repeat:3} ${b|repeat:2:", "|red}"#, opts.clone()).unwrap();
+        let args = Args::new().with("a", "x").with("b", "y");
+        assert_eq!(template.apply(&args).unwrap(), "xxx y, y");
+
+        let template = Template::parse("${a|repeat}", opts.clone()).unwrap();
+        let err = template.apply(&Args::new().with("a", "x")).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::Filter {
+                key: "a".into(),
+                filter: "repeat".into()
+            }
+        );
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), "expected a count");
+
+        let template = Template::parse(
+            "<${a|repeat|md}>",
+            opts.clone().on_filter_error(OnFilterError::Unfiltered),
+        )
+        .unwrap();
+        assert_eq!(
+            template.apply(&Args::new().with("a", "*")).unwrap(),
+            r"<\*>"
+        );
+
+        let template = Template::parse(
+            "echo ${a|sh|repeat}",
+            opts.clone().on_filter_error(OnFilterError::Unfiltered),
+        )
+        .unwrap();
+        let args = Args::new().with("a", "x; rm -rf /");
+        assert_eq!(template.apply(&args).unwrap(), "echo 'x; rm -rf /'");
+
+        let template = Template::parse(
+            "echo ${a|repeat}",
+            opts.clone()
+                .on_filter_error(OnFilterError::Unfiltered)
+                .escape(Escape::Shell),
+        )
+        .unwrap();
+        assert_eq!(template.apply(&args).unwrap(), "echo 'x; rm -rf /'");
+
+        let template = Template::parse(
+            "<${a|repeat}>",
+            opts.on_filter_error(OnFilterError::Placeholder("#ERR")),
+        )
+        .unwrap();
+        assert_eq!(
+            template.apply(&Args::new().with("a", "*")).unwrap(),
+            "<#ERR>"
+        );
+
+        let err = Template::parse("${a|repeat}", Opts::default()).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::UnknownFilter { .. }));
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn regex() {
+        let template = Template::parse(
+            r#"${value|re:"\\s+":" "|re:"<[^>]*>":""}!"#,
+            Opts::default(),
+        )
+        .unwrap();
+        let args = Args::new().with("value", "a  <b>b</b>\n\tc");
+        assert_eq!(template.apply(&args).unwrap(), "a b c!");
+
+        let template =
+            Template::parse(r#"${value|re:"(\w+)@(\w+)":"$2 at $1"}"#, Opts::default()).unwrap();
+        let args = Args::new().with("value", "bob@example");
+        assert_eq!(template.apply(&args).unwrap(), "example at bob");
+
+        for input in &[r#"${value|re:"(":""}"#, r#"${value|re:"a"}"#] {
+            let err = Template::parse(input, Opts::default()).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::InvalidFilter { .. }));
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{ColorChoice, Escape, OnFilterError, Opts, SqlDialect};
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// The source of a template that is always syntactically valid, with the `arbitrary` feature
+///
+/// Its markers are balanced and its blocks are nested properly, so parsing it can only fail because of the
+/// [`Opts`](./struct.Opts.html) it is parsed with, e.g. duplicate keys or limits. Keys are picked from a small
+/// set of names, so fuzzed [`Args`](./struct.Args.html) can use them too. See [`TemplateSource::KEYS`](#associatedconstant.KEYS)
+///
+/// This dereferences to a `str`
+/// ```
+/// # use markings::{Template, TemplateSource, Opts};
+/// use arbitrary::{Arbitrary, Unstructured};
+///
+/// let mut u = Unstructured::new(b"some fuzzer input");
+/// let source = TemplateSource::arbitrary(&mut u).unwrap();
+/// let opts = Opts::default().optional_keys().duplicate_keys().empty_template();
+/// assert!(Template::parse(&source, opts).is_ok());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TemplateSource(String);
+
+impl TemplateSource {
+    /// The names of the keys used in generated templates
+    pub const KEYS: &'static [&'static str] = &["name", "id", "user", "count", "admin", "a.b"];
+
+    // the builtin filters used in generated templates
+    const FILTERS: &'static [&'static str] = &["bold", "red", "md", "sh", "sql_str"];
+
+    // how deeply blocks are nested
+    const MAX_DEPTH: usize = 3;
+
+    /// The template source as a string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Take the template source
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    fn push_items(&mut self, u: &mut Unstructured<'_>, depth: usize) -> Result<()> {
+        for _ in 0..u.int_in_range(0..=8)? {
+            match u.int_in_range(0..=4)? {
+                0 => self.push_literal(u)?,
+                1 => self.push_key(u)?,
+                2 => self.0.push_str("${}"),
+                3 if depth < Self::MAX_DEPTH => self.push_if(u, depth)?,
+                4 if depth < Self::MAX_DEPTH => {
+                    self.push_marker("@", u.choose(Self::KEYS)?);
+                    self.push_items(u, depth + 1)?;
+                    self.0.push_str("${/}");
+                }
+                _ => self.push_literal(u)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn push_literal(&mut self, u: &mut Unstructured<'_>) -> Result<()> {
+        let literal = <&str>::arbitrary(u)?;
+        self.0
+            .extend(literal.chars().filter(|c| !matches!(c, '$' | '{' | '}')));
+        Ok(())
+    }
+
+    fn push_key(&mut self, u: &mut Unstructured<'_>) -> Result<()> {
+        self.0.push_str("${");
+        self.0.push_str(u.choose(Self::KEYS)?);
+        for _ in 0..u.int_in_range(0..=2)? {
+            self.0.push('|');
+            self.0.push_str(u.choose(Self::FILTERS)?);
+        }
+        self.0.push('}');
+        Ok(())
+    }
+
+    fn push_if(&mut self, u: &mut Unstructured<'_>, depth: usize) -> Result<()> {
+        self.push_marker("?", u.choose(Self::KEYS)?);
+        self.push_items(u, depth + 1)?;
+        for _ in 0..u.int_in_range(0..=2)? {
+            self.push_marker(":?", u.choose(Self::KEYS)?);
+            self.push_items(u, depth + 1)?;
+        }
+        if u.arbitrary()? {
+            self.0.push_str("${:else}");
+            self.push_items(u, depth + 1)?;
+        }
+        self.0.push_str("${/}");
+        Ok(())
+    }
+
+    fn push_marker(&mut self, prefix: &str, key: &str) {
+        self.0.push_str("${");
+        self.0.push_str(prefix);
+        self.0.push_str(key);
+        self.0.push('}');
+    }
+}
+
+impl<'a> Arbitrary<'a> for TemplateSource {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut source = Self(String::new());
+        source.push_items(u, 0)?;
+        Ok(source)
+    }
+}
+
+impl std::ops::Deref for TemplateSource {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl std::fmt::Display for TemplateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// custom truthiness and filters can't be generated, so they are left as the default
+impl<'a> Arbitrary<'a> for Opts {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let options = [
+            Opts::optional_keys,
+            Opts::duplicate_keys,
+            Opts::empty_template,
+            Opts::case_insensitive_keys,
+            Opts::bare_dollar_keys,
+            Opts::trim_key_whitespace,
+        ];
+        let mut opts = Opts::default();
+        for option in &options {
+            if u.arbitrary()? {
+                opts = option(opts);
+            }
+        }
+
+        let limits = [
+            Opts::max_output_len,
+            Opts::max_template_len,
+            Opts::max_keys,
+            Opts::max_key_len,
+            Opts::max_depth,
+            Opts::arg_uses,
+        ];
+        for limit in &limits {
+            if let Some(value) = Option::<u16>::arbitrary(u)? {
+                opts = limit(opts, value as usize);
+            }
+        }
+
+        let dialect = *u.choose(&[SqlDialect::Ansi, SqlDialect::MySql, SqlDialect::SqlServer])?;
+        let escape = *u.choose(&[
+            Escape::None,
+            Escape::Markdown,
+            Escape::Shell,
+            Escape::PowerShell,
+            Escape::SqlIdent(dialect),
+            Escape::SqlString(dialect),
+        ])?;
+        let color = *u.choose(&[ColorChoice::Auto, ColorChoice::Always, ColorChoice::Never])?;
+        let policy = *u.choose(&[
+            OnFilterError::Fail,
+            OnFilterError::Unfiltered,
+            OnFilterError::Placeholder("?"),
+        ])?;
+
+        Ok(opts
+            .sql_dialect(dialect)
+            .escape(escape)
+            .color(color)
+            .on_filter_error(policy)
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Template;
+
+    #[test]
+    fn always_valid() {
+        let opts = Opts::default()
+            .optional_keys()
+            .duplicate_keys()
+            .empty_template();
+
+        let data = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        let mut u = Unstructured::new(&data);
+        while !u.is_empty() {
+            let source = TemplateSource::arbitrary(&mut u).unwrap();
+            let template = Template::parse(&source, opts.clone()).unwrap();
+            assert_eq!(template.to_string(), source.as_str());
+
+            let _ = Opts::arbitrary(&mut u).unwrap();
+        }
+    }
+}
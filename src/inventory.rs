@@ -0,0 +1,122 @@
+//! Key inventories across many template strings
+use crate::{BlockKind, Opts, Result, Template};
+use std::ops::Range;
+
+/// A key found by [`extract_keys_multi`](./fn.extract_keys_multi.html), with everywhere it was found
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtractedKey<'a> {
+    name: &'a str,
+    sources: Vec<KeySource>,
+}
+
+impl<'a> ExtractedKey<'a> {
+    /// The name of the key. Anonymous keys (`${}`) have an empty name
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Where the key was found, in input order
+    pub fn sources(&self) -> &[KeySource] {
+        &self.sources
+    }
+}
+
+/// Where an [`ExtractedKey`](./struct.ExtractedKey.html) was found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySource {
+    input: usize,
+    span: Range<usize>,
+}
+
+impl KeySource {
+    /// The index of the input the key was found in
+    pub fn input(&self) -> usize {
+        self.input
+    }
+
+    /// The byte range of the `${key}` marker in that input
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+}
+
+/// Find all the *keys* across many input strings
+///
+/// Each distinct key is returned once, in order of first appearance, along with every place it was found.
+/// This is useful for building translation catalogs, or auditing which variables an application's templates use.
+///
+/// This fails on the first input that [`Template::find_keys`](./struct.Template.html#method.find_keys) would fail on.
+/// The error's span refers to that input.
+///
+/// ```
+/// let keys = markings::extract_keys_multi(vec!["hello ${name}", "${count} items for ${name}"]).unwrap();
+/// let names = keys.iter().map(|key| key.name()).collect::<Vec<_>>();
+/// assert_eq!(names, vec!["name", "count"]);
+///
+/// let sources = keys[0].sources().iter().map(|s| (s.input(), s.span())).collect::<Vec<_>>();
+/// assert_eq!(sources, vec![(0, 6..13), (1, 19..26)]);
+/// ```
+pub fn extract_keys_multi<'a>(
+    inputs: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<ExtractedKey<'a>>> {
+    let mut keys: Vec<ExtractedKey<'a>> = vec![];
+    for (index, input) in inputs.into_iter().enumerate() {
+        for (span, key) in Template::find_markers(input.as_bytes(), &Opts::default())? {
+            // blocks are found by the keys they use
+            let name = match BlockKind::split(&input[key]) {
+                (Some(..), "") => continue,
+                (Some(..), name) => name,
+                // without any filters
+                (None, name) => name.split('|').next().unwrap_or_default(),
+            };
+            let source = KeySource { input: index, span };
+            match keys.iter_mut().find(|key| key.name == name) {
+                Some(key) => key.sources.push(source),
+                None => keys.push(ExtractedKey {
+                    name,
+                    sources: vec![source],
+                }),
+            }
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn extract_keys_multi() {
+        let inputs = ["${a} ${b}", "no keys", "${b}${a}${a|bold}${?b}${/}"];
+        let keys = super::extract_keys_multi(inputs.iter().copied()).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].name(), "a");
+        assert_eq!(
+            keys[0].sources(),
+            &[
+                KeySource {
+                    input: 0,
+                    span: 0..4
+                },
+                KeySource {
+                    input: 2,
+                    span: 4..8
+                },
+                KeySource {
+                    input: 2,
+                    span: 8..17
+                },
+            ]
+        );
+        assert_eq!(keys[1].name(), "b");
+        assert_eq!(keys[1].sources().len(), 3);
+
+        let err = super::extract_keys_multi(vec!["${a}", "${b"]).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MismatchedBraces { open: 1, close: 0 }
+        );
+    }
+}
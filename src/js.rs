@@ -0,0 +1,60 @@
+use crate::Args;
+use js_sys::{Array, Object, JSON};
+use wasm_bindgen::{JsCast as _, JsValue};
+
+impl Args<'static> {
+    /// Build args from a JavaScript object
+    ///
+    /// This follows [`Args::from_json`](#method.from_json):
+    /// * strings are used as is
+    /// * numbers and booleans are formatted like they are in JavaScript
+    /// * nested objects are flattened into dotted keys, e.g. `user.name`
+    /// * arrays are formatted as JSON
+    /// * `null` and `undefined` values are skipped
+    ///
+    /// Only the object's own enumerable string keys are used
+    pub fn from_js_object(object: &Object) -> Self {
+        let mut args = Args::new();
+        flatten(&mut args, None, object);
+        args
+    }
+}
+
+fn flatten(args: &mut Args<'static>, prefix: Option<&str>, object: &Object) {
+    for entry in Object::entries(object).iter() {
+        let entry = Array::from(&entry);
+        let key = match entry.get(0).as_string() {
+            Some(key) => key,
+            None => continue,
+        };
+        let key = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key,
+        };
+
+        let value = entry.get(1);
+        let val = if value.is_null() || value.is_undefined() {
+            continue;
+        } else if let Some(val) = value.as_string() {
+            val
+        } else if value.as_bool().is_some() || value.as_f64().is_some() || value.is_bigint() {
+            // formatted with their `toString`, like JavaScript would
+            value.unchecked_ref::<Object>().to_string().into()
+        } else if Array::is_array(&value) {
+            stringify(&value)
+        } else if value.is_object() {
+            flatten(args, Some(&key), value.unchecked_ref());
+            continue;
+        } else {
+            stringify(&value)
+        };
+        args.values.mapping.insert(crate::ArgKey::from(key), val);
+    }
+}
+
+fn stringify(value: &JsValue) -> String {
+    JSON::stringify(value)
+        .ok()
+        .and_then(|json| json.as_string())
+        .unwrap_or_default()
+}
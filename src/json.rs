@@ -0,0 +1,81 @@
+use crate::Args;
+use serde_json::Value;
+
+impl Args<'static> {
+    /// Build args from a JSON object
+    ///
+    /// * strings are used as is
+    /// * numbers and booleans are formatted with `Display`
+    /// * nested objects are flattened into dotted keys, e.g. `user.name`
+    /// * arrays are formatted as JSON
+    /// * `null` values are skipped
+    ///
+    /// Returns `None` if `value` isn't an object
+    ///
+    /// ```
+    /// # use markings::{Args, Template, Opts};
+    /// let value = serde_json::json!({
+    ///     "user": { "name": "bob", "id": 42 },
+    ///     "admin": false,
+    /// });
+    /// let args = Args::from_json(&value).unwrap();
+    ///
+    /// let template = Template::parse("${user.name} (${user.id}) ${admin}", Opts::default()).unwrap();
+    /// assert_eq!(template.apply(&args).unwrap(), "bob (42) false");
+    /// ```
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let mut args = Args::new();
+        flatten(&mut args, None, value.as_object()?);
+        Some(args)
+    }
+}
+
+fn flatten(args: &mut Args<'static>, prefix: Option<&str>, map: &serde_json::Map<String, Value>) {
+    for (key, value) in map {
+        let key = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key.clone(),
+        };
+        let val = match value {
+            Value::Null => continue,
+            Value::String(s) => s.clone(),
+            Value::Object(map) => {
+                flatten(args, Some(&key), map);
+                continue;
+            }
+            value => value.to_string(),
+        };
+        args.values.mapping.insert(crate::ArgKey::from(key), val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn from_json() {
+        let value = json!({
+            "str": "hello",
+            "num": 1.5,
+            "null": null,
+            "list": [1, "two"],
+            "a": { "b": { "c": true } },
+        });
+        let args = Args::from_json(&value).unwrap();
+        let mut v = args.into_iter().collect::<Vec<_>>();
+        v.sort();
+        assert_eq!(
+            v,
+            vec![
+                ("a.b.c".into(), "true".to_string()),
+                ("list".into(), r#"[1,"two"]"#.to_string()),
+                ("num".into(), "1.5".to_string()),
+                ("str".into(), "hello".to_string()),
+            ]
+        );
+
+        assert!(Args::from_json(&json!([1, 2, 3])).is_none());
+    }
+}
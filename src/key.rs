@@ -0,0 +1,146 @@
+use std::borrow::Cow;
+
+// owned keys are stored inline when they are short, with the `compact_str` feature
+#[cfg(feature = "compact_str")]
+type Owned = compact_str::CompactString;
+#[cfg(not(feature = "compact_str"))]
+type Owned = String;
+
+/// The key of an argument in [`Args`](./struct.Args.html)
+///
+/// Borrowed keys are never copied. With the `compact_str` feature, owned keys of up to 24 bytes
+/// are stored without allocating.
+///
+/// This dereferences to a `str`
+#[derive(Clone)]
+pub struct ArgKey<'k>(Repr<'k>);
+
+#[derive(Clone)]
+enum Repr<'k> {
+    Borrowed(&'k str),
+    Owned(Owned),
+}
+
+impl<'k> ArgKey<'k> {
+    /// The key as a string
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Borrowed(key) => key,
+            Repr::Owned(key) => key,
+        }
+    }
+
+    /// Convert the key into a `Cow`, only allocating if it is stored inline
+    // the conversion is only needed with the `compact_str` feature
+    #[allow(clippy::useless_conversion)]
+    pub fn into_cow(self) -> Cow<'k, str> {
+        match self.0 {
+            Repr::Borrowed(key) => Cow::Borrowed(key),
+            Repr::Owned(key) => Cow::Owned(key.into()),
+        }
+    }
+}
+
+impl<'k> From<&'k str> for ArgKey<'k> {
+    fn from(key: &'k str) -> Self {
+        Self(Repr::Borrowed(key))
+    }
+}
+
+impl<'k> From<String> for ArgKey<'k> {
+    #[allow(clippy::useless_conversion)]
+    fn from(key: String) -> Self {
+        Self(Repr::Owned(key.into()))
+    }
+}
+
+impl<'k> From<Cow<'k, str>> for ArgKey<'k> {
+    fn from(key: Cow<'k, str>) -> Self {
+        match key {
+            Cow::Borrowed(key) => key.into(),
+            Cow::Owned(key) => key.into(),
+        }
+    }
+}
+
+impl<'k> std::ops::Deref for ArgKey<'k> {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl<'k> std::borrow::Borrow<str> for ArgKey<'k> {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'k> AsRef<str> for ArgKey<'k> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// keys compare by their text, however they are stored
+impl<'k> PartialEq for ArgKey<'k> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<'k> Eq for ArgKey<'k> {}
+
+impl<'k> PartialOrd for ArgKey<'k> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'k> Ord for ArgKey<'k> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl<'k> std::hash::Hash for ArgKey<'k> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl<'k> std::fmt::Debug for ArgKey<'k> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl<'k> std::fmt::Display for ArgKey<'k> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, Opts, Template};
+
+    #[test]
+    fn owned_and_borrowed() {
+        let owned = ArgKey::from("name".to_string());
+        let borrowed = ArgKey::from("name");
+        assert_eq!(owned, borrowed);
+        assert_eq!(owned.clone().into_cow(), borrowed.clone().into_cow());
+        assert_eq!(&*owned, "name");
+
+        let args = Args::new().with("a".to_string(), 1).with("b", 2);
+        let template = Template::parse("${a}${b}", Opts::default()).unwrap();
+        assert_eq!(template.apply(&args).unwrap(), "12");
+        let keys = args.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![Cow::<str>::Owned("a".into()), Cow::Borrowed("b")]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{Args, ErrorKind, Result};
+use std::borrow::Cow;
+
+impl<'k> Args<'k> {
+    /// Parse args from `key=value` strings, like the `--set` flags of a command line tool
+    ///
+    /// The key is everything before the first `=`, and can't be empty. The value is everything after it, as it
+    /// is, unless the whole value is quoted:
+    /// - in `'single quotes'` the value is taken as it is
+    /// - in `"double quotes"` a `\` escapes a `"` or `\`, or is a `\n`, `\r` or `\t`
+    ///
+    /// A key that is given more than once is like calling [`Args::with`](#method.with) again. A string that
+    /// can't be parsed is an `InvalidArg` error, with the offset in the string where it went wrong
+    /// ```
+    /// # use markings::{Args, ErrorKind, Template, Opts};
+    /// let args = Args::parse_kv_list(&["name=bob", r#"greeting="hello,\tworld""#, "path='C:\\tmp'"]).unwrap();
+    ///
+    /// let template = Template::parse("${greeting} ${name} ${path}", Opts::default()).unwrap();
+    /// assert_eq!(template.apply(&args).unwrap(), "hello,\tworld bob C:\\tmp");
+    ///
+    /// let err = Args::parse_kv_list(&["name"]).unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::InvalidArg { arg: "name".into(), pos: 4 });
+    /// ```
+    pub fn parse_kv_list<I>(list: I) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        list.into_iter().try_fold(Self::new(), |args, arg| {
+            let arg = arg.as_ref();
+            let (key, val) = parse_kv(arg).map_err(|pos| ErrorKind::InvalidArg {
+                arg: arg.to_string(),
+                pos,
+            })?;
+            Ok(args.with(key.to_string(), val))
+        })
+    }
+}
+
+// splits a `key=value` string, or returns the offset where it can't be parsed
+fn parse_kv(arg: &str) -> std::result::Result<(&str, Cow<'_, str>), usize> {
+    let eq = arg.find('=').ok_or(arg.len())?;
+    if eq == 0 {
+        return Err(0);
+    }
+    let (key, val, start) = (&arg[..eq], &arg[eq + 1..], eq + 1);
+
+    let quote = match val.chars().next() {
+        Some(quote) if quote == '"' || quote == '\'' => quote,
+        _ => return Ok((key, val.into())),
+    };
+
+    let mut output = String::with_capacity(val.len());
+    let mut chars = val.char_indices().skip(1);
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, ch @ '"')) | Some((_, ch @ '\\')) => output.push(ch),
+                Some((_, 'n')) => output.push('\n'),
+                Some((_, 'r')) => output.push('\r'),
+                Some((_, 't')) => output.push('\t'),
+                _ => return Err(start + i),
+            },
+            ch if ch == quote => {
+                return match i + 1 == val.len() {
+                    true => Ok((key, output.into())),
+                    false => Err(start + i + 1),
+                };
+            }
+            ch => output.push(ch),
+        }
+    }
+    // the quote was never closed
+    Err(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_kv_list() {
+        let args = Args::parse_kv_list(vec![
+            "a=1",
+            "b=x=y",
+            "c=",
+            "d='x y'",
+            r#"e="say \"hi\"\n""#,
+            r#"f='a\b'"#,
+            "a=2",
+        ])
+        .unwrap();
+        let args = args
+            .iter()
+            .map(|(k, v)| (k, v.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            vec![
+                ("a", "2"),
+                ("b", "x=y"),
+                ("c", ""),
+                ("d", "x y"),
+                ("e", "say \"hi\"\n"),
+                ("f", "a\\b"),
+            ]
+        );
+
+        let invalid = |arg: &str| match Args::parse_kv_list(&[arg]).unwrap_err().kind() {
+            ErrorKind::InvalidArg { pos, .. } => *pos,
+            kind => panic!("unexpected error: {:?}", kind),
+        };
+        assert_eq!(invalid("abc"), 3);
+        assert_eq!(invalid("=abc"), 0);
+        assert_eq!(invalid(r#"a="abc"#), 2);
+        assert_eq!(invalid(r#"a="abc"d"#), 7);
+        assert_eq!(invalid(r#"a="\x""#), 3);
+        assert_eq!(invalid(r#"a="\"#), 3);
+    }
+}
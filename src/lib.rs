@@ -10,7 +10,7 @@
 //! let input = "hello ${name}, an answer: ${greeting}.";
 //!
 //! // parse a template with the default options
-//! // templates are clonable, they are 'consumed' on application.
+//! // templates are clonable, and can be applied any number of times
 //! let template = Template::parse(&input, Opts::default()).unwrap();
 //!
 //! // construct some replacement args, this is reusable
@@ -21,16 +21,229 @@
 //!     .with("name", &"test-user")
 //!     .with("greeting", &false);
 //!
-//! // apply the pre-computed args to the template, consuming the template
+//! // apply the pre-computed args to the template
 //! let output = template.apply(&args).unwrap();
 //! assert_eq!(output, "hello test-user, an answer: false.");
 //! ```
+//!
+//! # Reserved characters
+//! Some characters in a marker are syntax rather than part of the key, so keys can't use them:
+//! - a marker starting with `?`, `/`, `@`, `:?`, `:else` or `block ` is a block, like `${?name}...${/name}`
+//! - `|` starts a filter, like `${name|capitalize}`
+//! - `??` starts a fallback, like `${name ?? "guest"}`
+//! - `!` starts an annotation, like `${amount!number}`
+//! - with [`Opts::expressions`](./struct.Opts.html#method.expressions), `+ - * / % ( )` make an expression
+//!
+//! Earlier versions took these as part of the key, so `${a|b}` was the key `a|b`
+//! ```
+//! # use markings::{Args, Template, Opts};
+//! let template = Template::parse("${name|capitalize}", Opts::default()).unwrap();
+//! assert_eq!(template.apply(&Args::new().with("name", "bob")).unwrap(), "Bob");
+//! ```
+
+#[cfg(feature = "miette")]
+mod diagnostic;
+
+#[cfg(feature = "json")]
+mod json;
+
+#[cfg(feature = "serde")]
+mod serialize;
+
+#[cfg(feature = "wasm")]
+mod js;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "python")]
+pub mod python;
+
+#[cfg(feature = "testutil")]
+pub mod testutil;
+
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+#[cfg(feature = "arbitrary")]
+pub use fuzz::TemplateSource;
+
+mod bytes;
+pub use bytes::{ByteArgs, ByteTemplate};
+
+mod compile;
+pub use compile::CompiledTemplate;
+
+#[cfg(any(feature = "toml", feature = "serde_yaml"))]
+mod config;
+#[cfg(feature = "toml")]
+pub use config::interpolate_toml;
+#[cfg(feature = "serde_yaml")]
+pub use config::interpolate_yaml;
+
+mod convert;
+
+mod diff;
+pub use diff::{KeyChange, RenderDiff};
+
+mod error;
+pub use error::{ApplyError, ApplyErrorKind, ParseError, ParseErrorKind};
+
+mod expr;
+use expr::Expr;
+
+mod extract;
+
+mod filters;
+pub use filters::{
+    ColorChoice, Escape, FilterArg, FilterError, FilterFn, Filters, OnFilterError, SqlDialect,
+};
+
+mod key;
+pub use key::ArgKey;
+
+mod kv;
+
+mod inventory;
+pub use inventory::{extract_keys_multi, ExtractedKey, KeySource};
+
+mod lint;
+pub use lint::{Lint, LintKind, Severity};
+
+mod os;
+pub use os::OsArgs;
+
+mod plan;
+pub use plan::ApplyPlan;
+
+mod renderer;
+pub use renderer::{Renderer, RendererPool};
+
+mod sandbox;
+pub use sandbox::Sandbox;
+
+mod sink;
+pub use sink::{FmtSink, IoSink, Sink};
+
+mod schema;
+pub use schema::{Schema, ValueKind, Violation};
+
+mod session;
+pub use session::RenderSession;
 
-use std::collections::HashMap;
+mod set;
+pub use set::{SetStats, TemplateSet};
+
+mod sourcemap;
+pub use sourcemap::{Mapping, SourceMap};
+
+#[cfg(feature = "tracing")]
+mod trace;
+
+mod tree;
+pub use tree::{render_tree, TreeRenderer};
+
+mod visit;
+pub use visit::TemplateVisitor;
+
+#[cfg(feature = "async")]
+mod resolver;
+#[cfg(feature = "async")]
+pub use resolver::AsyncResolver;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+
+/// A `Result` with this crate's [`Error`](./struct.Error.html)
+pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// An error produced by this crate
+///
+/// Use [`Error::kind`](./struct.Error.html#method.kind) to find out what went wrong
+///
+/// [`ParseError`](./struct.ParseError.html) and [`ApplyError`](./struct.ApplyError.html) are the errors of just parsing or
+/// just applying a template, and convert into this
 #[derive(Debug)]
-pub enum Error {
+pub struct Error {
+    kind: ErrorKind,
+    span: Option<Range<usize>>,
+    source: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
+}
+
+impl Error {
+    /// The kind of error this is
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// The byte range in the template source this error refers to, if any
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    /// The stable code of the error, see [`ErrorKind::code`](./enum.ErrorKind.html#method.code)
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    fn with_span(mut self, span: Range<usize>) -> Self {
+        self.span.replace(span);
+        self
+    }
+
+    // an `Io` error, for a failed write
+    fn io(err: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self {
+            kind: ErrorKind::Io,
+            span: None,
+            source: Some(Box::new(err)),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|err| &**err as _)
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        use ErrorKind::*;
+        let span = match kind {
+            ExpectedClosing { head } => Some(head..head + 2),
+            ExpectedOpening { tail } => Some(tail..tail + 1),
+            NestedTemplate { pos } | UnsupportedSyntax { pos } => Some(pos..pos + 1),
+            InvalidEdit { pos } => Some(pos..pos),
+            ForbiddenKey { pos, .. } => Some(pos..pos + 1),
+            _ => None,
+        };
+        Self {
+            kind,
+            span,
+            source: None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::io(err)
+    }
+}
+
+/// The kind of an [`Error`](./struct.Error.html)
+///
+/// More kinds can be added in later versions, so matching on it needs a wildcard arm
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
     /// Mismatched braces were found
     ///
     /// `open` count and `closed` count
@@ -59,11 +272,144 @@ pub enum Error {
 
     /// Optional keys were found, but not configured in [`Opts`](./struct.Opts.html)
     OptionalKeys,
+
+    /// The output was larger than the limit configured in [`Opts`](./struct.Opts.html)
+    ///
+    /// `limit` is the maximum length, in bytes
+    OutputTooLarge { limit: usize },
+
+    /// The template was longer than the limit configured in [`Opts`](./struct.Opts.html)
+    ///
+    /// `limit` is the maximum length, in bytes
+    TemplateTooLong { limit: usize },
+
+    /// The template had more keys than the limit configured in [`Opts`](./struct.Opts.html)
+    TooManyKeys { limit: usize },
+
+    /// A key was longer than the limit configured in [`Opts`](./struct.Opts.html)
+    ///
+    /// `limit` is the maximum length, in bytes
+    KeyTooLong { limit: usize },
+
+    /// Syntax that isn't supported was found, e.g. while converting from another template syntax
+    ///
+    /// `pos` is where the syntax begins
+    UnsupportedSyntax { pos: usize },
+
+    /// The wrong number of values were given to a [`CompiledTemplate`](./struct.CompiledTemplate.html)
+    WrongValueCount { expected: usize, found: usize },
+
+    /// An argument replaced a different number of keys than configured in [`Opts`](./struct.Opts.html)
+    ArgUseCount {
+        key: String,
+        expected: usize,
+        found: usize,
+    },
+
+    /// A block was opened, but never closed
+    ///
+    /// `pos` is where the block begins
+    UnclosedBlock { pos: usize },
+
+    /// A block was closed, but wasn't open. Or `${/key}` closed a block for a different key
+    ///
+    /// `pos` is where the closing marker begins
+    UnmatchedBlockEnd { pos: usize },
+
+    /// A `${:?key}` or `${:else}` was found outside of a block, or after the `${:else}` of its block
+    ///
+    /// `pos` is where the marker begins
+    MisplacedElse { pos: usize },
+
+    /// A template wasn't found in a [`TemplateSet`](./struct.TemplateSet.html)
+    UnknownTemplate { name: String },
+
+    /// A key used a filter that doesn't exist
+    UnknownFilter { name: String },
+
+    /// A key used a filter with the wrong arguments, or arguments that can't be parsed
+    InvalidFilter { filter: String },
+
+    /// A custom filter failed while rendering the value of `key`
+    ///
+    /// The [`FilterError`](./struct.FilterError.html) is available as the error's `source`
+    Filter { key: String, filter: String },
+
+    /// A string couldn't have been rendered by the template it was matched against
+    NoMatch,
+
+    /// An I/O error occurred while writing a template
+    ///
+    /// The underlying error is available as the error's `source`
+    Io,
+
+    /// A template of this version couldn't be migrated to the version of a [`TemplateSet`](./struct.TemplateSet.html)
+    ///
+    /// It is newer than the set, or a migration from `version` is missing
+    UnsupportedVersion { version: u32 },
+
+    /// A preset of options wasn't found in a [`TemplateSet`](./struct.TemplateSet.html)
+    UnknownPreset { name: String },
+
+    /// Keys were rendered without a value, but optional keys weren't configured in [`Opts`](./struct.Opts.html)
+    ///
+    /// `keys` are the keys without a value, sorted
+    MissingKeys { keys: Vec<String> },
+
+    /// An edit of a template didn't fit its parts, e.g. it was inside of a key
+    ///
+    /// `pos` is where the edit begins
+    InvalidEdit { pos: usize },
+
+    /// The value of a key didn't match its annotation, like `${amount!number}`
+    ///
+    /// This is only checked with [`Opts::validate_key_specs`](./struct.Opts.html#method.validate_key_specs)
+    InvalidValue { key: String, spec: String },
+
+    /// Args didn't match a [`Schema`](./struct.Schema.html)
+    ///
+    /// `violations` are every value that didn't match, sorted by key
+    SchemaViolations { violations: Vec<Violation> },
+
+    /// An expression, like `${count / 0}`, couldn't be evaluated, because it divided by zero or overflowed
+    ///
+    /// See [`Opts::expressions`](./struct.Opts.html#method.expressions)
+    Arithmetic { expr: String },
+
+    /// A filter isn't one of the filters allowed by [`Opts::allowed_filters`](./struct.Opts.html#method.allowed_filters)
+    ForbiddenFilter { name: String },
+
+    /// A key isn't one of the keys allowed by [`Opts::allowed_keys`](./struct.Opts.html#method.allowed_keys)
+    ///
+    /// `pos` is where the marker using it begins
+    ForbiddenKey { key: String, pos: usize },
+
+    /// Something was used that the [`Sandbox`](./struct.Sandbox.html) of the options doesn't allow, like `extends`
+    Sandboxed { feature: String },
+
+    /// An argument was given more than once, with [`DuplicateArgs::Error`](./enum.DuplicateArgs.html#variant.Error)
+    /// or [`Args::try_with`](./struct.Args.html#method.try_with)
+    DuplicateArg { key: String },
+
+    /// A `key=value` string couldn't be parsed by [`Args::parse_kv_list`](./struct.Args.html#method.parse_kv_list)
+    ///
+    /// `pos` is the offset in `arg` where it went wrong
+    InvalidArg { arg: String, pos: usize },
+
+    /// Templates in a [`TemplateSet`](./struct.TemplateSet.html) extend each other in a cycle
+    ///
+    /// `names` are the templates in the cycle, in the order they extend each other, starting and ending with the same one
+    ExtendsCycle { names: Vec<String> },
+
+    /// A key was annotated with a kind of value that can't be checked, like `${amount!money}`
+    ///
+    /// This is only checked with [`Opts::validate_key_specs`](./struct.Opts.html#method.validate_key_specs)
+    UnknownSpec { spec: String },
 }
 
-impl std::fmt::Display for Error {
+impl std::fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use Error::*;
+        use ErrorKind::*;
         match self {
             MismatchedBraces { open, close } => write!(
                 f,
@@ -76,50 +422,448 @@ impl std::fmt::Display for Error {
             DuplicateKeys => f.write_str("duplicate keys were found"),
             EmptyTemplate => f.write_str("empty template was found"),
             OptionalKeys => f.write_str("optional keys were found"),
+            OutputTooLarge { limit } => write!(f, "output exceeded the limit of {} bytes", limit),
+            TemplateTooLong { limit } => {
+                write!(f, "template exceeded the limit of {} bytes", limit)
+            }
+            TooManyKeys { limit } => write!(f, "template exceeded the limit of {} keys", limit),
+            KeyTooLong { limit } => write!(f, "key exceeded the limit of {} bytes", limit),
+            UnsupportedSyntax { pos } => write!(f, "unsupported syntax at offset: {}", pos),
+            WrongValueCount { expected, found } => {
+                write!(f, "expected {} values, but found {}", expected, found)
+            }
+            ArgUseCount {
+                key,
+                expected,
+                found,
+            } => write!(
+                f,
+                "argument '{}' was expected to be used {} times, but was used {} times",
+                key, expected, found
+            ),
+            UnclosedBlock { pos } => write!(f, "block at offset {} was never closed", pos),
+            UnmatchedBlockEnd { pos } => write!(f, "unmatched block end at offset: {}", pos),
+            MisplacedElse { pos } => write!(f, "misplaced else branch at offset: {}", pos),
+            UnknownTemplate { name } => write!(f, "unknown template: '{}'", name),
+            UnknownFilter { name } => write!(f, "unknown filter: '{}'", name),
+            InvalidFilter { filter } => write!(f, "invalid filter arguments: '{}'", filter),
+            Filter { key, filter } => {
+                write!(f, "filter '{}' failed for key '{}'", filter, key)
+            }
+            NoMatch => f.write_str("the string doesn't match the template"),
+            Io => f.write_str("an i/o error occurred"),
+            UnsupportedVersion { version } => {
+                write!(f, "can't migrate a template from version {}", version)
+            }
+            UnknownPreset { name } => write!(f, "unknown preset: '{}'", name),
+            MissingKeys { keys } => write!(f, "missing values for keys: {}", keys.join(", ")),
+            InvalidEdit { pos } => write!(f, "invalid edit at offset: {}", pos),
+            InvalidValue { key, spec } => {
+                write!(f, "the value of key '{}' isn't a valid {}", key, spec)
+            }
+            SchemaViolations { violations } => {
+                f.write_str("the args didn't match the schema")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    let sep = if i == 0 { ": " } else { ", " };
+                    write!(f, "{}{}", sep, violation)?;
+                }
+                Ok(())
+            }
+            Arithmetic { expr } => write!(f, "couldn't evaluate the expression '{}'", expr),
+            ForbiddenFilter { name } => write!(f, "filter isn't allowed: '{}'", name),
+            ForbiddenKey { key, pos } => {
+                write!(f, "key '{}' at offset {} isn't allowed", key, pos)
+            }
+            Sandboxed { feature } => write!(f, "'{}' isn't allowed in the sandbox", feature),
+            DuplicateArg { key } => write!(f, "arg was given more than once: '{}'", key),
+            InvalidArg { arg, pos } => write!(f, "invalid arg '{}' at offset {}", arg, pos),
+            ExtendsCycle { names } => {
+                write!(f, "templates extend each other: {}", names.join(" -> "))
+            }
+            UnknownSpec { spec } => write!(f, "unknown annotation: '{}'", spec),
+        }
+    }
+}
+
+impl ErrorKind {
+    /// The stable code of this kind of error, like `M0005` for `DuplicateKeys`
+    ///
+    /// Codes never change, and are never reused, so they can be used to look up help text for an error.
+    /// They are the [`number`](#method.number) of the kind, which is also the code of the `ffi` feature
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let err = Template::parse("${a} ${a}", Opts::default()).unwrap_err();
+    /// assert_eq!(err.code(), "M0005");
+    /// ```
+    pub fn code(&self) -> &'static str {
+        let code = &CODES[usize::from(self.number()) - 1];
+        std::str::from_utf8(code).expect("codes are ascii")
+    }
+
+    /// The number of the stable [`code`](#method.code) of this kind of error, like `5` for `DuplicateKeys`
+    ///
+    /// Numbers are assigned in the order the kinds were added, starting at 1
+    /// ```
+    /// # use markings::ErrorKind;
+    /// assert_eq!(ErrorKind::DuplicateKeys.number(), 5);
+    /// ```
+    pub fn number(&self) -> u16 {
+        use ErrorKind::*;
+        match self {
+            MismatchedBraces { .. } => 1,
+            ExpectedClosing { .. } => 2,
+            ExpectedOpening { .. } => 3,
+            NestedTemplate { .. } => 4,
+            DuplicateKeys => 5,
+            EmptyTemplate => 6,
+            OptionalKeys => 7,
+            OutputTooLarge { .. } => 8,
+            TemplateTooLong { .. } => 9,
+            TooManyKeys { .. } => 10,
+            KeyTooLong { .. } => 11,
+            UnsupportedSyntax { .. } => 12,
+            WrongValueCount { .. } => 13,
+            ArgUseCount { .. } => 14,
+            UnclosedBlock { .. } => 15,
+            UnmatchedBlockEnd { .. } => 16,
+            MisplacedElse { .. } => 17,
+            UnknownTemplate { .. } => 18,
+            UnknownFilter { .. } => 19,
+            InvalidFilter { .. } => 20,
+            Filter { .. } => 21,
+            NoMatch => 22,
+            Io => 23,
+            UnsupportedVersion { .. } => 24,
+            UnknownPreset { .. } => 25,
+            MissingKeys { .. } => 26,
+            InvalidEdit { .. } => 27,
+            InvalidValue { .. } => 28,
+            SchemaViolations { .. } => 29,
+            Arithmetic { .. } => 30,
+            ForbiddenFilter { .. } => 31,
+            ForbiddenKey { .. } => 32,
+            Sandboxed { .. } => 33,
+            DuplicateArg { .. } => 34,
+            InvalidArg { .. } => 35,
+            ExtendsCycle { .. } => 36,
+            UnknownSpec { .. } => 37,
         }
     }
 }
-impl std::error::Error for Error {}
+
+// the highest number of any kind of error
+const MAX_CODE: usize = 37;
+
+// the text of each code, like `M0005`, by its number starting at 1
+static CODES: [[u8; 5]; MAX_CODE] = {
+    let mut codes = [*b"M0000"; MAX_CODE];
+    let mut i = 0;
+    while i < MAX_CODE {
+        let mut n = i + 1;
+        let mut digit = 4;
+        while n > 0 {
+            codes[i][digit] = b'0' + (n % 10) as u8;
+            n /= 10;
+            digit -= 1;
+        }
+        i += 1;
+    }
+    codes
+};
+
+// with the `smallvec` feature, templates with a few keys are validated and rendered without
+// allocating for their keys and rendered pieces
+//
+// the segments of a template stay in a `Vec`, a `SmallVec` would make `Template` invariant over its lifetime
+#[cfg(feature = "smallvec")]
+type Keys<'a> = smallvec::SmallVec<[&'a str; 8]>;
+#[cfg(not(feature = "smallvec"))]
+type Keys<'a> = Vec<&'a str>;
+
+#[cfg(feature = "smallvec")]
+type Positions = smallvec::SmallVec<[usize; 8]>;
+#[cfg(not(feature = "smallvec"))]
+type Positions = Vec<usize>;
+
+#[cfg(feature = "smallvec")]
+type Pieces<'a> = smallvec::SmallVec<[(Cow<'a, str>, Option<&'a str>, Range<usize>); 16]>;
+#[cfg(not(feature = "smallvec"))]
+type Pieces<'a> = Vec<(Cow<'a, str>, Option<&'a str>, Range<usize>)>;
 
 #[derive(Debug, Clone)]
 struct State<'a> {
-    keys: Vec<&'a str>,
+    keys: Keys<'a>,
 }
 
 impl<'a> State<'a> {
-    fn new(keys: Vec<&'a str>) -> Self {
-        Self { keys }
+    fn new(keys: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            keys: keys.into_iter().collect(),
+        }
     }
 
     fn has_keys(&self) -> bool {
         !self.keys.is_empty()
     }
 
-    fn remove(&mut self, key: &str) -> Option<(&'a str, usize)> {
-        if self.keys.is_empty() {
-            return None;
+    // index of the first key that was already seen
+    fn duplicate(&self, opts: &Opts) -> Option<usize> {
+        if !opts.folds_keys() {
+            let mut set = std::collections::HashSet::new();
+            return self.keys.iter().position(|key| !set.insert(key));
         }
 
-        let mut out = None;
-        let mut i = 0;
-        while i != self.keys.len() {
-            if self.keys[i] == key {
-                let s = self.keys.remove(i);
-                let (_, count) = out.get_or_insert_with(|| (s, 0));
-                *count += 1;
-            } else {
-                i += 1;
+        self.keys
+            .iter()
+            .enumerate()
+            .position(|(i, left)| self.keys[..i].iter().any(|right| opts.key_eq(left, right)))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment<'a> {
+    /// Literal text between keys
+    Literal(Cow<'a, str>),
+    /// A key, the filters applied to its value, its annotation, what it falls back to, and the marker it was parsed from
+    ///
+    /// `chain` is the filters parsed with the options of the template, when it is validated
+    Key {
+        name: Cow<'a, str>,
+        filters: Vec<Cow<'a, str>>,
+        chain: filters::Chain,
+        spec: Option<Cow<'a, str>>,
+        fallbacks: Vec<Fallback<'a>>,
+        raw: Cow<'a, str>,
+    },
+    /// A block marker, like `${?key}` or `${/}`. `name` is empty for markers without a key
+    Block {
+        kind: BlockKind,
+        name: Cow<'a, str>,
+        raw: Cow<'a, str>,
+    },
+    /// An arithmetic expression, like `${count + 1}`, and the marker it was parsed from
+    Expr { expr: Expr, raw: Cow<'a, str> },
+}
+
+/// The kind of a block marker, see [`Template::parse`](./struct.Template.html#method.parse)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BlockKind {
+    /// `${?key}`, rendered if the key is truthy
+    If,
+    /// `${:?key}`, rendered if the key is truthy and no earlier branch was
+    ElseIf,
+    /// `${:else}`, rendered if no earlier branch was
+    Else,
+    /// `${@key}`, keys inside are looked up under `key.` first
+    With,
+    /// `${block name}`, a region that a [`TemplateSet`](./struct.TemplateSet.html) child can override
+    Region,
+    /// `${/}` or `${/key}`, closes the open block
+    End,
+}
+
+/// What a key falls back to after `??`, when it has no value, or an empty one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Fallback<'a> {
+    /// Another key
+    Key(Cow<'a, str>),
+    /// Quoted text, like `"guest"`, which is unquoted when it is used
+    Literal(Cow<'a, str>),
+}
+
+impl<'a> Fallback<'a> {
+    fn parse(text: &'a str) -> Self {
+        let text = text.trim();
+        match text.starts_with('"') {
+            true => Fallback::Literal(text.into()),
+            false => Fallback::Key(text.into()),
+        }
+    }
+
+    fn into_owned(self) -> Fallback<'static> {
+        match self {
+            Fallback::Key(key) => Fallback::Key(key.into_owned().into()),
+            Fallback::Literal(text) => Fallback::Literal(text.into_owned().into()),
+        }
+    }
+}
+
+impl<'a> Segment<'a> {
+    fn into_owned(self) -> Segment<'static> {
+        let owned = |text: Cow<'a, str>| Cow::Owned(text.into_owned());
+        match self {
+            Segment::Literal(literal) => Segment::Literal(owned(literal)),
+            Segment::Key {
+                name,
+                filters,
+                chain,
+                spec,
+                fallbacks,
+                raw,
+            } => Segment::Key {
+                name: owned(name),
+                filters: filters.into_iter().map(owned).collect(),
+                chain,
+                spec: spec.map(owned),
+                fallbacks: fallbacks.into_iter().map(Fallback::into_owned).collect(),
+                raw: owned(raw),
+            },
+            Segment::Block { kind, name, raw } => Segment::Block {
+                kind,
+                name: owned(name),
+                raw: owned(raw),
+            },
+            Segment::Expr { expr, raw } => Segment::Expr {
+                expr,
+                raw: owned(raw),
+            },
+        }
+    }
+
+    // classifies a marker as a key or a block
+    fn marker(name: &'a str, raw: &'a str, trim: bool) -> Self {
+        let name = if trim { name.trim() } else { name };
+        match BlockKind::split(name) {
+            (Some(kind), name) => Segment::Block {
+                kind,
+                name: name.into(),
+                raw: raw.into(),
+            },
+            (None, name) => {
+                // filters follow the key, like `${name|upper|bold}`
+                let mut parts = filters::split(name, "|").into_iter();
+                let name = parts.next().unwrap_or_default();
+                // the key can fall back to other keys or quoted text, like `${name ?? "guest"}`
+                let (name, fallbacks) = match &*filters::split(name, "??") {
+                    [name, fallbacks @ ..] if !fallbacks.is_empty() => (
+                        name.trim(),
+                        fallbacks.iter().map(|f| Fallback::parse(f)).collect(),
+                    ),
+                    _ => (name, vec![]),
+                };
+                // and the key can be annotated, like `${amount!number}`
+                let (name, spec) = match name.split_once('!') {
+                    Some((name, spec)) => (name, Some(spec.trim().into())),
+                    None => (name, None),
+                };
+                Segment::Key {
+                    name: if trim { name.trim_end() } else { name }.into(),
+                    filters: parts.map(|filter| filter.trim().into()).collect(),
+                    chain: filters::Chain::default(),
+                    spec,
+                    fallbacks,
+                    raw: raw.into(),
+                }
+            }
+        }
+    }
+}
+
+impl BlockKind {
+    // splits the text of a marker into the kind of block, if it is one, and its key
+    fn split(name: &str) -> (Option<Self>, &str) {
+        match name.as_bytes().first() {
+            Some(b'?') => (Some(BlockKind::If), &name[1..]),
+            _ if name == "/block" => (Some(BlockKind::End), ""),
+            Some(b'/') => (Some(BlockKind::End), &name[1..]),
+            Some(b'@') if name.len() > 1 => (Some(BlockKind::With), &name[1..]),
+            _ if name.starts_with(":?") => (Some(BlockKind::ElseIf), &name[2..]),
+            _ if name == ":else" => (Some(BlockKind::Else), ""),
+            _ => match name.strip_prefix("block ").map(str::trim) {
+                Some(region) if !region.is_empty() => (Some(BlockKind::Region), region),
+                _ => (None, name),
+            },
+        }
+    }
+
+    // whether this opens a block that has to be closed
+    fn opens(self) -> bool {
+        matches!(self, BlockKind::If | BlockKind::With | BlockKind::Region)
+    }
+
+    // whether the name of this block refers to a key
+    fn uses_key(self) -> bool {
+        self != BlockKind::Region
+    }
+}
+
+// segments compare by their literal text and key names, the spelling of a marker doesn't matter
+impl<'a> PartialEq for Segment<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Segment::Literal(left), Segment::Literal(right)) => left == right,
+            (
+                Segment::Key {
+                    name: left,
+                    filters: left_filters,
+                    spec: left_spec,
+                    fallbacks: left_fallbacks,
+                    ..
+                },
+                Segment::Key {
+                    name: right,
+                    filters: right_filters,
+                    spec: right_spec,
+                    fallbacks: right_fallbacks,
+                    ..
+                },
+            ) => {
+                left == right
+                    && left_filters == right_filters
+                    && left_spec == right_spec
+                    && left_fallbacks == right_fallbacks
             }
+            (
+                Segment::Block {
+                    kind: left_kind,
+                    name: left,
+                    ..
+                },
+                Segment::Block {
+                    kind: right_kind,
+                    name: right,
+                    ..
+                },
+            ) => left_kind == right_kind && left == right,
+            (Segment::Expr { expr: left, .. }, Segment::Expr { expr: right, .. }) => left == right,
+            _ => false,
         }
-        out
     }
+}
+
+impl<'a> Eq for Segment<'a> {}
 
-    fn has_duplicates(&self) -> bool {
-        let mut set = std::collections::HashSet::new();
-        self.keys.iter().any(|key| !set.insert(key))
+impl<'a> std::hash::Hash for Segment<'a> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Segment::Literal(literal) => literal.hash(state),
+            Segment::Key {
+                name,
+                filters,
+                spec,
+                fallbacks,
+                ..
+            } => {
+                name.hash(state);
+                filters.hash(state);
+                spec.hash(state);
+                fallbacks.hash(state)
+            }
+            Segment::Block { kind, name, .. } => {
+                kind.hash(state);
+                name.hash(state)
+            }
+            Segment::Expr { expr, .. } => expr.hash(state),
+        }
     }
 }
 
+const ANONYMOUS: &str = "${}";
+
+// how deeply markers can nest without `Opts::max_depth`
+const MAX_DEPTH: usize = 64;
+
 /// Templates allows for string replacement by **name**
 ///
 /// ```
@@ -134,7 +878,7 @@ impl<'a> State<'a> {
 ///     .with("world", &"world")
 ///     .with("end", &(0x21 as char));
 ///
-/// // apply the args to the template, consuming the template
+/// // apply the args to the template
 /// let template = template
 ///     .apply(&args)
 ///     .unwrap();
@@ -145,10 +889,22 @@ impl<'a> State<'a> {
 /// See [`Template::apply`](./fn.Template.apply.html) for applying arguments to this template.
 ///
 /// See [`Opts`](./struct.Opts.html) for a way to change the behavior of the parser
-#[derive(Clone, Debug)]
+///
+/// Templates are equal when their literal text, keys and options are equal. How a key was
+/// written isn't compared, so `${ name }` parsed with `trim_key_whitespace` is equal to `${name}`
+/// ```
+/// # use markings::{Template, Opts};
+/// # use std::collections::HashSet;
+/// let opts = Opts::default().trim_key_whitespace();
+/// let mut set = HashSet::new();
+/// set.insert(Template::parse("hello ${name}", opts.clone()).unwrap());
+/// set.insert(Template::parse("hello ${ name }", opts).unwrap());
+/// assert_eq!(set.len(), 1);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[must_use]
 pub struct Template<'a> {
-    data: String, // total string
-    state: State<'a>,
+    segments: Vec<Segment<'a>>,
     opts: Opts,
 }
 
@@ -157,177 +913,2598 @@ impl<'a> Template<'a> {
     ///
     /// The syntax is extremely basic: just `${key}`
     ///
+    /// An empty marker, `${}`, is an anonymous positional key. These are numbered in order of
+    /// appearance, like `{}` in `format!`
+    ///
+    /// Keys can be namespaced with dots, like `${user.name}`. See [`Args::namespace`](./struct.Args.html#method.namespace)
+    ///
     /// The *key* gets replaced by a *value* matching it during the [`Template::apply`](./struct.Template.html#method.apply) call
-    pub fn parse(input: &'a str, opts: Opts) -> Result<Self, Error> {
-        let state = State::new(Self::find_keys(input)?);
-        opts.validate(&state)?;
-        Ok(Self {
-            data: input.to_string(),
-            state,
-            opts,
-        })
-    }
-
-    /// Was this template empty?
-    pub fn is_empty(&self) -> bool {
-        self.opts.empty_template
-    }
-
-    /// Apply the arguments to the template
     ///
-    /// One can use the [`Args`](./struct.Args.html) builder to make this less tedious
-    pub fn apply<'k>(mut self, args: &Args<'k>) -> Result<String, Error> {
-        for (key, val) in &args.mapping {
-            let matches = self.state.remove(key);
-            match matches {
-                Some((match_, _)) => {
-                    let s = self.data.replace(&format!("${{{}}}", match_), &val);
-                    std::mem::replace(&mut self.data, s);
-                }
-                None if self.opts.optional_keys || self.is_empty() => continue,
-                _ => return Err(Error::OptionalKeys),
-            }
-        }
-
-        self.data.shrink_to_fit();
-        Ok(self.data)
+    /// Parts of a template can be rendered conditionally with a block: `${?key}` renders everything up to
+    /// the closing `${/}` (or `${/key}`) only if the value of `key` is *truthy*. See [`Truthiness`](./enum.Truthiness.html)
+    ///
+    /// A block can have more branches: `${:?other}` is rendered if `other` is truthy and no earlier branch was,
+    /// and `${:else}` is rendered if no other branch was
+    ///
+    /// Keys inside of a `${@key}` block are looked up under `key.` first, so the fields of a
+    /// [namespace](./struct.Args.html#method.namespace) can be used without repeating it
+    ///
+    /// A key can be followed by *filters* that change its value, like `${name|red|bold}`. They are applied in order.
+    /// These are available:
+    /// * colors, which use ANSI escape codes depending on [`Opts::color`](./struct.Opts.html#method.color):
+    ///   `bold`, `dim`, `italic`, `underline`, `black`, `red`, `green`, `yellow`, `blue`, `magenta`, `cyan` and `white`
+    /// * escapes, see [`Escape`](./enum.Escape.html): `md`, `sh`, `ps`, `sql_ident` and `sql_str`
+    /// * text, counted in characters: `truncate:n` keeps the first `n`, `pad_left:n` and `pad_right:n` pad with spaces
+    ///   up to `n`, `replace:"from":"to"` replaces every match, `capitalize` uppercases the first character, `title` the
+    ///   first character of each word, `slug` makes a lowercase slug like `hello-world`, and `reverse` reverses it.
+    ///   `n` can't be more than [`Opts::max_output_len`](./struct.Opts.html#method.max_output_len), or 1 MiB without it,
+    ///   and a `replace` that would make a longer value fails with `OutputTooLarge`
+    /// * with the `regex` feature, `re:"pattern":"replacement"` replaces every match of the regex.
+    ///   The replacement can refer to groups, like `$1`
+    ///
+    /// * custom filters, see [`Opts::filters`](./struct.Opts.html#method.filters)
+    ///
+    /// Filter arguments follow the name, separated by `:`. They can be quoted with `"`, and then use
+    /// `\"` and `\\` for a literal quote or backslash. Arguments can't contain braces.
+    ///
+    /// A key can be annotated with the kind of value it expects, like `${amount!number}`. See [`Template::key_specs`](#method.key_specs)
+    /// and [`Opts::validate_key_specs`](./struct.Opts.html#method.validate_key_specs)
+    ///
+    /// A key can *fall back* to other keys or quoted text when it has no value, or an empty one, like `${name ?? user ?? "guest"}`.
+    /// The first of them with a non-empty value is used, and filters are applied to it, like `${name ?? "guest"|bold}`
+    ///
+    /// With [`Opts::expressions`](./struct.Opts.html#method.expressions), markers can be arithmetic, like `${count + 1}`
+    ///
+    /// `${block name}` starts a region that can be overridden by templates extending this one in a
+    /// [`TemplateSet`](./struct.TemplateSet.html). It is closed by `${/block}`, and is rendered as is otherwise
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("${name}${?admin} (admin)${/}", Opts::default()).unwrap();
+    /// assert_eq!(template.apply(&Args::new().with("name", "bob").with("admin", true)).unwrap(), "bob (admin)");
+    /// assert_eq!(template.apply(&Args::new().with("name", "bob").with("admin", false)).unwrap(), "bob");
+    ///
+    /// let input = "${?none}no items${:?one}1 item${:else}${count} items${/}";
+    /// let template = Template::parse(input, Opts::default().optional_keys()).unwrap();
+    /// assert_eq!(template.apply(&Args::new().with("none", true)).unwrap(), "no items");
+    /// assert_eq!(template.apply(&Args::new().with("one", true)).unwrap(), "1 item");
+    /// assert_eq!(template.apply(&Args::new().with("count", 5)).unwrap(), "5 items");
+    ///
+    /// let template = Template::parse("${@user}Hello ${name} (${id})${/user}", Opts::default()).unwrap();
+    /// let user = Args::new().with("name", "bob").with("id", 42);
+    /// assert_eq!(template.apply(&Args::new().namespace("user", user)).unwrap(), "Hello bob (42)");
+    ///
+    /// let template = Template::parse(r#"Hello ${name ?? nick ?? "guest"}"#, Opts::default().optional_keys()).unwrap();
+    /// assert_eq!(template.apply(&Args::new().with("name", "bob")).unwrap(), "Hello bob");
+    /// assert_eq!(template.apply(&Args::new().with("name", "").with("nick", "b0b")).unwrap(), "Hello b0b");
+    /// assert_eq!(template.apply(&Args::new()).unwrap(), "Hello guest");
+    /// ```
+    ///
+    /// Parsing is lossless: the template [displays](#impl-Display-for-Template%3C'a%3E) as the exact input,
+    /// including the original spelling of each marker. Editing the template, e.g. with
+    /// [`Template::replace_key_name`](#method.replace_key_name), only changes the edited parts.
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let input = "hello ${user}, ${} new messages";
+    /// let template = Template::parse(input, Opts::default()).unwrap();
+    /// assert_eq!(template.to_string(), input);
+    ///
+    /// let template = template.replace_key_name("user", "name").unwrap();
+    /// assert_eq!(template.to_string(), "hello ${name}, ${} new messages");
+    /// ```
+    pub fn parse(input: &'a str, opts: Opts) -> Result<Self> {
+        #[cfg(feature = "tracing")]
+        return trace::parse(input, || Self::parse_segments(input, opts));
+        #[cfg(not(feature = "tracing"))]
+        Self::parse_segments(input, opts)
     }
 
-    /// Find all the *keys* in the input string, returning them in a Vec
-    ///
-    /// This is exposed as a convenient function for doing pre-parsing.
+    /// Parses a template that can start with a header of options, like `#! optional_keys, max_keys: 3`
     ///
-    /// This returns an error if there are:
-    /// * nested templates
-    /// * mismatched braces
+    /// The header is the first line. Its options are named like the setters of [`Opts`](./struct.Opts.html),
+    /// separated by commas, and are enabled on top of `opts`. Limits take a value after a `:`. These options
+    /// are available: `optional_keys`, `duplicate_keys`, `empty_template`, `case_insensitive_keys`, `bare_dollar_keys`,
+    /// `percent_keys`, `trim_key_whitespace`, `max_output_len`, `max_template_len`, `max_keys`, `max_key_len`, `max_depth` and `arg_uses`
     ///
+    /// Unknown options are an `UnsupportedSyntax` error. The template is parsed from the line after the header,
+    /// so it doesn't display the header, and offsets in its errors start after it
     /// ```
-    /// # use markings::Template;
-    /// let keys = Template::find_keys("${this} is a ${test} ${with some keys}").unwrap();
-    /// assert_eq!(keys, vec!["this", "test", "with some keys"]);
+    /// # use markings::{Template, Args, Opts};
+    /// let input = "#! optional_keys, duplicate_keys\n${name} and ${name}";
+    /// let template = Template::parse_with_frontmatter(input, Opts::default()).unwrap();
+    /// assert_eq!(template.to_string(), "${name} and ${name}");
+    ///
+    /// let args = Args::new().with("name", "bob").with("unused", true);
+    /// assert_eq!(template.apply(&args).unwrap(), "bob and bob");
     /// ```
-    pub fn find_keys(input: &str) -> Result<Vec<&str>, Error> {
-        let mut heads = vec![];
-        let mut tails = vec![];
+    pub fn parse_with_frontmatter(input: &'a str, mut opts: Opts) -> Result<Self> {
+        let header = match input.strip_prefix("#!") {
+            Some(header) => header,
+            None => return Self::parse(input, opts),
+        };
+        let (header, rest) = header.split_once('\n').unwrap_or((header, ""));
 
-        let mut last = None;
-        let mut iter = input.char_indices().peekable();
-        while let Some((pos, ch)) = iter.next() {
-            if ch == '$' && iter.peek().map(|&(_, d)| d == '{').unwrap_or_default() {
-                last.replace(pos);
-                heads.push(pos);
-                iter.next();
-            }
-            if ch == '{' && last.is_some() {
-                return Err(Error::NestedTemplate { pos });
+        let mut pos = 2;
+        for option in header.split(',') {
+            let start = pos + option.len() - option.trim_start().len();
+            pos += option.len() + 1;
+            let option = option.trim();
+            if option.is_empty() {
+                continue;
             }
+            let (name, value) = match option.split_once(':') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (option, None),
+            };
+            opts = opts
+                .with_option(name, value)
+                .ok_or(ErrorKind::UnsupportedSyntax { pos: start })?;
+        }
+        Self::parse(rest, opts)
+    }
+
+    fn parse_segments(input: &'a str, opts: Opts) -> Result<Self> {
+        if let Some(limit) = opts.max_template_len {
+            if input.len() > limit {
+                return Err(ErrorKind::TemplateTooLong { limit }.into());
+            }
+        }
+
+        let mut segments = vec![];
+        let mut last = 0;
+        for (marker, key) in Self::find_markers(input.as_bytes(), &opts)? {
+            if marker.start > last {
+                segments.push(Segment::Literal(input[last..marker.start].into()));
+            }
+            last = marker.end;
+            let name = &input[key];
+            if opts.expressions && Expr::is_expr(name) && BlockKind::split(name).0.is_none() {
+                let expr = Expr::parse(name, opts.depth_limit()).ok_or_else(|| {
+                    let err = ErrorKind::UnsupportedSyntax { pos: marker.start };
+                    Error::from(err).with_span(marker.clone())
+                })?;
+                let raw = input[marker].into();
+                segments.push(Segment::Expr { expr, raw });
+                continue;
+            }
+            segments.push(Segment::marker(
+                name,
+                &input[marker],
+                opts.trim_key_whitespace,
+            ));
+        }
+        if last < input.len() {
+            segments.push(Segment::Literal(input[last..].into()));
+        }
+
+        let mut template = Self { segments, opts };
+        template.renumber();
+        template.validate()?;
+        Ok(template)
+    }
+
+    // a template without any segments, which still needs to be validated
+    fn empty(opts: Opts) -> Self {
+        Self {
+            segments: vec![],
+            opts,
+        }
+    }
+
+    /// Copy the borrowed parts of the template, so it no longer borrows its input
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template: Template<'static> = {
+    ///     let input = String::from("hello ${name}");
+    ///     Template::parse(&input, Opts::default()).unwrap().into_owned()
+    /// };
+    /// assert_eq!(template.to_string(), "hello ${name}");
+    /// ```
+    pub fn into_owned(self) -> Template<'static> {
+        Template {
+            segments: self.segments.into_iter().map(Segment::into_owned).collect(),
+            opts: self.opts,
+        }
+    }
+
+    /// Was this template parsed with [`Opts::empty_template`](./struct.Opts.html#method.empty_template)?
+    ///
+    /// This doesn't look at the keys of the template, it is the same as [`Template::allows_empty`](#method.allows_empty).
+    /// Use [`Template::has_keys`](#method.has_keys) to find out whether the template has keys
+    pub fn is_empty(&self) -> bool {
+        self.allows_empty()
+    }
+
+    /// Was this template parsed with [`Opts::empty_template`](./struct.Opts.html#method.empty_template)?
+    ///
+    /// Such a template can have keys, it just isn't required to
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("hello ${name}", Opts::default().empty_template()).unwrap();
+    /// assert!(template.allows_empty());
+    /// assert!(template.has_keys());
+    /// ```
+    pub fn allows_empty(&self) -> bool {
+        self.opts.empty_template
+    }
+
+    /// Does the template have any keys?
+    ///
+    /// Blocks, like `${?key}`, aren't keys
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("${?admin}hello${/}", Opts::default().empty_template()).unwrap();
+    /// assert!(!template.has_keys());
+    /// ```
+    pub fn has_keys(&self) -> bool {
+        self.key_count() > 0
+    }
+
+    /// How many keys the template has, counting each use of a key
+    ///
+    /// With the default markers, this is the same as [`Template::count_keys`](#method.count_keys) of the template's
+    /// source. Blocks and expressions aren't keys
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("${a} ${b} ${a} ${}", Opts::default().duplicate_keys()).unwrap();
+    /// assert_eq!(template.key_count(), 4);
+    /// ```
+    pub fn key_count(&self) -> usize {
+        self.keys().count()
+    }
+
+    /// The annotated keys of the template, and their annotations, in order
+    ///
+    /// A key is annotated after a `!`, like `${amount!number}`. See [`Opts::validate_key_specs`](./struct.Opts.html#method.validate_key_specs)
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("${name} <${email!email}>: ${amount!number|bold}", Opts::default()).unwrap();
+    /// assert_eq!(template.key_specs().collect::<Vec<_>>(), vec![("email", "email"), ("amount", "number")]);
+    /// ```
+    pub fn key_specs(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Key {
+                name,
+                spec: Some(spec),
+                ..
+            } => Some((&**name, &**spec)),
+            _ => None,
+        })
+    }
+
+    /// The keys of the template, with the byte range of each marker in the source, in order
+    ///
+    /// Every use of a key has a range. Keys that are only used by blocks, expressions or fallbacks don't.
+    /// Errors from applying the template, like a filter failing, also have the range of the key they are about
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("hello ${name}, ${}!", Opts::default()).unwrap();
+    /// assert_eq!(template.key_spans(), vec![("name".to_string(), 6..13), ("0".to_string(), 15..18)]);
+    /// ```
+    pub fn key_spans(&self) -> Vec<(String, Range<usize>)> {
+        self.spans()
+            .filter_map(|(segment, span)| match segment {
+                Segment::Key { name, .. } => Some((name.to_string(), span)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Apply the arguments to the template
+    ///
+    /// One can use the [`Args`](./struct.Args.html) builder to make this less tedious
+    pub fn apply(&self, args: &Args<'_>) -> Result<String> {
+        self.render(args, |_, _, _| {})
+    }
+
+    /// Apply the arguments to the template, writing the output to `writer`
+    ///
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("hello ${name}", Opts::default()).unwrap();
+    /// let mut out = vec![];
+    /// template.render_to(&Args::new().with("name", "bob"), &mut out).unwrap();
+    /// assert_eq!(out, b"hello bob");
+    /// ```
+    ///
+    /// The output is written all at once. To write it as it is rendered, see [`Template::render_sink`](#method.render_sink),
+    /// and to reuse the buffer of the output between calls, see [`RendererPool::render_to`](./struct.RendererPool.html#method.render_to)
+    pub fn render_to(&self, args: &Args<'_>, mut writer: impl std::io::Write) -> Result<()> {
+        writer.write_all(self.apply(args)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Apply the arguments to the template, also capturing where each key was substituted
+    ///
+    /// The captures map each key that was replaced to the value used, and the byte ranges of
+    /// the output it was written to
+    ///
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("hello ${name}!", Opts::default()).unwrap();
+    /// let (output, captures) = template.apply_captured(&Args::new().with("name", "bob")).unwrap();
+    /// assert_eq!(output, "hello bob!");
+    ///
+    /// let capture = &captures["name"];
+    /// assert_eq!(capture.value(), "bob");
+    /// assert_eq!(capture.ranges(), &[6..9]);
+    /// ```
+    pub fn apply_captured(&self, args: &Args<'_>) -> Result<(String, HashMap<String, Capture>)> {
+        let mut captures = HashMap::<String, Capture>::new();
+        let output = self.render(args, |key, val, range| {
+            captures
+                .entry(key.to_string())
+                .or_insert_with(|| Capture {
+                    value: val.to_string(),
+                    ranges: vec![],
+                    secret: self
+                        .lookup_arg(args, key)
+                        .is_some_and(|(arg, _)| args.is_secret(arg)),
+                })
+                .ranges
+                .push(range)
+        })?;
+        Ok((output, captures))
+    }
+
+    /// Apply the arguments to the template, also reporting how each key was used
+    ///
+    /// See [`ApplyReport`](./struct.ApplyReport.html)
+    ///
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let opts = Opts::default().optional_keys().build();
+    /// let template = Template::parse("${greeting}, ${name}", opts).unwrap();
+    /// let args = Args::new().with("greeting", "hello").with("extra", true);
+    ///
+    /// let (output, report) = template.apply_with_report(&args).unwrap();
+    /// assert_eq!(output, "hello, ${name}");
+    /// assert_eq!(report.get("greeting").unwrap().replaced(), 1);
+    /// assert_eq!(report.unfilled_keys().collect::<Vec<_>>(), vec!["name"]);
+    /// assert_eq!(report.unused_args(), &["extra"]);
+    /// ```
+    pub fn apply_with_report(&self, args: &Args<'_>) -> Result<(String, ApplyReport)> {
+        let mut report = ApplyReport::default();
+        for key in self.keys() {
+            report.keys.entry(key.to_string()).or_default();
+        }
+
+        let output = self.render(args, |key, _, range| {
+            let usage = report.keys.entry(key.to_string()).or_default();
+            usage.replaced += 1;
+            usage.bytes += range.len();
+        })?;
+
+        report.unused = args
+            .values
+            .mapping
+            .keys()
+            .filter(|arg| {
+                !(self.keys().chain(self.inner_keys())).any(|key| self.opts.key_eq(key, arg))
+            })
+            .map(|arg| arg.to_string())
+            .collect();
+        report.unused.sort();
+
+        Ok((output, report))
+    }
+
+    /// Estimate the length of the output of applying `args` to the template
+    ///
+    /// This is the length of the literal text, plus the length of the value of each key. Keys without
+    /// a value count as their marker. Every branch of a block is counted, and filters are ignored, so
+    /// the output can be shorter or longer than this
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("hello ${name}!", Opts::default()).unwrap();
+    /// let args = Args::new().with("name", "bob");
+    /// assert_eq!(template.len_hint(&args), 10);
+    /// assert_eq!(template.apply(&args).unwrap().len(), 10);
+    /// ```
+    pub fn len_hint(&self, args: &Args<'_>) -> usize {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => self.opts.unescape(literal).len(),
+                Segment::Key { name, raw, .. } => self.lookup(args, name).unwrap_or(raw).len(),
+                Segment::Expr { raw, .. } => raw.len(),
+                Segment::Block { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Apply the arguments to the template, borrowing the output when it is a single piece
+    ///
+    /// The output is borrowed from the template or the args when the template has no keys, or is just
+    /// one key with nothing around it. It is only allocated when pieces have to be joined.
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// # use std::borrow::Cow;
+    /// let args = Args::new().with("name", "bob");
+    ///
+    /// let template = Template::parse("hello world", Opts::default().empty_template().optional_keys()).unwrap();
+    /// assert!(matches!(template.render_cow(&args).unwrap(), Cow::Borrowed("hello world")));
+    ///
+    /// let template = Template::parse("${name}", Opts::default()).unwrap();
+    /// assert!(matches!(template.render_cow(&args).unwrap(), Cow::Borrowed("bob")));
+    ///
+    /// let template = Template::parse("hello ${name}", Opts::default()).unwrap();
+    /// assert!(matches!(template.render_cow(&args).unwrap(), Cow::Owned(..)));
+    /// ```
+    pub fn render_cow<'s>(&'s self, args: &'s Args<'_>) -> Result<Cow<'s, str>> {
+        let (mut pieces, len) = self.pieces(args)?;
+        if pieces.len() <= 1 {
+            return Ok(pieces.pop().map(|(piece, ..)| piece).unwrap_or_default());
+        }
+
+        let mut data = String::with_capacity(len);
+        for (piece, ..) in pieces {
+            data.push_str(&piece);
+        }
+        Ok(data.into())
+    }
+
+    // `on_key` is called with the key, its value and the range of the output it was written to
+    fn render(
+        &self,
+        args: &Args<'_>,
+        on_key: impl FnMut(&str, &str, Range<usize>),
+    ) -> Result<String> {
+        let mut data = String::new();
+        self.render_into(args, &mut data, on_key)?;
+        Ok(data)
+    }
+
+    // writes the output to the sink. the ranges given to `on_key` are of what was written
+    fn render_into(
+        &self,
+        args: &Args<'_>,
+        sink: &mut impl Sink,
+        mut on_key: impl FnMut(&str, &str, Range<usize>),
+    ) -> Result<()> {
+        // the pieces are collected first, so the output can be allocated once with its exact length
+        let (pieces, len) = self.pieces(args)?;
+        sink.reserve(len);
+        let mut written = 0;
+        for (piece, key, _) in pieces {
+            sink.write_str(&piece).map_err(Error::io)?;
+            written += piece.len();
+            if let Some(key) = key {
+                on_key(key, &piece, written - piece.len()..written);
+            }
+        }
+        Ok(())
+    }
+
+    // the rendered pieces of the output, with the key each was rendered for and the span of the segment it was
+    // rendered from, and the length of the output
+    fn pieces<'s>(&'s self, args: &'s Args<'_>) -> Result<(Pieces<'s>, usize)> {
+        #[cfg(feature = "tracing")]
+        return trace::apply(self, args, || self.render_pieces(args));
+        #[cfg(not(feature = "tracing"))]
+        self.render_pieces(args)
+    }
+
+    fn render_pieces<'s>(&'s self, args: &'s Args<'_>) -> Result<(Pieces<'s>, usize)> {
+        self.check_args(args)?;
+        self.check_arg_uses(args)?;
+        self.check_duplicate_args(args)?;
+
+        let mut pieces = Pieces::with_capacity(self.segments.len());
+        let mut len = 0;
+        // the open blocks, and the prefixes of the open `${@key}` blocks, so lookups only go through those
+        let mut blocks: Vec<OpenBlock> = vec![];
+        let mut scopes: Vec<String> = vec![];
+        let mut missing = BTreeSet::new();
+        for (segment, span) in self.spans() {
+            let active = blocks.last().is_none_or(|block| block.active);
+            let (piece, key) = match segment {
+                Segment::Block { kind, name, .. } => {
+                    let len = blocks.len();
+                    let parent = len < 2 || blocks[len - 2].active;
+                    let truthy = || self.is_truthy(args, &scopes, name);
+                    match kind {
+                        BlockKind::If => {
+                            let taken = active && truthy();
+                            blocks.push(OpenBlock::new(taken, false))
+                        }
+                        BlockKind::Region => blocks.push(OpenBlock::new(active, false)),
+                        BlockKind::With => {
+                            scopes.push(OpenBlock::scope(&scopes, name));
+                            blocks.push(OpenBlock::new(active, true))
+                        }
+                        BlockKind::ElseIf => {
+                            let truthy = parent && truthy();
+                            if let Some(block) = blocks.last_mut() {
+                                block.active = truthy && !block.taken;
+                                block.taken |= block.active;
+                            }
+                        }
+                        BlockKind::Else => {
+                            if let Some(block) = blocks.last_mut() {
+                                block.active = parent && !block.taken;
+                                block.taken = true;
+                            }
+                        }
+                        BlockKind::End => {
+                            if blocks.pop().is_some_and(|block| block.scoped) {
+                                scopes.pop();
+                            }
+                        }
+                    }
+                    continue;
+                }
+                _ if !active => continue,
+                Segment::Literal(literal) => (self.opts.unescape(literal), None),
+                Segment::Key {
+                    name,
+                    chain,
+                    spec,
+                    fallbacks,
+                    raw,
+                    ..
+                } => match self.lookup_fallbacks(args, &scopes, name, fallbacks) {
+                    Some(val) => {
+                        if let Some(spec) = spec.as_deref() {
+                            if self.opts.validate_key_specs && !spec_matches(spec, &val) {
+                                let (key, spec) = (name.to_string(), spec.to_string());
+                                let err = Error::from(ErrorKind::InvalidValue { key, spec });
+                                return Err(err.with_span(span));
+                            }
+                        }
+                        let with_span = |err: Error| err.with_span(span.clone());
+                        let piece = match val {
+                            Cow::Borrowed(val) => {
+                                filters::apply(name, chain, val, &self.opts).map_err(with_span)?
+                            }
+                            Cow::Owned(val) => {
+                                let piece = filters::apply(name, chain, &val, &self.opts)
+                                    .map_err(with_span)?;
+                                Cow::Owned(piece.into_owned())
+                            }
+                        };
+                        (piece, Some(name))
+                    }
+                    None => {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(key = &**name, "key has no value");
+                        missing.insert(&**name);
+                        (Cow::Borrowed(&**raw), None)
+                    }
+                },
+                Segment::Expr { expr, raw } => {
+                    let lookup = |key: &str| self.lookup_scoped(args, &scopes, key);
+                    let absent = expr.keys().filter(|key| lookup(key).is_none());
+                    let absent = absent.collect::<Vec<_>>();
+                    if absent.is_empty() {
+                        let val = expr.eval(|key| lookup(key).unwrap_or_default());
+                        (val.map_err(|err| err.with_span(span.clone()))?.into(), None)
+                    } else {
+                        #[cfg(feature = "tracing")]
+                        for key in &absent {
+                            tracing::debug!(key, "key has no value");
+                        }
+                        missing.extend(absent);
+                        (Cow::Borrowed(&**raw), None)
+                    }
+                }
+            };
+
+            len += piece.len();
+            if let Some(limit) = self.opts.max_output_len {
+                if len > limit {
+                    return Err(ErrorKind::OutputTooLarge { limit }.into());
+                }
+            }
+            pieces.push((piece, key.map(|key| &**key), span));
+        }
+
+        if !self.opts.optional_keys && !missing.is_empty() {
+            let keys = missing.into_iter().map(str::to_string).collect();
+            return Err(ErrorKind::MissingKeys { keys }.into());
+        }
+        Ok((pieces, len))
+    }
+
+    /// Render the template with a placeholder for each key, like `«name»`
+    ///
+    /// This shows what the template looks like before there are values for it. Every key of a block also
+    /// has a placeholder, so the first branch of each block is rendered. Filters are applied to the placeholders
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("hello ${name}${?admin} (admin)${/}", Opts::default()).unwrap();
+    /// assert_eq!(template.preview().unwrap(), "hello «name» (admin)");
+    /// ```
+    pub fn preview(&self) -> Result<String> {
+        self.preview_with(|key| format!("«{}»", key))
+    }
+
+    /// Render the template with a placeholder for each key, like [`Template::preview`](#method.preview)
+    ///
+    /// `placeholder` is called with the name of each key, and the text of each expression, like `count + 1`
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("hello ${name}", Opts::default()).unwrap();
+    /// assert_eq!(template.preview_with(|key| key.to_uppercase()).unwrap(), "hello NAME");
+    /// ```
+    pub fn preview_with(&self, mut placeholder: impl FnMut(&str) -> String) -> Result<String> {
+        let args = self
+            .names()
+            .map(|name| (name, placeholder(name)))
+            .collect::<Args<'_>>();
+
+        // the placeholders are for every key, so they don't have to be used like real arguments
+        let mut template = self.clone();
+        for segment in &mut template.segments {
+            if let Segment::Expr { expr, .. } = segment {
+                *segment = Segment::Literal(placeholder(expr.as_str()).into());
+            }
+        }
+        template.opts.optional_keys = true;
+        template.opts.arg_uses = None;
+        template.apply(&args)
+    }
+
+    /// Apply layers of arguments to the template
+    ///
+    /// The layers are merged in order, and `precedence` decides which value is used when more
+    /// than one layer has the same key. The merged arguments are applied once
+    /// ```
+    /// # use markings::{Template, Args, Opts, Precedence};
+    /// let template = Template::parse("${greeting}, ${name}", Opts::default()).unwrap();
+    /// let defaults = Args::new().with("greeting", "hello").with("name", "stranger");
+    /// let user = Args::new().with("name", "bob");
+    ///
+    /// let layers = [defaults, user];
+    /// assert_eq!(template.apply_all_args(&layers, Precedence::Last).unwrap(), "hello, bob");
+    /// assert_eq!(template.apply_all_args(&layers, Precedence::First).unwrap(), "hello, stranger");
+    /// ```
+    pub fn apply_all_args(&self, layers: &[Args<'_>], precedence: Precedence) -> Result<String> {
+        let mut args = Args::new();
+        for layer in layers {
+            for (key, val) in &layer.values.mapping {
+                if precedence == Precedence::First && args.values.mapping.contains_key(key) {
+                    continue;
+                }
+                args.values.mapping.insert(key.clone(), val.clone());
+                match layer.values.secrets.contains(key) {
+                    true => args.values.secrets.insert(key.clone()),
+                    false => args.values.secrets.remove(key),
+                };
+            }
+        }
+        self.apply(&args)
+    }
+
+    /// Apply positional arguments to the template
+    ///
+    /// Positional keys are just numbers: `${0}`, `${1}`, etc. The value at index `N` in the slice
+    /// replaces the key `${N}`
+    ///
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("${0} + ${1} = ${2}", Opts::default()).unwrap();
+    /// let output = template.apply_positional(&[1, 2, 3]).unwrap();
+    /// assert_eq!(output, "1 + 2 = 3");
+    /// ```
+    pub fn apply_positional(&self, values: &[impl std::fmt::Display]) -> Result<String> {
+        self.apply_ordered(values)
+    }
+
+    /// Apply an ordered sequence of values to the positional keys of the template
+    ///
+    /// This is most useful with anonymous `${}` keys, which are numbered in order of appearance
+    ///
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("${} and ${}", Opts::default()).unwrap();
+    /// let output = template.apply_ordered(vec!["this", "that"]).unwrap();
+    /// assert_eq!(output, "this and that");
+    /// ```
+    pub fn apply_ordered<I>(&self, values: I) -> Result<String>
+    where
+        I: IntoIterator,
+        I::Item: std::fmt::Display,
+    {
+        let args = values
+            .into_iter()
+            .enumerate()
+            .map(|(i, val)| (i.to_string(), val))
+            .collect::<Args<'_>>();
+        self.apply(&args)
+    }
+
+    /// Concatenate another template onto the end of this one
+    ///
+    /// The combined template has the keys of both templates. Anonymous `${}` keys in `other`
+    /// are renumbered to follow those in `self`, as if both were parsed as a single string.
+    ///
+    /// The combined template uses the [`Opts`](./struct.Opts.html) of `self` and is validated
+    /// against them, so a key found in both templates is an error unless `duplicate_keys` is enabled.
+    ///
+    /// Templates can also be combined with `+`:
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let header = Template::parse("${greeting}, ", Opts::default()).unwrap();
+    /// let body = Template::parse("${name}", Opts::default()).unwrap();
+    /// let footer = Template::parse("${end}", Opts::default()).unwrap();
+    ///
+    /// let template = (header + body + footer).unwrap();
+    /// let args = Args::new()
+    ///     .with("greeting", "hello")
+    ///     .with("name", "world")
+    ///     .with("end", '!');
+    /// assert_eq!(template.apply(&args).unwrap(), "hello, world!");
+    /// ```
+    pub fn concat(mut self, other: Template<'a>) -> Result<Self> {
+        for segment in other.segments {
+            self.push(segment);
+        }
+        self.renumber();
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Split this template into two templates around the first occurrence of a *sentinel* key
+    ///
+    /// The sentinel key is removed, and everything before and after it become independent
+    /// templates with the options of this template. Returns `None` if the key isn't found, or is inside of a block.
+    ///
+    /// A part without any keys will have `empty_template` enabled, so it can be applied like any other template.
+    ///
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("Subject: ${subject}${body}Hello ${name}", Opts::default()).unwrap();
+    /// let (header, body) = template.split_at_key("body").unwrap();
+    /// assert_eq!(header.apply(&Args::new().with("subject", "hi")).unwrap(), "Subject: hi");
+    /// assert_eq!(body.apply(&Args::new().with("name", "bob")).unwrap(), "Hello bob");
+    /// ```
+    pub fn split_at_key(&self, key: &str) -> Option<(Self, Self)> {
+        let pos = self.segments.iter().position(|segment| match segment {
+            Segment::Key { name, .. } => self.opts.key_eq(name, key),
+            _ => false,
+        })?;
+
+        // splitting inside of a block would leave both parts unbalanced
+        let depth = self.segments[..pos]
+            .iter()
+            .fold(0_isize, |depth, segment| match segment {
+                Segment::Block { kind, .. } if kind.opens() => depth + 1,
+                Segment::Block {
+                    kind: BlockKind::End,
+                    ..
+                } => depth - 1,
+                _ => depth,
+            });
+        if depth != 0 {
+            return None;
+        }
+
+        let part = |segments: &[Segment<'a>]| {
+            let mut template = Self {
+                segments: segments.to_vec(),
+                opts: self.opts.clone(),
+            };
+            template.renumber();
+            if template.keys().next().is_none() {
+                template.opts.empty_template = true;
+            }
+            template
+        };
+
+        Some((part(&self.segments[..pos]), part(&self.segments[pos + 1..])))
+    }
+
+    /// Rename every occurrence of a key, returning a new template
+    ///
+    /// Only the keys (and blocks that use them) are changed, literal text that happens to contain the old name is left alone.
+    /// This fails if the new name isn't a valid key, or if the renamed template isn't valid
+    /// for its options (e.g. the new name was already used).
+    ///
+    /// ```
+    /// # use markings::{Template, Opts};
+    /// let template = Template::parse("user: ${user}", Opts::default()).unwrap();
+    /// let template = template.replace_key_name("user", "name").unwrap();
+    /// assert_eq!(template.to_string(), "user: ${name}");
+    /// ```
+    pub fn replace_key_name(&self, old: &str, new: &str) -> Result<Template<'a>> {
+        let marker = format!("${{{}}}", new);
+        if Self::find_keys(&marker)? != [new] {
+            // a closing brace in the new name would end the marker early
+            let close = marker.matches('}').count();
+            return Err(ErrorKind::MismatchedBraces { open: 1, close }.into());
+        }
+
+        let mut template = self.clone();
+        for segment in &mut template.segments {
+            let (name, raw) = match segment {
+                Segment::Key { name, raw, .. } => (name, raw),
+                Segment::Block { kind, name, raw } if kind.uses_key() && !name.is_empty() => {
+                    (name, raw)
+                }
+                _ => continue,
+            };
+            if self.opts.key_eq(name, old) {
+                *raw = rename_marker(raw, name, new)
+                    .unwrap_or_else(|| marker.clone())
+                    .into();
+                *name = new.to_string().into();
+            }
+        }
+        template.renumber();
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Rewrite the literal text of the template, leaving the keys intact
+    ///
+    /// `map` is called for each run of literal text between keys. The new text is used as is,
+    /// it is not parsed for keys.
+    ///
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("hello ${name}, how are you?", Opts::default()).unwrap();
+    /// let template = template.map_literals(|literal| literal.to_uppercase());
+    /// assert_eq!(template.apply(&Args::new().with("name", "bob")).unwrap(), "HELLO bob, HOW ARE YOU?");
+    /// ```
+    pub fn map_literals(mut self, mut map: impl FnMut(&str) -> String) -> Self {
+        for segment in &mut self.segments {
+            if let Segment::Literal(literal) = segment {
+                *literal = map(literal).into();
+            }
+        }
+        self.segments.retain(|segment| match segment {
+            Segment::Literal(literal) => !literal.is_empty(),
+            _ => true,
+        });
+        self
+    }
+
+    /// Replace the text in `range` of the template's source with `text`
+    ///
+    /// The range is in the text the template [displays](#impl-Display-for-Template%3C'a%3E) as, and has to be
+    /// inside of literal text, or be empty. The new text is used as is, it is not parsed for keys, so it can't make a marker,
+    /// like `${key}`, `$KEY` or `%KEY%` with the options for those, with itself or the text around it. With
+    /// [`Opts::percent_keys`](./struct.Opts.html#method.percent_keys) it can't pair a `%` with one around it either.
+    /// This fails with `InvalidEdit` otherwise
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let mut template = Template::parse("hello ${name}!", Opts::default()).unwrap();
+    /// template.set_literal(0..5, "goodbye").unwrap();
+    /// template.set_literal(15..15, ", see you").unwrap();
+    /// assert_eq!(template.to_string(), "goodbye ${name}, see you!");
+    /// ```
+    pub fn set_literal(&mut self, range: Range<usize>, text: &str) -> Result<()> {
+        let invalid = || Error::from(ErrorKind::InvalidEdit { pos: range.start });
+        if range.start > range.end {
+            return Err(invalid());
+        }
+
+        // the source has to have the same markers and `%%` escapes after the edit, other than the ones in the new text
+        let display = self.to_string();
+        if let (Some(head), Some(tail)) = (display.get(..range.start), display.get(range.end..)) {
+            let edited = format!("{}{}{}", head, text, tail);
+            let inserted = range.start..range.start + text.len();
+            let shift = |pos: usize| match pos >= range.end {
+                true => pos - range.end + inserted.end,
+                false => pos,
+            };
+            let (markers, escapes) = self.syntax(&display).ok_or_else(invalid)?;
+            let (edited_markers, edited_escapes) = self.syntax(&edited).ok_or_else(invalid)?;
+
+            let markers = markers
+                .into_iter()
+                .map(|marker| match marker.start >= range.end {
+                    true => shift(marker.start)..shift(marker.end),
+                    false => marker,
+                });
+            let escapes = escapes.into_iter().map(shift);
+            let edited_escapes = edited_escapes
+                .into_iter()
+                .filter(|&pos| !(inserted.start <= pos && pos + 2 <= inserted.end));
+            if !markers.eq(edited_markers) || !escapes.eq(edited_escapes) {
+                return Err(invalid());
+            }
+        }
+
+        let literal = self.spans().position(|(segment, span)| {
+            matches!(segment, Segment::Literal(..))
+                && span.start <= range.start
+                && range.end <= span.end
+        });
+        match literal {
+            Some(index) => {
+                let start = self.spans().nth(index).map_or(0, |(_, span)| span.start);
+                let Segment::Literal(literal) = &mut self.segments[index] else {
+                    unreachable!("the segment is a literal")
+                };
+                let range = range.start - start..range.end - start;
+                if !literal.is_char_boundary(range.start) || !literal.is_char_boundary(range.end) {
+                    return Err(invalid());
+                }
+                literal.to_mut().replace_range(range, text);
+            }
+            None if range.is_empty() => {
+                let index = self.split_at(range.start)?;
+                self.segments
+                    .insert(index, Segment::Literal(text.to_string().into()));
+            }
+            None => return Err(invalid()),
+        }
+
+        self.segments.retain(|segment| match segment {
+            Segment::Literal(literal) => !literal.is_empty(),
+            _ => true,
+        });
+        Ok(())
+    }
+
+    /// Insert a key at `pos` of the template's source
+    ///
+    /// The position is in the text the template [displays](#impl-Display-for-Template%3C'a%3E) as, and can't be inside of a marker.
+    /// This fails with `InvalidEdit` if it is, or if `name` isn't just a key, like a block, or a key with filters or an annotation. An empty name
+    /// inserts an anonymous key. The template is left as is if the edited template isn't valid for its options
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let mut template = Template::parse("hello ${name}!", Opts::default()).unwrap();
+    /// template.insert_key(3, "x").unwrap();
+    /// template.insert_key(17, "").unwrap();
+    /// assert_eq!(template.to_string(), "hel${x}lo ${name}${}!");
+    ///
+    /// assert!(template.insert_key(3, "name").is_err());
+    /// ```
+    pub fn insert_key(&mut self, pos: usize, name: &str) -> Result<()> {
+        let marker = format!("${{{}}}", name);
+        if Self::find_keys(&marker)? != [name] {
+            // a closing brace in the new name would end the marker early
+            let close = marker.matches('}').count();
+            return Err(ErrorKind::MismatchedBraces { open: 1, close }.into());
+        }
+        if BlockKind::split(name).0.is_some()
+            || filters::split(name, "|").len() > 1
+            || filters::split(name, "??").len() > 1
+            || name.contains('!')
+        {
+            return Err(ErrorKind::InvalidEdit { pos }.into());
+        }
+
+        let mut template = self.clone();
+        let index = template.split_at(pos)?;
+        let key = Segment::Key {
+            name: name.to_string().into(),
+            filters: vec![],
+            chain: filters::Chain::default(),
+            spec: None,
+            fallbacks: vec![],
+            raw: marker.into(),
+        };
+        template.segments.insert(index, key);
+        template.renumber();
+        template.validate()?;
+        *self = template;
+        Ok(())
+    }
+
+    // splits the literal at `pos`, returning the index of the segment that starts at `pos`
+    fn split_at(&mut self, pos: usize) -> Result<usize> {
+        let invalid = || Error::from(ErrorKind::InvalidEdit { pos });
+        let end = self.spans().last().map_or(0, |(_, span)| span.end);
+        if pos == end {
+            return Ok(self.segments.len());
+        }
+
+        let (index, span) = self
+            .spans()
+            .enumerate()
+            .find(|(_, (_, span))| span.contains(&pos))
+            .map(|(index, (_, span))| (index, span))
+            .ok_or_else(invalid)?;
+        if pos == span.start {
+            return Ok(index);
+        }
+        let (left, right) = match &self.segments[index] {
+            Segment::Literal(literal) if literal.is_char_boundary(pos - span.start) => {
+                let (left, right) = literal.split_at(pos - span.start);
+                (left.to_string(), right.to_string())
+            }
+            _ => return Err(invalid()),
+        };
+        self.segments[index] = Segment::Literal(left.into());
+        self.segments
+            .insert(index + 1, Segment::Literal(right.into()));
+        Ok(index + 1)
+    }
+
+    // anonymous keys are numbered in order of appearance
+    fn renumber(&mut self) {
+        let anonymous = self
+            .segments
+            .iter_mut()
+            .filter_map(|segment| match segment {
+                Segment::Key { name, raw, .. } if raw == ANONYMOUS => Some(name),
+                _ => None,
+            });
+        for (i, name) in anonymous.enumerate() {
+            *name = i.to_string().into();
+        }
+    }
+
+    fn push(&mut self, segment: Segment<'a>) {
+        if let (Some(Segment::Literal(left)), Segment::Literal(right)) =
+            (self.segments.last_mut(), &segment)
+        {
+            left.to_mut().push_str(right);
+            return;
+        }
+        self.segments.push(segment);
+    }
+
+    // args can't be given more than once, or match the same key, with `DuplicateArgs::Error`
+    fn check_duplicate_args(&self, args: &Args<'_>) -> Result<()> {
+        if self.opts.duplicate_args != DuplicateArgs::Error {
+            return Ok(());
+        }
+        if let Some(key) = args.values.overwritten.keys().next() {
+            let key = key.to_string();
+            return Err(ErrorKind::DuplicateArg { key }.into());
+        }
+        let state = State::new(args.values.mapping.keys().map(|arg| &**arg));
+        match state.duplicate(&self.opts) {
+            Some(index) => {
+                let key = state.keys[index].to_string();
+                Err(ErrorKind::DuplicateArg { key }.into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // every arg has to match a key, unless keys are optional
+    fn check_args(&self, args: &Args<'_>) -> Result<()> {
+        if self.opts.optional_keys || self.allows_empty() {
+            return Ok(());
+        }
+        // anything under the scope of a `${@key}` block can be used by it
+        let scopes = self.scopes();
+        let scoped = |arg: &str| {
+            scopes.iter().any(|scope| {
+                arg.strip_prefix(&**scope)
+                    .is_some_and(|rest| rest.starts_with('.'))
+            })
+        };
+        let mut names = self.names().collect::<Keys<'_>>();
+        names.sort_unstable();
+        let known = |arg: &str| {
+            names.binary_search(&arg).is_ok()
+                || (self.opts.folds_keys() && names.iter().any(|key| self.opts.key_eq(key, arg)))
+        };
+        let unknown = args
+            .values
+            .mapping
+            .keys()
+            .any(|arg| !known(arg) && !scoped(arg));
+        if unknown {
+            return Err(ErrorKind::OptionalKeys.into());
+        }
+        Ok(())
+    }
+
+    fn validate(&mut self) -> Result<()> {
+        if let Some(limit) = self.opts.max_template_len {
+            if self.spans().last().map(|(_, span)| span.end).unwrap_or(0) > limit {
+                return Err(ErrorKind::TemplateTooLong { limit }.into());
+            }
+        }
+
+        self.check_blocks()?;
+        self.parse_filters()?;
+        self.check_fallbacks()?;
+        self.check_specs()?;
+        self.check_allowed_keys()?;
+
+        // keys that are only used by blocks still count, so a template of only blocks isn't empty
+        let mut opts = self.opts.clone();
+        opts.empty_template |= self.names().next().is_some();
+
+        let state = State::new(self.keys());
+        opts.validate(&state).map_err(|(kind, index)| {
+            let err = Error::from(kind);
+            match index.and_then(|index| self.key_spans().into_iter().nth(index)) {
+                Some((_, span)) => err.with_span(span),
+                None => err,
+            }
+        })
+    }
+
+    // parses the filters of each key, so they aren't parsed again when the template is applied
+    fn parse_filters(&mut self) -> Result<()> {
+        let mut pos = 0;
+        for segment in &mut self.segments {
+            let start = pos;
+            pos += match segment {
+                Segment::Literal(literal) => literal.len(),
+                Segment::Key { raw, .. }
+                | Segment::Block { raw, .. }
+                | Segment::Expr { raw, .. } => raw.len(),
+            };
+            if let Segment::Key { filters, chain, .. } = segment {
+                let filters = filters.iter().map(|filter| &**filter);
+                *chain = filters::Chain::parse(filters, &self.opts)
+                    .map_err(|err| Error::from(err).with_span(start..pos))?;
+            }
+        }
+        Ok(())
+    }
+
+    // fallbacks need a key or quoted text, and so does the key that falls back
+    fn check_fallbacks(&self) -> Result<()> {
+        for (segment, span) in self.spans() {
+            let (name, fallbacks) = match segment {
+                Segment::Key {
+                    name, fallbacks, ..
+                } if !fallbacks.is_empty() => (name, fallbacks),
+                _ => continue,
+            };
+            let valid = |fallback: &Fallback<'_>| match fallback {
+                Fallback::Key(key) => !key.is_empty(),
+                Fallback::Literal(text) => filters::unquote(text).is_some(),
+            };
+            if name.is_empty() || !fallbacks.iter().all(valid) {
+                let err = ErrorKind::UnsupportedSyntax { pos: span.start };
+                return Err(Error::from(err).with_span(span));
+            }
+        }
+        Ok(())
+    }
+
+    // annotations have to be ones that can be checked, when they are checked
+    fn check_specs(&self) -> Result<()> {
+        if !self.opts.validate_key_specs {
+            return Ok(());
+        }
+        for (segment, span) in self.spans() {
+            match segment {
+                Segment::Key {
+                    spec: Some(spec), ..
+                } if Spec::parse(spec).is_none() => {
+                    let err = ErrorKind::UnknownSpec {
+                        spec: spec.to_string(),
+                    };
+                    return Err(Error::from(err).with_span(span));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // every key a marker uses has to be allowed, if only some keys are
+    fn check_allowed_keys(&self) -> Result<()> {
+        let allowed = match self.opts.allowed_keys {
+            Some(allowed) => allowed,
+            None => return Ok(()),
+        };
+        for (segment, span) in self.spans() {
+            let mut names = vec![];
+            match segment {
+                Segment::Key {
+                    name, fallbacks, ..
+                } => {
+                    names.push(&**name);
+                    names.extend(fallbacks.iter().filter_map(|fallback| match fallback {
+                        Fallback::Key(key) => Some(&**key),
+                        Fallback::Literal(..) => None,
+                    }))
+                }
+                Segment::Block { kind, name, .. } if kind.uses_key() && !name.is_empty() => {
+                    names.push(name)
+                }
+                Segment::Expr { expr, .. } => names.extend(expr.keys()),
+                _ => continue,
+            }
+
+            let forbidden = names
+                .into_iter()
+                .find(|name| !allowed.iter().any(|key| self.opts.key_eq(key, name)));
+            if let Some(key) = forbidden {
+                let key = key.to_string();
+                let err = ErrorKind::ForbiddenKey {
+                    key,
+                    pos: span.start,
+                };
+                return Err(Error::from(err).with_span(span));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_blocks(&self) -> Result<()> {
+        let mut open = vec![];
+        for (segment, span) in self.spans() {
+            let (kind, name) = match segment {
+                Segment::Block { kind, name, .. } => (kind, name),
+                _ => continue,
+            };
+            if kind.opens() && open.len() == self.opts.depth_limit() {
+                let err = ErrorKind::UnsupportedSyntax { pos: span.start };
+                return Err(Error::from(err).with_span(span));
+            }
+            match kind {
+                BlockKind::If => open.push((name, span, false)),
+                // a scope has no branches, so it acts like it already had its else
+                BlockKind::With | BlockKind::Region => open.push((name, span, true)),
+                BlockKind::ElseIf | BlockKind::Else => match open.last_mut() {
+                    Some((_, _, seen_else)) if !*seen_else => {
+                        *seen_else = *kind == BlockKind::Else;
+                    }
+                    _ => {
+                        let err = ErrorKind::MisplacedElse { pos: span.start };
+                        return Err(Error::from(err).with_span(span));
+                    }
+                },
+                BlockKind::End => match open.pop() {
+                    Some((open, ..)) if name.is_empty() || self.opts.key_eq(open, name) => {}
+                    _ => {
+                        let err = ErrorKind::UnmatchedBlockEnd { pos: span.start };
+                        return Err(Error::from(err).with_span(span));
+                    }
+                },
+            }
+        }
+
+        match open.pop() {
+            Some((_, span, _)) => {
+                let err = ErrorKind::UnclosedBlock { pos: span.start };
+                Err(Error::from(err).with_span(span))
+            }
+            None => Ok(()),
+        }
+    }
+
+    // the byte range of each segment in the template source
+    fn spans(&self) -> impl Iterator<Item = (&Segment<'a>, Range<usize>)> + '_ {
+        let mut pos = 0;
+        self.segments.iter().map(move |segment| {
+            let len = match segment {
+                Segment::Literal(literal) => literal.len(),
+                Segment::Key { raw, .. }
+                | Segment::Block { raw, .. }
+                | Segment::Expr { raw, .. } => raw.len(),
+            };
+            pos += len;
+            (segment, pos - len..pos)
+        })
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Key { name, .. } => Some(&**name),
+            _ => None,
+        })
+    }
+
+    // the keys, and the keys that blocks, expressions and fallbacks depend on
+    fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        let names = self.segments.iter().filter_map(|segment| match segment {
+            Segment::Key { name, .. } => Some(&**name),
+            Segment::Block { kind, name, .. } if kind.uses_key() && !name.is_empty() => {
+                Some(&**name)
+            }
+            _ => None,
+        });
+        names.chain(self.inner_keys())
+    }
+
+    // the keys used inside of markers, like `count` in `${count + 1}` and `user` in `${name ?? user}`
+    fn inner_keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.segments.iter().flat_map(|segment| {
+            let (exprs, fallbacks) = match segment {
+                Segment::Expr { expr, .. } => (Some(expr.keys()), &[][..]),
+                Segment::Key { fallbacks, .. } => (None, &fallbacks[..]),
+                _ => (None, &[][..]),
+            };
+            let fallbacks = fallbacks.iter().filter_map(|fallback| match fallback {
+                Fallback::Key(key) => Some(&**key),
+                Fallback::Literal(..) => None,
+            });
+            exprs.into_iter().flatten().chain(fallbacks)
+        })
+    }
+
+    fn is_truthy(&self, args: &Args<'_>, scopes: &[String], key: &str) -> bool {
+        self.lookup_scoped(args, scopes, key)
+            .is_some_and(|val| self.opts.truthiness.is_truthy(val))
+    }
+
+    // looks up a key in the scope of each open `${@key}` block, innermost first
+    fn lookup_scoped<'s>(
+        &self,
+        args: &'s Args<'_>,
+        scopes: &[String],
+        key: &str,
+    ) -> Option<&'s str> {
+        let mut scoped = String::new();
+        scopes
+            .iter()
+            .rev()
+            .find_map(|scope| {
+                scoped.clear();
+                scoped.extend([scope, ".", key].iter().copied());
+                self.lookup(args, &scoped)
+            })
+            .or_else(|| self.lookup(args, key))
+    }
+
+    // the full prefix of each `${@key}` block
+    fn scopes(&self) -> Vec<String> {
+        let mut blocks = vec![];
+        let mut open = vec![];
+        let mut scopes = vec![];
+        for segment in &self.segments {
+            let (kind, name) = match segment {
+                Segment::Block { kind, name, .. } => (kind, name),
+                _ => continue,
+            };
+            match kind {
+                BlockKind::With => {
+                    let scope = OpenBlock::scope(&open, name);
+                    scopes.push(scope.clone());
+                    open.push(scope);
+                    blocks.push(OpenBlock::new(true, true));
+                }
+                BlockKind::If | BlockKind::Region => blocks.push(OpenBlock::new(true, false)),
+                BlockKind::End => {
+                    if blocks.pop().is_some_and(|block| block.scoped) {
+                        open.pop();
+                    }
+                }
+                BlockKind::ElseIf | BlockKind::Else => {}
+            }
+        }
+        scopes
+    }
+
+    // the value of a key, or of the first of its fallbacks with a value. empty values fall back too
+    fn lookup_fallbacks<'s>(
+        &self,
+        args: &'s Args<'_>,
+        scopes: &[String],
+        key: &str,
+        fallbacks: &[Fallback<'_>],
+    ) -> Option<Cow<'s, str>> {
+        let mut found = self.lookup_scoped(args, scopes, key).map(Cow::Borrowed);
+        for fallback in fallbacks {
+            if found.as_ref().is_some_and(|val| !val.is_empty()) {
+                break;
+            }
+            let val = match fallback {
+                Fallback::Key(key) => self.lookup_scoped(args, scopes, key).map(Cow::Borrowed),
+                Fallback::Literal(text) => filters::unquote(text).map(Cow::Owned),
+            };
+            found = val.or(found);
+        }
+        found
+    }
+
+    fn lookup<'s>(&self, args: &'s Args<'_>, key: &str) -> Option<&'s str> {
+        self.lookup_arg(args, key).map(|(_, val)| val)
+    }
+
+    // finds the argument for a key, returning its name and value
+    fn lookup_arg<'s>(&self, args: &'s Args<'_>, key: &str) -> Option<(&'s str, &'s str)> {
+        let arg = match args.values.mapping.get_key_value(key) {
+            Some((arg, _)) => &**arg,
+            None if !self.opts.folds_keys() => return None,
+            None => args
+                .values
+                .mapping
+                .keys()
+                .find(|arg| self.opts.key_eq(arg, key))
+                .map(|arg| &**arg)?,
+        };
+        Some((arg, args.value(arg, self.opts.duplicate_args)?))
+    }
+
+    fn check_arg_uses(&self, args: &Args<'_>) -> Result<()> {
+        let expected = match self.opts.arg_uses {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let mut uses = args
+            .values
+            .mapping
+            .keys()
+            .map(|arg| (&**arg, 0))
+            .collect::<BTreeMap<_, _>>();
+        for key in self.keys().chain(self.inner_keys()) {
+            if let Some((arg, _)) = self.lookup_arg(args, key) {
+                *uses.entry(arg).or_default() += 1;
+            }
+        }
+
+        match uses.into_iter().find(|&(_, found)| found != expected) {
+            Some((key, found)) => Err(ErrorKind::ArgUseCount {
+                key: key.to_string(),
+                expected,
+                found,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Find all the *keys* in the input string, returning them in a Vec
+    ///
+    /// This is exposed as a convenient function for doing pre-parsing.
+    ///
+    /// This returns an error if there are:
+    /// * nested templates
+    /// * mismatched braces
+    ///
+    /// ```
+    /// # use markings::Template;
+    /// let keys = Template::find_keys("${this} is a ${test} ${with some keys}").unwrap();
+    /// assert_eq!(keys, vec!["this", "test", "with some keys"]);
+    /// ```
+    pub fn find_keys(input: &str) -> Result<Vec<&str>> {
+        Ok(Self::find_markers(input.as_bytes(), &Opts::default())?
+            .into_iter()
+            .map(|(_, key)| &input[key])
+            .collect())
+    }
+
+    /// Count the keys in the input, at compile time
+    ///
+    /// This counts each `${key}` and anonymous `${}`, including repeated keys, as parsed with the
+    /// default [`Opts`](./struct.Opts.html). Blocks aren't counted. The input isn't validated, so this
+    /// is only meaningful for templates that parse.
+    /// ```
+    /// # use markings::Template;
+    /// const KEYS: usize = Template::count_keys("${?admin}${name}${/}: ${} of ${total}");
+    /// let values: [&str; KEYS] = ["bob", "1", "2"];
+    /// # assert_eq!(KEYS, 3);
+    /// ```
+    pub const fn count_keys(input: &str) -> usize {
+        let bytes = input.as_bytes();
+        let mut count = 0;
+        let mut pos = 0;
+        while pos + 1 < bytes.len() {
+            if bytes[pos] != b'$' || bytes[pos + 1] != b'{' {
+                pos += 1;
+                continue;
+            }
+
+            let start = pos + 2;
+            let mut end = start;
+            while end < bytes.len() && bytes[end] != b'}' {
+                end += 1;
+            }
+            if end == bytes.len() {
+                break;
+            }
+            if !is_block_marker(bytes, start, end) {
+                count += 1;
+            }
+            pos = end + 1;
+        }
+        count
+    }
+
+    // where the markers of a source are, and its `%%` escapes with `percent_keys`
+    fn syntax(&self, source: &str) -> Option<(Vec<Range<usize>>, Vec<usize>)> {
+        let markers = Self::find_markers(source.as_bytes(), &self.opts).ok()?;
+        let markers = markers
+            .into_iter()
+            .map(|(marker, _)| marker)
+            .collect::<Vec<_>>();
+        let mut escapes = vec![];
+        if self.opts.percent_keys {
+            let bytes = source.as_bytes();
+            let (mut pos, mut next) = (0, markers.iter().peekable());
+            while pos + 1 < bytes.len() {
+                match next.peek() {
+                    Some(marker) if marker.start <= pos => {
+                        pos = pos.max(next.next().map_or(pos, |m| m.end))
+                    }
+                    _ if &bytes[pos..pos + 2] == b"%%" => {
+                        escapes.push(pos);
+                        pos += 2;
+                    }
+                    _ => pos += 1,
+                }
+            }
+        }
+        Some((markers, escapes))
+    }
+
+    // all of the interesting characters are ascii, so the input is scanned as bytes
+    fn find_markers(bytes: &[u8], opts: &Opts) -> Result<Vec<Marker>> {
+        let mut heads = Positions::new();
+        let mut tails = Positions::new();
+        let mut bare_markers = vec![];
+
+        let mut last = None;
+        let mut next = 0;
+        while let Some(offset) = find_special(&bytes[next..], opts.percent_keys) {
+            let pos = next + offset;
+            next = pos + 1;
+
+            match bytes[pos] {
+                b'$' if bytes.get(pos + 1) == Some(&b'{') => {
+                    last.replace(pos);
+                    heads.push(pos);
+                    next += 1;
+                }
+                b'$' if opts.bare_dollar_keys && last.is_none() => {
+                    let start = pos + 1;
+                    let len = bytes[start..]
+                        .iter()
+                        .enumerate()
+                        .take_while(|&(i, &b)| {
+                            b == b'_' || b.is_ascii_alphabetic() || (i > 0 && b.is_ascii_digit())
+                        })
+                        .count();
+                    if len > 0 {
+                        bare_markers.push((pos..start + len, start..start + len));
+                        next = start + len;
+                    }
+                }
+                // `%%` is an escaped `%`, which is unescaped when the template is rendered
+                b'%' if bytes.get(pos + 1) == Some(&b'%') && last.is_none() => next += 1,
+                b'%' if last.is_none() => {
+                    let start = pos + 1;
+                    let len = bytes[start..]
+                        .iter()
+                        .enumerate()
+                        .take_while(|&(i, &b)| {
+                            b == b'_'
+                                || b.is_ascii_alphabetic()
+                                || (i > 0
+                                    && (b.is_ascii_digit() || matches!(b, b'.' | b'(' | b')')))
+                        })
+                        .count();
+                    if len > 0 && bytes.get(start + len) == Some(&b'%') {
+                        bare_markers.push((pos..start + len + 1, start..start + len));
+                        next = start + len + 1;
+                    }
+                }
+                b'{' if last.is_some() => {
+                    return Err(ErrorKind::NestedTemplate { pos }.into());
+                }
+                b'}' if last.is_some() => {
+                    tails.push(pos);
+                    last.take();
+                }
+                _ => {}
+            }
+        }
+
+        if heads.len() != tails.len() {
+            return Err(ErrorKind::MismatchedBraces {
+                open: heads.len(),
+                close: tails.len(),
+            }
+            .into());
+        }
+
+        tails.reverse();
+
+        let mut markers = Vec::with_capacity(heads.len());
+        for head in heads {
+            let tail = tails.pop().ok_or(ErrorKind::ExpectedClosing { head })?;
+            if tail > head {
+                markers.push((head..tail + 1, head + 2..tail));
+            } else {
+                return Err(ErrorKind::ExpectedOpening { tail }.into());
+            }
+        }
+
+        if !tails.is_empty() {
+            return Err(ErrorKind::MismatchedBraces {
+                open: 0,
+                close: tails.len(),
+            }
+            .into());
+        }
+
+        if !bare_markers.is_empty() {
+            markers.extend(bare_markers);
+            markers.sort_by_key(|(marker, _)| marker.start);
+        }
+        Ok(markers)
+    }
+}
+
+// a block that is open while rendering
+struct OpenBlock {
+    // whether the current branch is being rendered
+    active: bool,
+    // whether any branch has been rendered
+    taken: bool,
+    // whether it is a `${@key}` block, with a prefix for its keys
+    scoped: bool,
+}
+
+impl OpenBlock {
+    fn new(active: bool, scoped: bool) -> Self {
+        Self {
+            active,
+            taken: active,
+            scoped,
+        }
+    }
+
+    // the prefix for a `${@key}` block nested in the blocks with these prefixes
+    fn scope(scopes: &[String], key: &str) -> String {
+        match scopes.last() {
+            Some(scope) => format!("{}.{}", scope, key),
+            None => key.to_string(),
+        }
+    }
+}
+
+// renames the key in a marker, keeping any formatting around it (e.g. the spaces in `${ key }`)
+fn rename_marker(raw: &str, name: &str, new: &str) -> Option<String> {
+    if let Some(inner) = raw.strip_prefix("${").and_then(|raw| raw.strip_suffix('}')) {
+        let pos = inner.find(name).filter(|_| !name.is_empty())?;
+        let (head, tail) = (&inner[..pos], &inner[pos + name.len()..]);
+        return Some(format!("${{{}{}{}}}", head, new, tail));
+    }
+
+    let mut chars = new.chars();
+    let ident = chars
+        .next()
+        .filter(|&c| c == '_' || c.is_ascii_alphabetic())
+        .is_some()
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric());
+    if ident {
+        Some(format!("${}", new))
+    } else {
+        None
+    }
+}
+
+// the range of a marker, and the range of its key
+type Marker = (Range<usize>, Range<usize>);
+
+/// Templates display as their source text
+impl<'a> std::fmt::Display for Template<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => f.write_str(literal)?,
+                Segment::Key { raw, .. }
+                | Segment::Block { raw, .. }
+                | Segment::Expr { raw, .. } => f.write_str(raw)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// whether `value` matches the annotation of its key. unknown annotations always match
+fn spec_matches(spec: &str, value: &str) -> bool {
+    Spec::parse(spec).is_none_or(|spec| spec.matches(value))
+}
+
+// the kind of value an annotation, like the `number` in `${amount!number}`, checks for
+#[derive(Copy, Clone)]
+enum Spec {
+    Kind(ValueKind),
+    Email,
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Option<Self> {
+        let kind = match spec {
+            "number" => ValueKind::Float,
+            "int" => ValueKind::Int,
+            "bool" => ValueKind::Bool,
+            "email" => return Some(Spec::Email),
+            _ => return None,
+        };
+        Some(Spec::Kind(kind))
+    }
+
+    fn matches(self, value: &str) -> bool {
+        match self {
+            Spec::Kind(kind) => kind.matches(value),
+            Spec::Email => value.split_once('@').is_some_and(|(user, domain)| {
+                let domain = domain.split('.').collect::<Vec<_>>();
+                !user.is_empty()
+                    && !user.contains(char::is_whitespace)
+                    && domain.len() > 1
+                    && domain
+                        .iter()
+                        .all(|part| !part.is_empty() && !part.contains(['@', ' ']))
+            }),
+        }
+    }
+}
+
+// whether the marker text in `bytes[start..end]` is a block, like `BlockKind::split` at compile time
+const fn is_block_marker(bytes: &[u8], start: usize, end: usize) -> bool {
+    const fn has_prefix(bytes: &[u8], start: usize, end: usize, prefix: &[u8]) -> bool {
+        if end - start < prefix.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < prefix.len() {
+            if bytes[start + i] != prefix[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    if start == end {
+        return false;
+    }
+    match bytes[start] {
+        b'?' | b'/' => true,
+        b'@' => end - start > 1,
+        b':' => {
+            has_prefix(bytes, start, end, b":?")
+                || (end - start == 5 && has_prefix(bytes, start, end, b":else"))
+        }
+        _ if has_prefix(bytes, start, end, b"block ") => {
+            let mut i = start + 6;
+            while i < end {
+                if !bytes[i].is_ascii_whitespace() {
+                    return true;
+                }
+                i += 1;
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+// finds the next `$`, `{` or `}`, or `%` with percent keys
+#[cfg(feature = "memchr")]
+fn find_special(bytes: &[u8], percent: bool) -> Option<usize> {
+    match percent {
+        true => bytes
+            .iter()
+            .position(|&b| matches!(b, b'$' | b'{' | b'}' | b'%')),
+        false => memchr::memchr3(b'$', b'{', b'}', bytes),
+    }
+}
+
+// finds the next `$`, `{` or `}`, or `%` with percent keys
+#[cfg(not(feature = "memchr"))]
+fn find_special(bytes: &[u8], percent: bool) -> Option<usize> {
+    bytes
+        .iter()
+        .position(|&b| b == b'$' || b == b'{' || b == b'}' || (percent && b == b'%'))
+}
+
+impl<'a> std::ops::Add for Template<'a> {
+    type Output = Result<Template<'a>>;
+    fn add(self, other: Self) -> Self::Output {
+        self.concat(other)
+    }
+}
+
+impl<'a> std::ops::Add<Template<'a>> for Result<Template<'a>> {
+    type Output = Self;
+    fn add(self, other: Template<'a>) -> Self::Output {
+        self?.concat(other)
+    }
+}
+
+/// A key that was substituted during [`Template::apply_captured`](./struct.Template.html#method.apply_captured)
+#[derive(Clone, PartialEq)]
+pub struct Capture {
+    value: String,
+    ranges: Vec<Range<usize>>,
+    secret: bool,
+}
+
+impl Capture {
+    /// The value that was substituted for the key
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The byte ranges in the output where the value was written, in order
+    pub fn ranges(&self) -> &[Range<usize>] {
+        &self.ranges
+    }
+}
+
+/// Values from [`Args::with_secret`](./struct.Args.html#method.with_secret) are redacted
+impl std::fmt::Debug for Capture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Capture");
+        match self.secret {
+            true => debug.field("value", &Secret(&self.value)),
+            false => debug.field("value", &self.value),
+        };
+        debug.field("ranges", &self.ranges).finish()
+    }
+}
+
+/// A wrapper for a value that should never be shown in `Debug` output
+///
+/// ```
+/// # use markings::Secret;
+/// let token = Secret::new("hunter2");
+/// assert_eq!(format!("{:?}", token), "<redacted>");
+/// assert_eq!(*token.expose(), "hunter2");
+/// ```
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    /// Wrap a value
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// The wrapped value
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Unwrap the value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// A report of how the keys of a template were used by [`Template::apply_with_report`](./struct.Template.html#method.apply_with_report)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ApplyReport {
+    keys: HashMap<String, KeyUsage>,
+    unused: Vec<String>,
+}
+
+impl ApplyReport {
+    /// Usage for a key in the template
+    pub fn get(&self, key: &str) -> Option<KeyUsage> {
+        self.keys.get(key).copied()
+    }
+
+    /// Usage for every key in the template
+    pub fn iter(&self) -> impl Iterator<Item = (&str, KeyUsage)> + '_ {
+        self.keys.iter().map(|(key, usage)| (&**key, *usage))
+    }
+
+    /// Keys in the template that weren't replaced by any argument
+    pub fn unfilled_keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.iter()
+            .filter(|(_, usage)| usage.replaced == 0)
+            .map(|(key, _)| key)
+    }
+
+    /// Arguments that didn't match any key in the template, sorted
+    pub fn unused_args(&self) -> &[String] {
+        &self.unused
+    }
+
+    /// The total bytes written for all keys
+    pub fn total_bytes(&self) -> usize {
+        self.keys.values().map(|usage| usage.bytes).sum()
+    }
+}
+
+/// How a single key was used in an [`ApplyReport`](./struct.ApplyReport.html)
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct KeyUsage {
+    replaced: usize,
+    bytes: usize,
+}
+
+impl KeyUsage {
+    /// How many occurrences of the key were replaced
+    pub fn replaced(&self) -> usize {
+        self.replaced
+    }
+
+    /// How many bytes were written for the key
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+/// `Opts` are a set of options to configure how a template will be **parsed** and **applied**
+///
+/// ### The default options would fail if
+/// - there is an empty template (e.g. no replacement keys)
+/// - there are duplicate keys
+/// - apply will fail if the exact keys aren't applied
+///
+/// ## default options
+/// ```
+/// # use markings::{Template, Opts};
+/// let input = "this is a ${name}.";
+/// let template = Template::parse(&input, Opts::default()).unwrap();
+/// ```
+/// ## various options
+/// ```
+/// # use markings::{Template, Opts};
+/// // this will allow these options in the parsing/application
+/// let opts = Opts::default()
+///     .optional_keys()  // optional keys -- args aren't required to match the template keys
+///     .duplicate_keys() // duplicate keys -- duplicate keys in the template will use the same argument
+///     .empty_template() // templates can just be strings that act as an "identity"
+///     .case_insensitive_keys() // case insensitive keys -- `${Name}` and `${name}` are the same key
+///     .build();
+///
+/// let input = "this is a ${name}.";
+/// let template = Template::parse(&input, opts).unwrap();
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Opts {
+    optional_keys: bool,
+    duplicate_keys: bool,
+    empty_template: bool,
+    case_insensitive_keys: bool,
+    bare_dollar_keys: bool,
+    percent_keys: bool,
+    trim_key_whitespace: bool,
+    validate_key_specs: bool,
+    expressions: bool,
+    max_output_len: Option<usize>,
+    max_template_len: Option<usize>,
+    max_keys: Option<usize>,
+    max_key_len: Option<usize>,
+    max_depth: Option<usize>,
+    arg_uses: Option<usize>,
+    truthiness: Truthiness,
+    color: ColorChoice,
+    escape: Escape,
+    sql_dialect: SqlDialect,
+    filters: Option<std::sync::Arc<Filters>>,
+    allowed_filters: Option<&'static [&'static str]>,
+    allowed_keys: Option<&'static [&'static str]>,
+    sandbox: Option<Sandbox>,
+    on_filter_error: OnFilterError,
+    duplicate_args: DuplicateArgs,
+    #[cfg(feature = "unicode")]
+    normalize_keys: Option<Normalization>,
+}
+
+impl Opts {
+    /// The default options, which reject anything unexpected
+    ///
+    /// Templates need keys, keys can't be repeated, and every argument has to match a key
+    pub fn strict() -> Self {
+        Self::default()
+    }
+
+    /// Options that accept any template and arguments
+    ///
+    /// Keys are optional, can be repeated, are matched case insensitively and can be surrounded by whitespace.
+    /// Templates don't need keys
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("${ Name } and ${name}", Opts::lenient()).unwrap();
+    /// let args = Args::new().with("name", "bob").with("unused", true);
+    /// assert_eq!(template.apply(&args).unwrap(), "bob and bob");
+    /// ```
+    pub fn lenient() -> Self {
+        Self::default()
+            .optional_keys()
+            .duplicate_keys()
+            .empty_template()
+            .case_insensitive_keys()
+            .trim_key_whitespace()
+    }
+
+    /// Options for templates of shell commands
+    ///
+    /// Keys can be written like shell variables, `$name`, and every value is quoted as a shell word.
+    /// Keys are optional and can be repeated, and templates don't need keys
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("cp $from ${to}", Opts::shell_like()).unwrap();
+    /// let args = Args::new().with("from", "my file").with("to", "it's");
+    /// assert_eq!(template.apply(&args).unwrap(), r"cp 'my file' 'it'\''s'");
+    /// ```
+    pub fn shell_like() -> Self {
+        Self::default()
+            .optional_keys()
+            .duplicate_keys()
+            .empty_template()
+            .bare_dollar_keys()
+            .escape(Escape::Shell)
+    }
+
+    /// Allow optional keys
+    ///
+    /// Keys found in the template application don't have to appear in the template, and keys in the
+    /// template don't need a value. Keys without a value are written as is
+    ///
+    /// Otherwise, rendering a key without a value is a `MissingKeys` error. Keys in branches that
+    /// aren't rendered don't need a value
+    #[must_use]
+    pub fn optional_keys(mut self) -> Self {
+        self.optional_keys = !self.optional_keys;
+        self
+    }
+
+    /// Allow duplicate keys
+    ///
+    /// Multiple keys in the template will be replaced by the same argument
+    #[must_use]
+    pub fn duplicate_keys(mut self) -> Self {
+        self.duplicate_keys = !self.duplicate_keys;
+        self
+    }
+
+    /// Allows for an empty template -- e.g. a template without any args
+    ///
+    /// When args are applied to this, the original string is returned
+    #[must_use]
+    pub fn empty_template(mut self) -> Self {
+        self.empty_template = !self.empty_template;
+        self
+    }
+
+    /// Match keys without regard to case
+    ///
+    /// `${Name}`, `${NAME}` and `${name}` will all be replaced by an argument named `name`
+    #[must_use]
+    pub fn case_insensitive_keys(mut self) -> Self {
+        self.case_insensitive_keys = !self.case_insensitive_keys;
+        self
+    }
+
+    /// Also allow shell-style keys without braces, like `$NAME`
+    ///
+    /// The key is the longest run of ascii letters, digits and underscores after the `$`, and can't start with a digit
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let opts = Opts::default().bare_dollar_keys().build();
+    /// let template = Template::parse("$HOME/${dir}", opts).unwrap();
+    /// let args = Args::new().with("HOME", "/home/bob").with("dir", "src");
+    /// assert_eq!(template.apply(&args).unwrap(), "/home/bob/src");
+    /// ```
+    #[must_use]
+    pub fn bare_dollar_keys(mut self) -> Self {
+        self.bare_dollar_keys = !self.bare_dollar_keys;
+        self
+    }
+
+    /// Also allow Windows-style keys between percent signs, like `%NAME%`, and escape `%` as `%%`
+    ///
+    /// The key is a run of ascii letters, digits, underscores, dots and parentheses, like `%ProgramFiles(x86)%`,
+    /// and can't start with a digit. A `%%` is written as a single `%`, and any other `%` is written as it is.
+    /// The template still displays with the escapes
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let opts = Opts::default().percent_keys();
+    /// let template = Template::parse("cd %USERPROFILE%\\${dir} && echo 100%%", opts).unwrap();
+    /// let args = Args::new().with("USERPROFILE", r"C:\Users\bob").with("dir", "src");
+    /// assert_eq!(template.apply(&args).unwrap(), r"cd C:\Users\bob\src && echo 100%");
+    /// assert_eq!(template.to_string(), r"cd %USERPROFILE%\${dir} && echo 100%%");
+    /// ```
+    #[must_use]
+    pub fn percent_keys(mut self) -> Self {
+        self.percent_keys = !self.percent_keys;
+        self
+    }
+
+    /// Trim whitespace around keys while parsing
+    ///
+    /// `${ name }` will be treated as `${name}`. The template still displays with the original spacing
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let opts = Opts::default().trim_key_whitespace().build();
+    /// let template = Template::parse("hello ${ name }", opts).unwrap();
+    /// assert_eq!(template.apply(&Args::new().with("name", "bob")).unwrap(), "hello bob");
+    /// ```
+    #[must_use]
+    pub fn trim_key_whitespace(mut self) -> Self {
+        self.trim_key_whitespace = !self.trim_key_whitespace;
+        self
+    }
+
+    /// Check the values of annotated keys while applying
+    ///
+    /// A key can be annotated with the kind of value it expects, like `${amount!number}`. These annotations are checked:
+    /// * `number`, a decimal number
+    /// * `int`, a whole number
+    /// * `bool`, `true` or `false`
+    /// * `email`, an email address, like `user@example.com`
+    ///
+    /// Other annotations are an `UnknownSpec` error when parsing. Without this option, annotations are never checked, but are
+    /// still available from [`Template::key_specs`](./struct.Template.html#method.key_specs).
+    /// A value that doesn't match is an `InvalidValue` error
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let opts = Opts::default().validate_key_specs();
+    /// let template = Template::parse("total: ${amount!number}", opts).unwrap();
+    /// assert_eq!(template.apply(&Args::new().with("amount", 4.5)).unwrap(), "total: 4.5");
+    /// assert!(template.apply(&Args::new().with("amount", "lots")).is_err());
+    /// ```
+    #[must_use]
+    pub fn validate_key_specs(mut self) -> Self {
+        self.validate_key_specs = !self.validate_key_specs;
+        self
+    }
+
+    /// Evaluate arithmetic in markers, like `${count + 1}` or `${price * quantity}`
+    ///
+    /// A marker is an expression when it uses any of `+ - * / % ( )`, so keys with these characters can't be used.
+    /// Expressions are made of numbers and keys whose values are numbers. Whole numbers stay whole, unless they don't
+    /// divide evenly. There are no function calls, and nothing else can be evaluated
+    ///
+    /// A value that isn't a number is an `InvalidValue` error, and dividing by zero or overflowing is an `Arithmetic` error.
+    /// Expressions whose keys have no value are rendered as their marker, like other keys
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let opts = Opts::default().expressions();
+    /// let template = Template::parse("${count + 1} items for ${price * count}", opts).unwrap();
+    /// let args = Args::new().with("count", 3).with("price", 1.5);
+    /// assert_eq!(template.apply(&args).unwrap(), "4 items for 4.5");
+    /// ```
+    #[must_use]
+    pub fn expressions(mut self) -> Self {
+        self.expressions = !self.expressions;
+        self
+    }
+
+    /// Limit the length of the output, in bytes
+    ///
+    /// Applying a template will fail with `OutputTooLarge` once the output would exceed this
+    #[must_use]
+    pub fn max_output_len(mut self, limit: usize) -> Self {
+        self.max_output_len = Some(limit);
+        self
+    }
+
+    /// Limit the length of the template source, in bytes
+    ///
+    /// Parsing a longer template will fail with `TemplateTooLong`
+    #[must_use]
+    pub fn max_template_len(mut self, limit: usize) -> Self {
+        self.max_template_len = Some(limit);
+        self
+    }
+
+    /// Limit the number of keys in the template, counting duplicates
+    ///
+    /// Parsing a template with more keys will fail with `TooManyKeys`
+    #[must_use]
+    pub fn max_keys(mut self, limit: usize) -> Self {
+        self.max_keys = Some(limit);
+        self
+    }
+
+    /// Limit the length of each key in the template, in bytes
+    ///
+    /// Parsing a template with a longer key will fail with `KeyTooLong`
+    #[must_use]
+    pub fn max_key_len(mut self, limit: usize) -> Self {
+        self.max_key_len = Some(limit);
+        self
+    }
+
+    /// Limit how deeply blocks nest in each other, and how deeply expressions nest, counting their operators and parentheses
+    ///
+    /// This defaults to 64. Parsing a block or an expression that nests deeper, like `${((((a))))}` with a limit of 3,
+    /// will fail with `UnsupportedSyntax`
+    /// ```
+    /// # use markings::{Template, Opts, ErrorKind};
+    /// let opts = Opts::default().expressions().optional_keys().max_depth(3);
+    /// assert!(Template::parse("${(a + 1) * 2}", opts.clone()).is_ok());
+    /// assert!(Template::parse("${?a}${?b}${?c}${c}${/}${/}${/}", opts.clone()).is_ok());
+    ///
+    /// let err = Template::parse("${((((a))))}", opts.clone()).unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 0 });
+    ///
+    /// let err = Template::parse("${?a}${?b}${?c}${?d}${d}${/}${/}${/}${/}", opts).unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 15 });
+    /// ```
+    #[must_use]
+    pub fn max_depth(mut self, limit: usize) -> Self {
+        self.max_depth = Some(limit);
+        self
+    }
+
+    /// Require each argument to replace exactly `count` keys when applied
+    ///
+    /// This is useful with `duplicate_keys`, when repeated keys are wanted in some templates
+    /// but each argument should still be used a known number of times.
+    ///
+    /// Applying arguments that are used a different number of times will fail with `ArgUseCount`
+    /// ```
+    /// # use markings::{Template, Args, Opts, ErrorKind};
+    /// let opts = Opts::default().duplicate_keys().optional_keys().arg_uses(1).build();
+    /// let args = Args::new().with("name", "bob");
+    ///
+    /// let template = Template::parse("hello ${name}", opts.clone()).unwrap();
+    /// assert_eq!(template.apply(&args).unwrap(), "hello bob");
+    ///
+    /// let template = Template::parse("hello ${name}, ${name}", opts).unwrap();
+    /// let err = template.apply(&args).unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::ArgUseCount { key: "name".into(), expected: 1, found: 2 });
+    /// ```
+    #[must_use]
+    pub fn arg_uses(mut self, count: usize) -> Self {
+        self.arg_uses = Some(count);
+        self
+    }
+
+    /// Set how values are checked by conditional blocks, like `${?key}`
+    /// ```
+    /// # use markings::{Template, Args, Opts, Truthiness};
+    /// let opts = Opts::default().truthiness(Truthiness::Custom(|val| val == "yes"));
+    /// let template = Template::parse("${?ok}ok${/}", opts).unwrap();
+    /// assert_eq!(template.apply(&Args::new().with("ok", "yes")).unwrap(), "ok");
+    /// assert_eq!(template.apply(&Args::new().with("ok", true)).unwrap(), "");
+    /// ```
+    #[must_use]
+    pub fn truthiness(mut self, truthiness: Truthiness) -> Self {
+        self.truthiness = truthiness;
+        self
+    }
+
+    /// Set when the color filters, like `${name|red}`, write ANSI escape codes
+    ///
+    /// This defaults to [`ColorChoice::Auto`](./enum.ColorChoice.html)
+    #[must_use]
+    pub fn color(mut self, choice: ColorChoice) -> Self {
+        self.color = choice;
+        self
+    }
+
+    /// Escape every value that is written to the output
+    ///
+    /// Values are escaped after their filters, so a filter like `replace` can't undo the escaping, and colors are added
+    /// around the escaped value. Keys whose last filter escapes them, like `${name|md}`, are only escaped by their filter
+    /// ```
+    /// # use markings::{Template, Args, Opts, Escape};
+    /// let opts = Opts::default().escape(Escape::Markdown);
+    /// let template = Template::parse("**${name}**", opts).unwrap();
+    /// assert_eq!(template.apply(&Args::new().with("name", "*bob*")).unwrap(), r"**\*bob\***");
+    /// ```
+    #[must_use]
+    pub fn escape(mut self, escape: Escape) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Set the SQL dialect used by the `sql_ident` and `sql_str` filters
+    ///
+    /// This defaults to [`SqlDialect::Ansi`](./enum.SqlDialect.html)
+    /// ```
+    /// # use markings::{Template, Args, Opts, SqlDialect};
+    /// let opts = Opts::default().sql_dialect(SqlDialect::MySql);
+    /// let template = Template::parse("SELECT * FROM ${table|sql_ident}", opts).unwrap();
+    /// let args = Args::new().with("table", "orders");
+    /// assert_eq!(template.apply(&args).unwrap(), "SELECT * FROM `orders`");
+    /// ```
+    #[must_use]
+    pub fn sql_dialect(mut self, dialect: SqlDialect) -> Self {
+        self.sql_dialect = dialect;
+        self
+    }
+
+    /// Use the custom filters from a registry, see [`Filters`](./struct.Filters.html)
+    ///
+    /// The registry is shared by every clone of the options
+    #[must_use]
+    pub fn filters(mut self, filters: impl Into<std::sync::Arc<Filters>>) -> Self {
+        self.filters = Some(filters.into());
+        self
+    }
+
+    /// Only allow the filters with these names, built-in or custom
+    ///
+    /// Templates using any other filter fail to parse with `ForbiddenFilter`
+    /// ```
+    /// # use markings::{Template, Opts, ErrorKind};
+    /// let opts = Opts::default().allowed_filters(&["bold", "truncate"]);
+    /// assert!(Template::parse("${name|truncate:10|bold}", opts.clone()).is_ok());
+    ///
+    /// let err = Template::parse("${name|slug}", opts).unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::ForbiddenFilter { name: "slug".into() });
+    /// ```
+    #[must_use]
+    pub fn allowed_filters(mut self, names: &'static [&'static str]) -> Self {
+        self.allowed_filters = Some(names);
+        self
+    }
+
+    /// Only allow the keys with these names, for templates written by users who should only see some values
+    ///
+    /// Templates using any other key, in a key, a block, an expression or a fallback, fail to parse with `ForbiddenKey`.
+    /// Keys are compared as they are written, so keys inside of a `${@key}` block need to be allowed without their scope,
+    /// and anonymous keys are named by their position, like `0`
+    /// ```
+    /// # use markings::{Template, Opts, ErrorKind};
+    /// let opts = Opts::default().allowed_keys(&["user", "channel", "count"]);
+    /// assert!(Template::parse("${user} joined ${channel}${?count} (${count})${/}", opts.clone()).is_ok());
+    ///
+    /// let err = Template::parse("${user} has ${password}", opts).unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::ForbiddenKey { key: "password".into(), pos: 12 });
+    /// assert_eq!(err.span(), Some(12..23));
+    /// ```
+    #[must_use]
+    pub fn allowed_keys(mut self, keys: &'static [&'static str]) -> Self {
+        self.allowed_keys = Some(keys);
+        self
+    }
+
+    /// Set what happens when a custom filter fails while rendering
+    ///
+    /// This defaults to [`OnFilterError::Fail`](./enum.OnFilterError.html)
+    #[must_use]
+    pub fn on_filter_error(mut self, policy: OnFilterError) -> Self {
+        self.on_filter_error = policy;
+        self
+    }
+
+    /// Set which value is used when an argument was given more than once, like `.with("a", 1).with("a", 2)`
+    ///
+    /// This defaults to [`DuplicateArgs::LastWins`](./enum.DuplicateArgs.html). With `Error`, args that match
+    /// the same key, like `Name` and `name` with case insensitive keys, are also an error
+    /// ```
+    /// # use markings::{Template, Args, Opts, DuplicateArgs, ErrorKind};
+    /// let args = Args::new().with("name", "bob").with("name", "alice");
+    ///
+    /// let opts = Opts::default().duplicate_args(DuplicateArgs::FirstWins);
+    /// let template = Template::parse("hello ${name}", opts).unwrap();
+    /// assert_eq!(template.apply(&args).unwrap(), "hello bob");
+    ///
+    /// let opts = Opts::default().duplicate_args(DuplicateArgs::Error);
+    /// let template = Template::parse("hello ${name}", opts).unwrap();
+    /// let err = template.apply(&args).unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::DuplicateArg { key: "name".into() });
+    /// ```
+    #[must_use]
+    pub fn duplicate_args(mut self, policy: DuplicateArgs) -> Self {
+        self.duplicate_args = policy;
+        self
+    }
 
-            if ch == '}' && last.is_some() {
-                tails.push(pos);
-                last.take();
-            }
+    /// Normalize keys to a unicode normalization form before matching them
+    ///
+    /// Keys in both the template and the args are compared in this form, so a `NFD` encoded key
+    /// in the template will match a `NFC` encoded key in the args
+    #[cfg(feature = "unicode")]
+    #[must_use]
+    pub fn normalize_keys(mut self, form: Normalization) -> Self {
+        self.normalize_keys = Some(form);
+        self
+    }
+
+    /// Construct the option set
+    ///
+    /// The setters already return the options by value, so this is only needed for older code
+    #[must_use]
+    pub fn build(self) -> Self {
+        self
+    }
+
+    // enables an option by the name of its setter, with a value for limits. used by the headers of templates
+    pub(crate) fn with_option(mut self, name: &str, value: Option<&str>) -> Option<Self> {
+        let limit = || value.and_then(|value| value.parse().ok());
+        match (name, value) {
+            ("optional_keys", None) => self.optional_keys = true,
+            ("duplicate_keys", None) => self.duplicate_keys = true,
+            ("empty_template", None) => self.empty_template = true,
+            ("case_insensitive_keys", None) => self.case_insensitive_keys = true,
+            ("bare_dollar_keys", None) => self.bare_dollar_keys = true,
+            ("percent_keys", None) => self.percent_keys = true,
+            ("trim_key_whitespace", None) => self.trim_key_whitespace = true,
+            ("max_output_len", _) => self.max_output_len = Some(limit()?),
+            ("max_template_len", _) => self.max_template_len = Some(limit()?),
+            ("max_keys", _) => self.max_keys = Some(limit()?),
+            ("max_key_len", _) => self.max_key_len = Some(limit()?),
+            ("max_depth", _) => self.max_depth = Some(limit()?),
+            ("arg_uses", _) => self.arg_uses = Some(limit()?),
+            _ => return None,
+        }
+        // the limits of a sandbox can't be raised
+        if let Some(sandbox) = self.sandbox {
+            self = self.sandbox(sandbox);
         }
+        Some(self)
+    }
 
-        if heads.len() != tails.len() {
-            return Err(Error::MismatchedBraces {
-                open: heads.len(),
-                close: tails.len(),
-            });
+    // how deeply markers can nest
+    pub(crate) fn depth_limit(&self) -> usize {
+        self.max_depth.unwrap_or(MAX_DEPTH)
+    }
+
+    // whether values are escaped even without a filter
+    pub(crate) fn escapes(&self) -> bool {
+        self.escape != Escape::None
+    }
+
+    fn folds_keys(&self) -> bool {
+        #[cfg(feature = "unicode")]
+        {
+            if self.normalize_keys.is_some() {
+                return true;
+            }
         }
+        self.case_insensitive_keys
+    }
 
-        tails.reverse();
+    // the text a literal is written as, without the escapes of percent keys
+    fn unescape<'l>(&self, literal: &'l str) -> Cow<'l, str> {
+        match self.percent_keys && literal.contains("%%") {
+            true => Cow::Owned(literal.replace("%%", "%")),
+            false => Cow::Borrowed(literal),
+        }
+    }
 
-        let mut keys = Vec::with_capacity(heads.len());
-        for head in heads {
-            let tail = tails.pop().ok_or_else(|| Error::ExpectedClosing { head })?;
-            if tail > head {
-                keys.push(&input[head + 2..tail]);
-            } else {
-                return Err(Error::ExpectedOpening { tail });
+    fn key_eq(&self, left: &str, right: &str) -> bool {
+        #[cfg(feature = "unicode")]
+        {
+            if let Some(form) = self.normalize_keys {
+                return self.chars_eq(form.chars(left), form.chars(right));
             }
         }
+        if !self.case_insensitive_keys {
+            return left == right;
+        }
+        self.chars_eq(left.chars(), right.chars())
+    }
 
-        if !tails.is_empty() {
-            return Err(Error::MismatchedBraces {
-                open: 0,
-                close: tails.len(),
-            });
+    fn chars_eq(
+        &self,
+        left: impl Iterator<Item = char>,
+        right: impl Iterator<Item = char>,
+    ) -> bool {
+        if !self.case_insensitive_keys {
+            return left.eq(right);
         }
+        left.flat_map(char::to_lowercase)
+            .eq(right.flat_map(char::to_lowercase))
+    }
 
-        Ok(keys)
+    // errors with the index of the offending key, if there is one
+    fn validate(&self, keys: &State<'_>) -> Result<(), (ErrorKind, Option<usize>)> {
+        if !self.empty_template && !keys.has_keys() {
+            return Err((ErrorKind::EmptyTemplate, None));
+        }
+        if let Some(limit) = self.max_keys {
+            if keys.keys.len() > limit {
+                return Err((ErrorKind::TooManyKeys { limit }, None));
+            }
+        }
+        if let Some(limit) = self.max_key_len {
+            if let Some(index) = keys.keys.iter().position(|key| key.len() > limit) {
+                return Err((ErrorKind::KeyTooLong { limit }, Some(index)));
+            }
+        }
+        if !self.duplicate_keys {
+            if let Some(index) = keys.duplicate(self) {
+                return Err((ErrorKind::DuplicateKeys, Some(index)));
+            }
+        }
+        Ok(())
     }
 }
 
-/// `Opts` are a set of options to configure how a template will be **parsed** and **applied**
-///
-/// ### The default options would fail if
-/// - there is an empty template (e.g. no replacement keys)
-/// - there are duplicate keys
-/// - apply will fail if the exact keys aren't applied
-///
-/// ## default options
-/// ```
-/// # use markings::{Template, Opts};
-/// let input = "this is a ${name}.";
-/// let template = Template::parse(&input, Opts::default()).unwrap();
-/// ```
-/// ## various options
-/// ```
-/// # use markings::{Template, Opts};
-/// // this will allow these options in the parsing/application
-/// let opts = Opts::default()
-///     .optional_keys()  // optional keys -- args aren't required to match the template keys
-///     .duplicate_keys() // duplicate keys -- duplicate keys in the template will use the same argument
-///     .empty_template() // templates can just be strings that act as an "identity"
-///     .build();
+/// Which value is used when layers of arguments have the same key, see [`Template::apply_all_args`](./struct.Template.html#method.apply_all_args)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Precedence {
+    /// The value from the first layer with the key is used
+    First,
+    /// The value from the last layer with the key is used, so later layers override earlier ones
+    #[default]
+    Last,
+}
+
+/// Which value is used when an argument was given more than once, see [`Opts::duplicate_args`](./struct.Opts.html#method.duplicate_args)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DuplicateArgs {
+    /// The last value is used
+    #[default]
+    LastWins,
+    /// The first value is used
+    FirstWins,
+    /// Applying fails with a [`ErrorKind::DuplicateArg`](./enum.ErrorKind.html#variant.DuplicateArg) error
+    Error,
+}
+
+/// How conditional blocks decide whether a value is *truthy*
 ///
-/// let input = "this is a ${name}.";
-/// let template = Template::parse(&input, opts).unwrap();
-#[derive(Default, Copy, Clone, Debug, PartialEq)]
-pub struct Opts {
-    optional_keys: bool,
-    duplicate_keys: bool,
-    empty_template: bool,
+/// A key without a value is never truthy
+#[derive(Copy, Clone, Debug, Default)]
+pub enum Truthiness {
+    /// Values are truthy, except for an empty string, `0`, `false` and an empty list (`[]`)
+    #[default]
+    Default,
+    /// Values are truthy if the function returns `true`
+    Custom(fn(&str) -> bool),
 }
 
-impl Opts {
-    /// Allow optional keys
-    ///
-    /// Keys found in the template application don't have to appear in the template
-    pub fn optional_keys(&mut self) -> &mut Self {
-        self.optional_keys = !self.optional_keys;
-        self
+impl Truthiness {
+    /// Is this value truthy?
+    /// ```
+    /// # use markings::Truthiness;
+    /// assert!(Truthiness::Default.is_truthy("hello"));
+    /// assert!(!Truthiness::Default.is_truthy("false"));
+    /// ```
+    pub fn is_truthy(&self, value: &str) -> bool {
+        match self {
+            Truthiness::Default => !matches!(value, "" | "0" | "false" | "[]"),
+            Truthiness::Custom(func) => func(value),
+        }
     }
+}
 
-    /// Allow duplicate keys
-    ///
-    /// Multiple keys in the template will be replaced by the same argument
-    pub fn duplicate_keys(&mut self) -> &mut Self {
-        self.duplicate_keys = !self.duplicate_keys;
-        self
+// functions are compared by address, so this is only best effort
+impl PartialEq for Truthiness {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Truthiness::Default, Truthiness::Default) => true,
+            (Truthiness::Custom(left), Truthiness::Custom(right)) => {
+                std::ptr::fn_addr_eq(*left, *right)
+            }
+            _ => false,
+        }
     }
+}
 
-    /// Allows for an empty template -- e.g. a template without any args
-    ///
-    /// When args are applied to this, the original string is returned
-    pub fn empty_template(&mut self) -> &mut Self {
-        self.empty_template = !self.empty_template;
-        self
-    }
+impl Eq for Truthiness {}
 
-    /// Construct the option set
-    pub fn build(self) -> Self {
-        self
+impl std::hash::Hash for Truthiness {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        if let Truthiness::Custom(func) = self {
+            (*func as usize).hash(state)
+        }
     }
+}
 
-    fn validate(self, keys: &State<'_>) -> Result<(), Error> {
-        if !self.empty_template && !keys.has_keys() {
-            return Err(Error::EmptyTemplate);
+/// A unicode normalization form used for [`Opts::normalize_keys`](./struct.Opts.html#method.normalize_keys)
+#[cfg(feature = "unicode")]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Normalization {
+    /// Canonical decomposition, followed by canonical composition
+    NFC,
+    /// Canonical decomposition
+    NFD,
+    /// Compatibility decomposition, followed by canonical composition
+    NFKC,
+    /// Compatibility decomposition
+    NFKD,
+}
+
+#[cfg(feature = "unicode")]
+impl Normalization {
+    fn chars(self, input: &str) -> Normalized<'_> {
+        use unicode_normalization::UnicodeNormalization as _;
+        match self {
+            Normalization::NFC => Normalized::Composed(input.nfc()),
+            Normalization::NFD => Normalized::Decomposed(input.nfd()),
+            Normalization::NFKC => Normalized::Composed(input.nfkc()),
+            Normalization::NFKD => Normalized::Decomposed(input.nfkd()),
         }
-        if !self.duplicate_keys && keys.has_duplicates() {
-            return Err(Error::DuplicateKeys);
+    }
+}
+
+#[cfg(feature = "unicode")]
+enum Normalized<'a> {
+    Composed(unicode_normalization::Recompositions<std::str::Chars<'a>>),
+    Decomposed(unicode_normalization::Decompositions<std::str::Chars<'a>>),
+}
+
+#[cfg(feature = "unicode")]
+impl<'a> Iterator for Normalized<'a> {
+    type Item = char;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Normalized::Composed(iter) => iter.next(),
+            Normalized::Decomposed(iter) => iter.next(),
         }
-        Ok(())
     }
 }
 
@@ -335,7 +3512,12 @@ impl Opts {
 ///
 /// The *key* must be a [`&str`](https://doc.rust-lang.org/std/primitive.str.html) while the *value* can be any [`std::fmt::Display`](https://doc.rust-lang.org/std/path/struct.Display.html) trait object
 ///
-/// **note** The keys are unique, duplicates will be replaced by the last one
+/// **note** The keys are unique, duplicates will be replaced by the last one. The first value is kept for
+/// [`Opts::duplicate_args`](./struct.Opts.html#method.duplicate_args), which can use it instead or fail, and
+/// [`Args::try_with`](#method.try_with) fails on duplicates
+///
+/// The args are kept sorted by key, so iterating over them (and applying them) is deterministic.
+/// When more than one arg matches a key, e.g. with `case_insensitive_keys`, an exact match is used before the first one in this order
 /// ```
 /// # use markings::Args;
 /// let args = Args::new()
@@ -345,27 +3527,28 @@ impl Opts {
 ///     .with("key3", &42);
 /// # assert_eq!(args.len(), 3)
 /// ```
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq, Eq, Hash)]
+#[must_use]
 pub struct Args<'k> {
-    mapping: HashMap<std::borrow::Cow<'k, str>, String>,
+    values: ArgValues<'k, String>,
 }
 
 impl<'k> Args<'k> {
     /// Create a new Args builder
     pub fn new() -> Self {
         Self {
-            mapping: HashMap::new(),
+            values: ArgValues::default(),
         }
     }
 
     /// Length of the args
     pub fn len(&self) -> usize {
-        self.mapping.len()
+        self.values.mapping.len()
     }
 
     /// Whether the args is empty
     pub fn is_empty(&self) -> bool {
-        self.mapping.is_empty()
+        self.values.mapping.is_empty()
     }
 
     /// Maps a key to a type that implements [`std::fmt::Display`](https://doc.rust-lang.org/std/fmt/trait.Display.html)
@@ -374,22 +3557,285 @@ impl<'k> Args<'k> {
         key: impl Into<std::borrow::Cow<'k, str>>,
         val: impl std::fmt::Display,
     ) -> Self {
-        self.mapping.insert(key.into(), val.to_string().into());
+        self.values
+            .insert(ArgKey::from(key.into()), val.to_string(), false);
+        self
+    }
+
+    /// Maps a key to a value, like [`Args::with`](#method.with), failing if the key already has a value
+    ///
+    /// This is a `DuplicateArg` error
+    /// ```
+    /// # use markings::{Args, ErrorKind};
+    /// let args = Args::new().try_with("name", "bob").unwrap();
+    /// let err = args.try_with("name", "alice").unwrap_err();
+    /// assert_eq!(err.kind(), &ErrorKind::DuplicateArg { key: "name".into() });
+    /// ```
+    pub fn try_with(
+        self,
+        key: impl Into<std::borrow::Cow<'k, str>>,
+        val: impl std::fmt::Display,
+    ) -> Result<Self> {
+        let key = key.into();
+        if self.values.mapping.contains_key(&*key) {
+            let key = key.into_owned();
+            return Err(ErrorKind::DuplicateArg { key }.into());
+        }
+        Ok(self.with(key, val))
+    }
+
+    /// Maps a key to a value if there is one, like [`Args::with`](#method.with), otherwise the args are unchanged
+    /// ```
+    /// # use markings::Args;
+    /// let nickname: Option<&str> = None;
+    /// let args = Args::new().with_opt("name", Some("bob")).with_opt("nickname", nickname);
+    /// assert_eq!(args.len(), 1);
+    /// ```
+    pub fn with_opt(
+        self,
+        key: impl Into<std::borrow::Cow<'k, str>>,
+        val: Option<impl std::fmt::Display>,
+    ) -> Self {
+        match val {
+            Some(val) => self.with(key, val),
+            None => self,
+        }
+    }
+
+    /// Maps a key to a value if there is one, otherwise to the `default`
+    /// ```
+    /// # use markings::{Args, Template, Opts};
+    /// let port: Option<u16> = None;
+    /// let args = Args::new().with_default("port", port, 80);
+    ///
+    /// let template = Template::parse("localhost:${port}", Opts::default()).unwrap();
+    /// assert_eq!(template.apply(&args).unwrap(), "localhost:80");
+    /// ```
+    pub fn with_default(
+        self,
+        key: impl Into<std::borrow::Cow<'k, str>>,
+        val: Option<impl std::fmt::Display>,
+        default: impl std::fmt::Display,
+    ) -> Self {
+        match val {
+            Some(val) => self.with(key, val),
+            None => self.with(key, default),
+        }
+    }
+
+    /// Maps a key to a secret value
+    ///
+    /// The value is applied like any other, but is hidden when the args, or a [`Capture`](./struct.Capture.html)
+    /// of the value, are debug formatted
+    /// ```
+    /// # use markings::{Args, Template, Opts};
+    /// let args = Args::new().with("user", "bob").with_secret("token", "hunter2");
+    /// assert_eq!(format!("{:?}", args), r#"{"token": <redacted>, "user": "bob"}"#);
+    ///
+    /// let template = Template::parse("${user}:${token}", Opts::default()).unwrap();
+    /// assert_eq!(template.apply(&args).unwrap(), "bob:hunter2");
+    /// ```
+    pub fn with_secret(
+        mut self,
+        key: impl Into<std::borrow::Cow<'k, str>>,
+        val: impl std::fmt::Display,
+    ) -> Self {
+        self.values
+            .insert(ArgKey::from(key.into()), val.to_string(), true);
+        self
+    }
+
+    // the value of an arg, which is its first value with `DuplicateArgs::FirstWins`
+    pub(crate) fn value(&self, arg: &str, policy: DuplicateArgs) -> Option<&str> {
+        self.values.value(arg, policy).map(|val| &**val)
+    }
+
+    // changes the value of an arg, which isn't a duplicate
+    pub(crate) fn set(&mut self, key: ArgKey<'k>, val: String) {
+        self.values.set(key, val);
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+
+    fn is_secret(&self, key: &str) -> bool {
+        self.values.secrets.contains(key)
+    }
+
+    /// Adds all of the `args` under a namespace
+    ///
+    /// Each key is prefixed with the namespace and a dot, so `name` becomes `user.name`
+    /// ```
+    /// # use markings::{Args, Template, Opts};
+    /// let user = Args::new().with("name", "bob").with("id", 42);
+    /// let args = Args::new().with("greeting", "hello").namespace("user", user);
+    ///
+    /// let template = Template::parse("${greeting} ${user.name} (${user.id})", Opts::default()).unwrap();
+    /// assert_eq!(template.apply(&args).unwrap(), "hello bob (42)");
+    /// ```
+    pub fn namespace(mut self, namespace: &str, args: Args<'_>) -> Self {
+        for (key, val) in args.values.mapping {
+            let secret = args.values.secrets.contains(&key);
+            let key = format!("{}.{}", namespace, key);
+            self = match secret {
+                true => self.with_secret(key, val),
+                false => self.with(key, val),
+            };
+        }
         self
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&'_ std::borrow::Cow<'k, str>, &'_ String)> + '_ {
-        self.mapping.iter()
+    /// The keys and values of the args, sorted by key
+    pub fn iter(&self) -> impl Iterator<Item = (&'_ str, &'_ String)> + '_ {
+        self.values
+            .mapping
+            .iter()
+            .map(|(key, val)| (key.as_str(), val))
+    }
+
+    /// Debug format the args, hiding the values of `keys`
+    ///
+    /// This is useful for logging args that contain secrets
+    /// ```
+    /// # use markings::Args;
+    /// let args = Args::new().with("user", "bob").with("token", "hunter2");
+    /// assert_eq!(
+    ///     format!("{:?}", args.redacted_debug(&["token"])),
+    ///     r#"{"token": <redacted>, "user": "bob"}"#
+    /// );
+    /// ```
+    pub fn redacted_debug<'a>(&'a self, keys: &'a [&'a str]) -> impl std::fmt::Debug + 'a {
+        RedactedArgs {
+            values: &self.values,
+            keys,
+        }
+    }
+}
+
+impl<'k> std::fmt::Debug for Args<'k> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.redacted_debug(&[]).fmt(f)
+    }
+}
+
+// the values of args by their keys, which `Args`, `OsArgs` and `ByteArgs` keep with their own type of value
+#[derive(Clone)]
+pub(crate) struct ArgValues<'k, V> {
+    pub(crate) mapping: BTreeMap<ArgKey<'k>, V>,
+    pub(crate) secrets: BTreeSet<ArgKey<'k>>,
+    // the first values of args that were given more than once
+    pub(crate) overwritten: BTreeMap<ArgKey<'k>, V>,
+}
+
+impl<'k, V> ArgValues<'k, V> {
+    // keeps the first value of an arg that is given more than once
+    pub(crate) fn insert(&mut self, key: ArgKey<'k>, val: V, secret: bool) {
+        match secret {
+            true => self.secrets.insert(key.clone()),
+            false => self.secrets.remove(&key),
+        };
+        if let Some(old) = self.mapping.insert(key.clone(), val) {
+            self.overwritten.entry(key).or_insert(old);
+        }
+    }
+
+    // changes the value of an arg, which isn't a duplicate
+    pub(crate) fn set(&mut self, key: ArgKey<'k>, val: V) {
+        self.secrets.remove(&key);
+        self.overwritten.remove(&key);
+        self.mapping.insert(key, val);
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.secrets.remove(key);
+        self.overwritten.remove(key);
+        self.mapping.remove(key);
+    }
+
+    // the value of an arg, which is its first value with `DuplicateArgs::FirstWins`
+    pub(crate) fn value(&self, arg: &str, policy: DuplicateArgs) -> Option<&V> {
+        match (policy, self.overwritten.get(arg)) {
+            (DuplicateArgs::FirstWins, Some(first)) => Some(first),
+            _ => self.mapping.get(arg),
+        }
+    }
+
+    // the same args, borrowing their keys, with each value converted
+    pub(crate) fn convert<'s, U>(&'s self, convert: impl Fn(&V) -> U) -> ArgValues<'s, U> {
+        let key = |key: &'s ArgKey<'k>| ArgKey::from(key.as_str());
+        let mapping = self.mapping.iter().map(|(k, val)| (key(k), convert(val)));
+        let overwritten = self
+            .overwritten
+            .iter()
+            .map(|(k, val)| (key(k), convert(val)));
+        ArgValues {
+            mapping: mapping.collect(),
+            secrets: self.secrets.iter().map(key).collect(),
+            overwritten: overwritten.collect(),
+        }
+    }
+}
+
+impl<V> Default for ArgValues<'_, V> {
+    fn default() -> Self {
+        Self {
+            mapping: BTreeMap::new(),
+            secrets: BTreeSet::new(),
+            overwritten: BTreeMap::new(),
+        }
+    }
+}
+
+// args are equal by their values, not by which values they replaced
+impl<V: PartialEq> PartialEq for ArgValues<'_, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.mapping == other.mapping && self.secrets == other.secrets
     }
 }
 
-pub type ArgsIntoIter<'k> = std::collections::hash_map::IntoIter<std::borrow::Cow<'k, str>, String>;
+impl<V: Eq> Eq for ArgValues<'_, V> {}
+
+impl<V: std::hash::Hash> std::hash::Hash for ArgValues<'_, V> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.mapping.hash(state);
+        self.secrets.hash(state);
+    }
+}
+
+// the args debug formatted as a map, hiding the values of `keys` and of secrets
+pub(crate) struct RedactedArgs<'a, 'k, V> {
+    pub(crate) values: &'a ArgValues<'k, V>,
+    pub(crate) keys: &'a [&'a str],
+}
+
+impl<V: std::fmt::Debug> std::fmt::Debug for RedactedArgs<'_, '_, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut map = f.debug_map();
+        for (key, val) in &self.values.mapping {
+            if self.keys.contains(&&**key) || self.values.secrets.contains(key) {
+                map.entry(key, &Secret(val));
+            } else {
+                map.entry(key, val);
+            }
+        }
+        map.finish()
+    }
+}
+
+pub type ArgsIntoIter<'k> = std::iter::Map<
+    std::collections::btree_map::IntoIter<ArgKey<'k>, String>,
+    fn((ArgKey<'k>, String)) -> (std::borrow::Cow<'k, str>, String),
+>;
 
 impl<'k> IntoIterator for Args<'k> {
     type Item = (std::borrow::Cow<'k, str>, String);
     type IntoIter = ArgsIntoIter<'k>;
     fn into_iter(self) -> Self::IntoIter {
-        self.mapping.into_iter()
+        self.values
+            .mapping
+            .into_iter()
+            .map(|(key, val)| (key.into_cow(), val))
     }
 }
 
@@ -399,16 +3845,34 @@ where
     V: std::fmt::Display,
 {
     fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
-        Self {
-            mapping: iter
-                .into_iter()
-                .map(|(k, v)| (k.into(), v.to_string()))
-                .collect(),
-        }
+        iter.into_iter()
+            .fold(Self::new(), |args, (k, v)| args.with(k, v))
+    }
+}
+
+impl<'k, K, V, S> From<HashMap<K, V, S>> for Args<'k>
+where
+    K: Into<std::borrow::Cow<'k, str>>,
+    V: std::fmt::Display,
+{
+    fn from(map: HashMap<K, V, S>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<'k, K, V> From<Vec<(K, V)>> for Args<'k>
+where
+    K: Into<std::borrow::Cow<'k, str>>,
+    V: std::fmt::Display,
+{
+    fn from(list: Vec<(K, V)>) -> Self {
+        list.into_iter().collect()
     }
 }
 
 #[cfg(test)]
+// the older tests pass values by reference, like code written for earlier versions
+#[allow(clippy::needless_borrow, clippy::needless_borrows_for_generic_args)]
 mod tests {
     use super::*;
 
@@ -429,10 +3893,18 @@ mod tests {
     #[test]
     fn duplicates() {
         let state = State::new(vec!["a", "b", "c"]);
-        assert!(!state.has_duplicates());
+        assert!(state.duplicate(&Opts::default()).is_none());
 
         let state = State::new(vec!["a", "b", "a", "c"]);
-        assert!(state.has_duplicates());
+        assert_eq!(state.duplicate(&Opts::default()), Some(2));
+    }
+
+    #[test]
+    fn case_insensitive_duplicates() {
+        let state = State::new(vec!["a", "B", "b"]);
+        assert!(state.duplicate(&Opts::default()).is_none());
+        let opts = Opts::default().case_insensitive_keys().build();
+        assert_eq!(state.duplicate(&opts), Some(2));
     }
 
     #[test]
@@ -451,7 +3923,8 @@ mod tests {
             .join(" ");
 
         for c in b'a'..=b'z' {
-            let t = Template::parse(&base, Default::default()).unwrap();
+            // the keys after this one are left for the next pass
+            let t = Template::parse(&base, Opts::default().optional_keys()).unwrap();
             let a = Args::new().with(format!("{}", c as char), format!("{} = {}", c as char, c));
             base = t.apply(&a).unwrap();
         }
@@ -463,13 +3936,66 @@ mod tests {
                         u = 117 v = 118 w = 119 x = 120 y = 121 \
                         z = 122";
 
-        assert_eq!(base, expected);
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn owned_key() {
+        let args: Args<'static> = Args::new().with("foo".to_string(), 42);
+        assert_eq!(args.len(), 1);
+    }
+
+    #[test]
+    fn args_from() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), "1".to_string());
+        map.insert("b".to_string(), "2".to_string());
+        let args: Args = map.into();
+        assert_eq!(args.len(), 2);
+
+        let list = vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 2),
+            ("a".to_string(), 3),
+        ];
+        let args: Args = list.into();
+        let template = Template::parse("${a} ${b}", Default::default()).unwrap();
+        assert_eq!(template.apply(&args).unwrap(), "3 2");
+    }
+
+    #[test]
+    fn args_sorted() {
+        let args = Args::new()
+            .with("c", 3)
+            .with("a", 1)
+            .with("b", 2)
+            .with("B", 0);
+        let keys = args.iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["B", "a", "b", "c"]);
+
+        let args = Args::new().with("name", 2).with("Name", 1);
+        let opts = Opts::default().case_insensitive_keys().build();
+        let template = Template::parse("${NAME}", opts).unwrap();
+        for _ in 0..10 {
+            assert_eq!(template.apply(&args).unwrap(), "1");
+        }
     }
 
     #[test]
-    fn owned_key() {
-        let args: Args<'static> = Args::new().with("foo".to_string(), 42);
-        assert_eq!(args.len(), 1);
+    fn args_namespace() {
+        let inner = Args::new()
+            .with("b", 1)
+            .namespace("c", Args::new().with("d", 2));
+        let args = Args::new().with("a.b", 0).namespace("a", inner);
+
+        let v = args.into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            v,
+            vec![
+                ("a.b".into(), "1".to_string()),
+                ("a.c.d".into(), "2".to_string())
+            ]
+        );
     }
 
     #[test]
@@ -514,6 +4040,19 @@ mod tests {
         assert_eq!(input, template.apply(&Args::new()).unwrap());
     }
 
+    #[test]
+    fn key_count() {
+        let template = Template::parse("foobar baz", Opts::default().empty_template()).unwrap();
+        assert!(template.allows_empty());
+        assert!(!template.has_keys());
+        assert_eq!(template.key_count(), 0);
+
+        let template = Template::parse("${a}", Opts::default()).unwrap();
+        assert!(!template.is_empty() && !template.allows_empty());
+        assert!(template.has_keys());
+        assert_eq!(template.key_count(), 1);
+    }
+
     #[test]
     fn duplicate_keys() {
         let input = "${one} and ${two} and ${one}";
@@ -538,6 +4077,638 @@ mod tests {
         assert_eq!("false ${bar} ${baz}", template.apply(&parts).unwrap());
     }
 
+    #[test]
+    fn case_insensitive_keys() {
+        let input = "${Name} ${NAME} ${name}";
+        let args = Args::new().with("nAmE", "test");
+
+        let template = Template::parse(input, Default::default()).unwrap();
+        template.apply(&args).unwrap_err();
+
+        let opts = Opts::default().case_insensitive_keys().build();
+        let err = Template::parse(input, opts).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+
+        let opts = Opts::default()
+            .case_insensitive_keys()
+            .duplicate_keys()
+            .build();
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(template.apply(&args).unwrap(), "test test test");
+    }
+
+    #[test]
+    #[cfg(feature = "unicode")]
+    fn normalize_keys() {
+        let input = "${cafe\u{301}}";
+        let args = Args::new().with("caf\u{e9}", 42);
+
+        let template = Template::parse(input, Default::default()).unwrap();
+        template.apply(&args).unwrap_err();
+
+        let opts = Opts::default().normalize_keys(Normalization::NFC).build();
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(template.apply(&args).unwrap(), "42");
+    }
+
+    #[test]
+    fn apply_positional() {
+        let template = Template::parse("${1} ${0}", Default::default()).unwrap();
+        assert_eq!(
+            template.apply_positional(&["world", "hello"]).unwrap(),
+            "hello world"
+        );
+
+        template.apply_positional(&["a", "b", "c"]).unwrap_err();
+
+        let opts = Opts::default().optional_keys().duplicate_keys().build();
+        let template = Template::parse("${0}${name}${0}", opts).unwrap();
+        assert_eq!(template.apply_positional(&[1]).unwrap(), "1${name}1");
+    }
+
+    #[test]
+    fn anonymous_keys() {
+        let template = Template::parse("${}, ${} and ${}", Default::default()).unwrap();
+        assert_eq!(template.apply_ordered([1, 2, 3]).unwrap(), "1, 2 and 3");
+        assert_eq!(
+            template.apply_ordered(vec!["a", "b", "c"]).unwrap(),
+            "a, b and c"
+        );
+
+        let opts = Opts::default().optional_keys().build();
+        let template = Template::parse("${}${}", opts).unwrap();
+        assert_eq!(template.apply_ordered(Some("a")).unwrap(), "a${}");
+
+        Template::parse("${} ${0}", Default::default()).unwrap_err();
+    }
+
+    #[test]
+    fn concat() {
+        let left = Template::parse("${} ${a} ", Default::default()).unwrap();
+        let right = Template::parse("${b} ${}", Default::default()).unwrap();
+        let template = left.concat(right).unwrap();
+        let args = Args::new()
+            .with("0", 0)
+            .with("1", 1)
+            .with("a", "a")
+            .with("b", "b");
+        assert_eq!(template.apply(&args).unwrap(), "0 a b 1");
+
+        let left = Template::parse("${a}", Default::default()).unwrap();
+        let right = Template::parse("${a}", Default::default()).unwrap();
+        let err = (left.clone() + right.clone()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+
+        let opts = Opts::default().duplicate_keys().build();
+        let left = Template::parse("${a}", opts).unwrap();
+        let template = (left + right).unwrap();
+        assert_eq!(template.apply(&Args::new().with("a", 1)).unwrap(), "11");
+
+        let opts = Opts::default().empty_template().build();
+        let left = Template::parse("hello ", opts.clone()).unwrap();
+        let right = Template::parse("there", opts).unwrap();
+        let template = (left + right).unwrap();
+        assert_eq!(template.segments.len(), 1);
+        assert_eq!(template.apply(&Args::new()).unwrap(), "hello there");
+    }
+
+    #[test]
+    fn split_at_key() {
+        let template = Template::parse("${} ${a}${split}${b} ${}", Default::default()).unwrap();
+        assert!(template.split_at_key("missing").is_none());
+
+        let (left, right) = template.split_at_key("split").unwrap();
+        let args = Args::new().with("0", 0).with("a", "a");
+        assert_eq!(left.apply(&args).unwrap(), "0 a");
+        let args = Args::new().with("0", 0).with("b", "b");
+        assert_eq!(right.apply(&args).unwrap(), "b 0");
+
+        let template = Template::parse("${split}hello", Default::default()).unwrap();
+        let (left, right) = template.split_at_key("split").unwrap();
+        assert!(left.is_empty() && right.is_empty());
+        assert_eq!(left.apply(&Args::new()).unwrap(), "");
+        assert_eq!(right.apply(&Args::new()).unwrap(), "hello");
+    }
+
+    #[test]
+    fn apply_captured() {
+        let opts = Opts::default().duplicate_keys().optional_keys().build();
+        let template = Template::parse("${a} and ${b}, ${a} ${c}", opts).unwrap();
+        let args = Args::new().with("a", "foo").with("b", 42);
+
+        let (output, captures) = template.apply_captured(&args).unwrap();
+        assert_eq!(output, "foo and 42, foo ${c}");
+        assert_eq!(captures.len(), 2);
+
+        let a = &captures["a"];
+        assert_eq!(a.value(), "foo");
+        assert_eq!(a.ranges(), &[0..3, 12..15]);
+        assert!(a
+            .ranges()
+            .iter()
+            .all(|range| &output[range.clone()] == "foo"));
+        assert_eq!(captures["b"].ranges().to_vec(), vec![8..10]);
+    }
+
+    #[test]
+    fn error_span() {
+        let err = Template::parse("${a} ${b} ${a}", Default::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+        assert_eq!(err.span(), Some(10..14));
+
+        let err = Template::parse("${a{}", Default::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::NestedTemplate { pos: 3 });
+        assert_eq!(err.span(), Some(3..4));
+
+        let err = Template::parse("hello", Default::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::EmptyTemplate);
+        assert_eq!(err.span(), None);
+    }
+
+    #[test]
+    fn render_to_error_source() {
+        struct Broken;
+        impl std::io::Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let template = Template::parse("${a}", Default::default()).unwrap();
+        let err = template
+            .render_to(&Args::new().with("a", 1), Broken)
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::Io);
+
+        use std::error::Error as _;
+        let source = err.source().unwrap();
+        let source = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn apply_with_report() {
+        let opts = Opts::default().duplicate_keys().build();
+        let template = Template::parse("${a} ${b} ${a}", opts).unwrap();
+        let args = Args::new().with("a", "foo").with("b", 42);
+
+        let (output, report) = template.apply_with_report(&args).unwrap();
+        assert_eq!(output, "foo 42 foo");
+
+        let a = report.get("a").unwrap();
+        assert_eq!((a.replaced(), a.bytes()), (2, 6));
+        let b = report.get("b").unwrap();
+        assert_eq!((b.replaced(), b.bytes()), (1, 2));
+        assert_eq!(report.total_bytes(), 8);
+        assert_eq!(report.unfilled_keys().count(), 0);
+        assert!(report.unused_args().is_empty());
+    }
+
+    #[test]
+    fn max_output_len() {
+        let opts = Opts::default().duplicate_keys().max_output_len(10).build();
+        let template = Template::parse("${a}: ${a}", opts).unwrap();
+        assert_eq!(
+            template.apply(&Args::new().with("a", "1234")).unwrap(),
+            "1234: 1234"
+        );
+
+        let err = template.apply(&Args::new().with("a", "12345")).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::OutputTooLarge { limit: 10 });
+    }
+
+    #[test]
+    fn parse_limits() {
+        let input = "${a} ${b} ${long}";
+        assert!(Template::parse(input, Default::default()).is_ok());
+
+        let err = Template::parse(input, Opts::default().max_template_len(16).build()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TemplateTooLong { limit: 16 });
+
+        let err = Template::parse(input, Opts::default().max_keys(2).build()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TooManyKeys { limit: 2 });
+
+        let err = Template::parse(input, Opts::default().max_key_len(3).build()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::KeyTooLong { limit: 3 });
+        assert_eq!(err.span(), Some(10..17));
+
+        let opts = Opts::default().max_keys(1).build();
+        let left = Template::parse("${a}", opts.clone()).unwrap();
+        let right = Template::parse("${b}", opts).unwrap();
+        let err = (left + right).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TooManyKeys { limit: 1 });
+    }
+
+    #[test]
+    fn find_keys_multibyte() {
+        let input = "ü${ä} {ö} $ü ${🦀}} ${}$";
+        assert_eq!(Template::find_keys(input).unwrap(), vec!["ä", "🦀", ""]);
+
+        let err = Template::find_keys("ü${ä{}").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::NestedTemplate { pos: 6 });
+    }
+
+    #[test]
+    fn replace_key_name() {
+        let input = "a ${a} ${b} ${a} ${}";
+        let opts = Opts::default().duplicate_keys().build();
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(template.to_string(), input);
+
+        let renamed = template.replace_key_name("a", "c").unwrap();
+        assert_eq!(renamed.to_string(), "a ${c} ${b} ${c} ${}");
+        let args = Args::new().with("c", 1).with("b", 2).with("0", 3);
+        assert_eq!(renamed.apply(&args).unwrap(), "a 1 2 1 3");
+
+        let renamed = template.replace_key_name("0", "d").unwrap();
+        assert_eq!(renamed.to_string(), "a ${a} ${b} ${a} ${d}");
+
+        template.replace_key_name("a", "{oops}").unwrap_err();
+        let err = template.replace_key_name("a", "oops}").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MismatchedBraces { open: 1, close: 2 }
+        );
+
+        let template = Template::parse("${a} ${b}", Default::default()).unwrap();
+        let err = template.replace_key_name("a", "b").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let input = "${ a }, $b and $${c}\n";
+        let opts = Opts::default().bare_dollar_keys().build();
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(template.to_string(), input);
+
+        let renamed = template.replace_key_name(" a ", "x").unwrap();
+        assert_eq!(renamed.to_string(), "${x}, $b and $${c}\n");
+        let renamed = template.replace_key_name("b", "d").unwrap();
+        assert_eq!(renamed.to_string(), "${ a }, $d and $${c}\n");
+        let renamed = template.replace_key_name("b", "d e").unwrap();
+        assert_eq!(renamed.to_string(), "${ a }, ${d e} and $${c}\n");
+    }
+
+    #[test]
+    fn trim_key_whitespace() {
+        let input = "${ a } ${b\t} ${ a b }";
+        let args = Args::new().with("a", 1).with("b", 2).with("a b", 3);
+
+        let template = Template::parse(input, Opts::default()).unwrap();
+        let keys = template.keys().collect::<Vec<_>>();
+        assert_eq!(keys, vec![" a ", "b\t", " a b "]);
+
+        let opts = Opts::default().trim_key_whitespace().build();
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(template.apply(&args).unwrap(), "1 2 3");
+        assert_eq!(template.to_string(), input);
+
+        let renamed = template.replace_key_name("a", "c").unwrap();
+        assert_eq!(renamed.to_string(), "${ c } ${b\t} ${ a b }");
+
+        let opts = Opts::default().trim_key_whitespace().build();
+        let err = Template::parse("${a} ${ a }", opts).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+    }
+
+    #[test]
+    fn arg_uses() {
+        let opts = Opts::default()
+            .duplicate_keys()
+            .optional_keys()
+            .case_insensitive_keys()
+            .arg_uses(2)
+            .build();
+        let template = Template::parse("${a} ${A} ${b} ${b} ${c}", opts).unwrap();
+        let args = Args::new().with("a", 1).with("b", 2);
+        assert_eq!(template.apply(&args).unwrap(), "1 1 2 2 ${c}");
+
+        let err = template.apply(&args.with("c", 3)).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::ArgUseCount {
+                key: "c".into(),
+                expected: 2,
+                found: 1
+            }
+        );
+
+        let err = template.apply(&Args::new().with("d", 4)).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::ArgUseCount {
+                key: "d".into(),
+                expected: 2,
+                found: 0
+            }
+        );
+    }
+
+    #[test]
+    fn template_eq() {
+        use std::collections::HashSet;
+
+        let a = Template::parse("${} and ${1}", Opts::default()).unwrap();
+        let b = Template::parse("${0} and ${1}", Opts::default()).unwrap();
+        assert_eq!(a, b);
+
+        let c = Template::parse("${0} or ${1}", Opts::default()).unwrap();
+        assert_ne!(a, c);
+        let d = Template::parse("${0} and ${1}", Opts::default().optional_keys()).unwrap();
+        assert_ne!(a, d);
+
+        let set = vec![a, b, c, d].into_iter().collect::<HashSet<_>>();
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn args_debug() {
+        let args = Args::new().with("b", 2).with("a", "one");
+        assert_eq!(format!("{:?}", args), r#"{"a": "one", "b": "2"}"#);
+        assert_eq!(
+            format!("{:?}", args.redacted_debug(&["a", "c"])),
+            r#"{"a": <redacted>, "b": "2"}"#
+        );
+
+        assert_eq!(args, Args::new().with("a", "one").with("b", "2"));
+        assert_ne!(args, Args::new().with("a", "one"));
+    }
+
+    #[test]
+    fn with_secret() {
+        let args = Args::new()
+            .with_secret("token", "hunter2")
+            .with("user", "bob");
+        let args = Args::new().namespace("auth", args);
+        assert_eq!(
+            format!("{:?}", args),
+            r#"{"auth.token": <redacted>, "auth.user": "bob"}"#
+        );
+
+        let template = Template::parse(
+            "${auth.user} ${AUTH.TOKEN}",
+            Opts::default().case_insensitive_keys(),
+        )
+        .unwrap();
+        let (output, captures) = template.apply_captured(&args).unwrap();
+        assert_eq!(output, "bob hunter2");
+        assert_eq!(captures["AUTH.TOKEN"].value(), "hunter2");
+        assert_eq!(
+            format!("{:?}", captures["AUTH.TOKEN"]),
+            "Capture { value: <redacted>, ranges: [4..11] }"
+        );
+        assert!(format!("{:?}", captures["auth.user"]).contains("bob"));
+
+        // overwriting a secret with a plain value makes it plain
+        let args = args.with("auth.token", "public");
+        assert!(format!("{:?}", args).contains("public"));
+    }
+
+    #[test]
+    fn duplicate_args() {
+        let args = Args::new().with("a", 1).with("a", 2).with("a", 3);
+        let apply = |policy, input| {
+            let opts = Opts::default()
+                .case_insensitive_keys()
+                .duplicate_args(policy);
+            Template::parse(input, opts).unwrap().apply(&args)
+        };
+        assert_eq!(apply(DuplicateArgs::LastWins, "${A}").unwrap(), "3");
+        assert_eq!(apply(DuplicateArgs::FirstWins, "${A}").unwrap(), "1");
+        let err = apply(DuplicateArgs::Error, "${A}").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateArg { key: "a".into() });
+
+        // args that match the same key are duplicates too
+        let args = vec![("A", 1), ("a", 2)].into_iter().collect::<Args<'_>>();
+        let opts = Opts::default()
+            .case_insensitive_keys()
+            .duplicate_keys()
+            .duplicate_args(DuplicateArgs::Error);
+        let template = Template::parse("${a}", opts).unwrap();
+        let err = template.apply(&args).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateArg { key: "a".into() });
+
+        let args = vec![("a", 1), ("a", 2)].into_iter().collect::<Args<'_>>();
+        let template = Template::parse(
+            "${a}",
+            Opts::default().duplicate_args(DuplicateArgs::FirstWins),
+        );
+        assert_eq!(template.unwrap().apply(&args).unwrap(), "1");
+        assert!(Args::new()
+            .try_with("a", 1)
+            .unwrap()
+            .try_with("b", 2)
+            .is_ok());
+
+        // args are equal by the values they have, not the values they replaced
+        let replaced = Args::new().with("a", 1).with("a", 2);
+        let args = Args::new().with("a", 2);
+        assert_eq!(replaced, args);
+        let hash = |args: &Args<'_>| {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            args.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&replaced), hash(&args));
+
+        let template = Template::parse(
+            "${a}",
+            Opts::default().duplicate_args(DuplicateArgs::FirstWins),
+        );
+        let os = OsArgs::from(replaced);
+        assert_eq!(template.unwrap().apply_os(&os).unwrap(), "1");
+    }
+
+    #[test]
+    fn conditional_blocks() {
+        let template = Template::parse(
+            "${?items}items: ${items}${?more}, ...${/more}${/}${?none}none${/}",
+            Opts::default().optional_keys(),
+        )
+        .unwrap();
+        assert_eq!(
+            template.to_string(),
+            "${?items}items: ${items}${?more}, ...${/more}${/}${?none}none${/}"
+        );
+
+        let apply = |args: Args<'_>| template.apply(&args).unwrap();
+        assert_eq!(apply(Args::new().with("items", "a")), "items: a");
+        assert_eq!(
+            apply(Args::new().with("items", "a").with("more", 1)),
+            "items: a, ..."
+        );
+        assert_eq!(
+            apply(Args::new().with("more", 1).with("none", true)),
+            "none"
+        );
+        for falsey in &["", "0", "false", "[]"] {
+            assert_eq!(apply(Args::new().with("items", falsey)), "");
+        }
+
+        // args that are only used by blocks aren't unknown
+        let template = Template::parse("${?a}${b}${/}", Opts::default()).unwrap();
+        assert_eq!(
+            template
+                .apply(&Args::new().with("a", 1).with("b", 2))
+                .unwrap(),
+            "2"
+        );
+
+        let err = Template::parse("a ${?b} ${c}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnclosedBlock { pos: 2 });
+        assert_eq!(err.span(), Some(2..7));
+
+        let err = Template::parse("${?a}${b}${/c}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnmatchedBlockEnd { pos: 9 });
+        let err = Template::parse("${b}${/}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnmatchedBlockEnd { pos: 4 });
+
+        let template = Template::parse("${x}${?a}${b}${/}${c}", Opts::default()).unwrap();
+        assert!(template.split_at_key("b").is_none());
+        assert!(template.split_at_key("c").is_some());
+        let renamed = template.replace_key_name("a", "d").unwrap();
+        assert_eq!(renamed.to_string(), "${x}${?d}${b}${/}${c}");
+    }
+
+    #[test]
+    fn else_branches() {
+        let input = "${?a}a${:?b}b${?c}c${:else}!c${/}${:?a}unreachable${:else}else${/}";
+        let template = Template::parse(input, Opts::default().optional_keys()).unwrap();
+        assert_eq!(template.to_string(), input);
+
+        let apply = |args: Args<'_>| template.apply(&args).unwrap();
+        assert_eq!(apply(Args::new().with("a", 1).with("b", 1)), "a");
+        assert_eq!(apply(Args::new().with("b", 1).with("c", 1)), "bc");
+        assert_eq!(apply(Args::new().with("b", 1)), "b!c");
+        assert_eq!(apply(Args::new().with("c", 1)), "else");
+
+        // the else of an inner block doesn't render when the outer branch isn't taken
+        let template = Template::parse("${?a}${?b}b${:else}!b${/}${/}", Opts::default()).unwrap();
+        assert_eq!(template.apply(&Args::new().with("b", 0)).unwrap(), "");
+
+        for (input, pos) in &[
+            ("${a}${:else}", 4),
+            ("${?a}${:else}${:else}${/}", 13),
+            ("${?a}${:else}${:?b}${/}", 13),
+        ] {
+            let err = Template::parse(input, Opts::default()).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::MisplacedElse { pos: *pos });
+        }
+    }
+
+    #[test]
+    fn with_blocks() {
+        let input = "${@a}${x} ${y}${@b}${?x} ${x}${/} ${z}${/b}${/a} ${x}";
+        let opts = Opts::default().duplicate_keys();
+        let template = Template::parse(input, opts.clone()).unwrap();
+
+        let args = Args::new()
+            .with("x", "x")
+            .with("y", "y")
+            .with("a.x", "a.x")
+            .with("a.b.x", "a.b.x")
+            .with("a.z", "a.z")
+            .with("a.unused", "");
+        assert_eq!(template.apply(&args).unwrap(), "a.x y a.b.x a.z x");
+
+        let args = args.with("b.z", "unscoped");
+        assert_eq!(
+            template.apply(&args).unwrap_err().kind(),
+            &ErrorKind::OptionalKeys
+        );
+
+        let err = Template::parse("${@a}${:else}${/}", opts.clone()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::MisplacedElse { pos: 5 });
+
+        // a lone `@` is just a key
+        let template = Template::parse("${@}", opts).unwrap();
+        assert_eq!(template.apply(&Args::new().with("@", 1)).unwrap(), "1");
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let nested = |depth| format!("{}${{x}}{}", "${@a}".repeat(depth), "${/}".repeat(depth));
+        let opts = Opts::default().optional_keys();
+
+        let input = nested(64);
+        let template = Template::parse(&input, opts.clone()).unwrap();
+        let key = format!("{}x", "a.".repeat(64));
+        assert_eq!(template.apply(&Args::new().with(key, 1)).unwrap(), "1");
+
+        let err = Template::parse(&nested(65), opts.clone()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 64 * 5 });
+
+        let input = nested(1000);
+        let template = Template::parse(&input, opts.clone().max_depth(1000)).unwrap();
+        assert_eq!(template.keys().collect::<Vec<_>>(), vec!["x"]);
+        let err = Template::parse(&nested(17), opts.sandbox(Sandbox::new())).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 16 * 5 });
+    }
+
+    #[test]
+    fn map_literals() {
+        let template = Template::parse("name: ${name}, ${a}; ${b}", Default::default()).unwrap();
+        let template = template.map_literals(|s| match s {
+            ", " => String::new(),
+            s => s.replace("name", "NAME"),
+        });
+        assert_eq!(template.to_string(), "NAME: ${name}${a}; ${b}");
+        assert_eq!(template.segments.len(), 5);
+    }
+
+    #[test]
+    fn bare_dollar_keys() {
+        let input = "$A_1-$_b $1 ${c} $ $$d";
+        let template = Template::parse(input, Default::default()).unwrap();
+        assert_eq!(template.keys().collect::<Vec<_>>(), vec!["c"]);
+
+        let opts = Opts::default().bare_dollar_keys().build();
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(
+            template.keys().collect::<Vec<_>>(),
+            vec!["A_1", "_b", "c", "d"]
+        );
+        assert_eq!(template.to_string(), input);
+
+        let args = Args::new()
+            .with("A_1", 1)
+            .with("_b", 2)
+            .with("c", 3)
+            .with("d", 4);
+        assert_eq!(template.apply(&args).unwrap(), "1-2 $1 3 $ $4");
+    }
+
+    #[test]
+    fn percent_keys() {
+        let input = "%A% %%B%% %_c.d(x)% %1% 5% ${e}%%%F%";
+        let template = Template::parse(input, Opts::default().empty_template()).unwrap();
+        assert_eq!(template.keys().collect::<Vec<_>>(), vec!["e"]);
+
+        let opts = Opts::default().percent_keys();
+        let template = Template::parse(input, opts).unwrap();
+        assert_eq!(
+            template.keys().collect::<Vec<_>>(),
+            vec!["A", "_c.d(x)", "e", "F"]
+        );
+        assert_eq!(template.to_string(), input);
+        assert_eq!(template.key_spans()[3], ("F".to_string(), 33..36));
+
+        let args = Args::new()
+            .with("A", 1)
+            .with("_c.d(x)", 2)
+            .with("e", 3)
+            .with("F", 4);
+        let output = template.apply(&args).unwrap();
+        assert_eq!(output, "1 %B% 2 %1% 5% 3%4");
+        assert_eq!(template.len_hint(&args), output.len());
+        assert_eq!(template.extract(&output).unwrap(), args);
+    }
+
     #[test]
     fn empty_template_replace() {
         let template =
@@ -559,4 +4730,383 @@ mod tests {
         let s = template.apply(&args).unwrap();
         assert_eq!(s, "42 false");
     }
+
+    #[test]
+    fn exact_allocation() {
+        let opts = Opts::default().duplicate_keys();
+        let template = Template::parse("${a}, ${b}${?c} and ${a}${/}!", opts).unwrap();
+        let args = Args::new()
+            .with("a", "hello")
+            .with("b", "world")
+            .with("c", true);
+        let output = template.apply(&args).unwrap();
+        assert_eq!(output, "hello, world and hello!");
+        assert_eq!(output.capacity(), output.len());
+    }
+
+    #[test]
+    fn count_keys() {
+        let inputs = [
+            "",
+            "${a} ${} ${a}",
+            "${?a}${b}${:?c}${:else}${/a}",
+            "${@user}${name}${/}${@}",
+            "${block body}${a}${/block}${block }",
+            "$${a}",
+        ];
+        for input in &inputs {
+            let opts = Opts::default()
+                .duplicate_keys()
+                .optional_keys()
+                .empty_template();
+            let template = Template::parse(input, opts).unwrap();
+            assert_eq!(
+                Template::count_keys(input),
+                template.keys().count(),
+                "{}",
+                input
+            );
+        }
+
+        // an unclosed marker ends the count
+        assert_eq!(Template::count_keys("${a} ${b"), 1);
+    }
+
+    #[test]
+    fn frontmatter() {
+        let input = "#!max_keys: 1,empty_template\nhello";
+        let template = Template::parse_with_frontmatter(input, Opts::default()).unwrap();
+        assert_eq!(template.to_string(), "hello");
+
+        let err = Template::parse_with_frontmatter("#! max_keys: 1\n${a}${b}", Opts::default())
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TooManyKeys { limit: 1 });
+
+        let err = Template::parse_with_frontmatter("#! optional_keys,  unknown", Opts::default())
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 19 });
+        let err =
+            Template::parse_with_frontmatter("#! max_keys\n${a}", Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 3 });
+
+        // without a header, this is the same as parsing
+        let template = Template::parse_with_frontmatter("# ${a}", Opts::default()).unwrap();
+        assert_eq!(template.to_string(), "# ${a}");
+    }
+
+    #[test]
+    fn missing_keys() {
+        let template = Template::parse(
+            "${b} ${a}${?c} ${d}${/} ${a}",
+            Opts::default().duplicate_keys(),
+        )
+        .unwrap();
+        let err = template.apply(&Args::new()).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MissingKeys {
+                keys: vec!["a".into(), "b".into()]
+            }
+        );
+        assert_eq!(err.to_string(), "missing values for keys: a, b");
+
+        let args = Args::new().with("a", 1).with("b", 2);
+        assert_eq!(template.apply(&args).unwrap(), "2 1 1");
+        let err = template.apply(&args.with("c", true)).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MissingKeys {
+                keys: vec!["d".into()]
+            }
+        );
+
+        let opts = Opts::default().duplicate_keys().optional_keys();
+        let template = Template::parse("${b} ${a}", opts).unwrap();
+        assert_eq!(template.apply(&Args::new()).unwrap(), "${b} ${a}");
+    }
+
+    #[test]
+    fn apply_all_args() {
+        let template = Template::parse("${a} ${b} ${c}", Opts::default()).unwrap();
+        let layers = [
+            Args::new().with("a", 1).with_secret("b", 1),
+            Args::new().with("b", 2).with("c", 2),
+            Args::new().with_secret("c", 3),
+        ];
+        assert_eq!(
+            template.apply_all_args(&layers, Precedence::Last).unwrap(),
+            "1 2 3"
+        );
+        assert_eq!(
+            template.apply_all_args(&layers, Precedence::First).unwrap(),
+            "1 1 2"
+        );
+        assert_eq!(
+            template
+                .apply_all_args(&[], Precedence::Last)
+                .unwrap_err()
+                .kind(),
+            &ErrorKind::MissingKeys {
+                keys: vec!["a".into(), "b".into(), "c".into()]
+            }
+        );
+
+        // the layers are merged before applying, so one layer can't fill the keys of another
+        let layers = [Args::new().with("a", "${b}"), Args::new().with("b", 2)];
+        let template = Template::parse("${a} ${b}", Opts::default()).unwrap();
+        assert_eq!(
+            template.apply_all_args(&layers, Precedence::Last).unwrap(),
+            "${b} 2"
+        );
+    }
+
+    #[test]
+    fn edit() {
+        let mut template = Template::parse("${a}, ${b}!", Opts::default()).unwrap();
+        template.set_literal(4..6, "").unwrap();
+        assert_eq!(template.to_string(), "${a}${b}!");
+        template.set_literal(4..4, " and ").unwrap();
+        template.set_literal(0..0, "> ").unwrap();
+        assert_eq!(template.to_string(), "> ${a} and ${b}!");
+
+        let err = template.set_literal(3..8, "x").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidEdit { pos: 3 });
+        let err = template.set_literal(4..4, "x").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidEdit { pos: 4 });
+
+        // the new text can't make a marker, even with the text next to it
+        let mut marked = Template::parse("$x{ ${a}", Opts::default()).unwrap();
+        for (range, text) in &[(3..3, "${b}"), (1..2, ""), (1..1, "{"), (2..2, "$")] {
+            let err = marked.set_literal(range.clone(), text).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::InvalidEdit { pos: range.start });
+        }
+        marked.set_literal(1..2, "y").unwrap();
+        assert_eq!(marked.to_string(), "$y{ ${a}");
+
+        // the edges of an edit are whole characters
+        let mut wide = Template::parse("é${a}", Opts::default()).unwrap();
+        wide.set_literal(2..2, "x").unwrap();
+        assert_eq!(wide.to_string(), "éx${a}");
+
+        let mut bare =
+            Template::parse("cost: $ USD ${x}", Opts::default().bare_dollar_keys()).unwrap();
+        let err = bare.set_literal(7..7, "NAME").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidEdit { pos: 7 });
+        bare.set_literal(7..7, "5").unwrap();
+        assert_eq!(bare.to_string(), "cost: $5 USD ${x}");
+
+        let mut percent =
+            Template::parse("100% of %a% %%", Opts::default().percent_keys()).unwrap();
+        for (range, text) in &[(4..4, "NAME%"), (3..3, "%"), (12..12, "%"), (4..4, "%x%")] {
+            let err = percent.set_literal(range.clone(), text).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::InvalidEdit { pos: range.start });
+        }
+        percent.set_literal(4..4, " %%").unwrap();
+        assert_eq!(percent.to_string(), "100% %% of %a% %%");
+
+        template.insert_key(16, "c").unwrap();
+        template.insert_key(0, "").unwrap();
+        assert_eq!(template.to_string(), "${}> ${a} and ${b}!${c}");
+        let args = Args::new()
+            .with("0", 0)
+            .with("a", 1)
+            .with("b", 2)
+            .with("c", 3);
+        assert_eq!(template.apply(&args).unwrap(), "0> 1 and 2!3");
+
+        for name in &["?a", "a|red", "@a"] {
+            let err = template.insert_key(0, name).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::InvalidEdit { pos: 0 });
+        }
+        let err = template.insert_key(0, "a}").unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::MismatchedBraces { .. }));
+        let err = template.insert_key(1, "d").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidEdit { pos: 1 });
+        let err = template.insert_key(0, "a").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+        assert_eq!(template.to_string(), "${}> ${a} and ${b}!${c}");
+    }
+
+    #[test]
+    fn preview() {
+        let opts = Opts::default().arg_uses(2);
+        let template =
+            Template::parse("${@user}${name}${/} ${?a}a${:else}b${/} ${}", opts).unwrap();
+        assert_eq!(template.preview().unwrap(), "«name» a «0»");
+        assert_eq!(template.preview_with(|_| String::new()).unwrap(), " b ");
+    }
+
+    #[test]
+    fn key_specs() {
+        let input = "${a!int} ${ b ! bool | bold } ${c!email} ${d!number} ${e!other}";
+        let opts = Opts::default().trim_key_whitespace();
+        let template = Template::parse(input, opts.clone()).unwrap();
+        assert_eq!(
+            template.key_specs().collect::<Vec<_>>(),
+            vec![
+                ("a", "int"),
+                ("b", "bool"),
+                ("c", "email"),
+                ("d", "number"),
+                ("e", "other")
+            ]
+        );
+        assert_eq!(template.to_string(), input);
+
+        let args = Args::new()
+            .with("a", 1.5)
+            .with("b", "yes")
+            .with("c", "bob")
+            .with("d", "x")
+            .with("e", "");
+        // annotations aren't checked by default
+        assert!(template.apply(&args).is_ok());
+
+        // annotations that can't be checked are rejected when they would be checked
+        let err = Template::parse(input, opts.clone().validate_key_specs()).unwrap_err();
+        let spec = "other".to_string();
+        assert_eq!(err.kind(), &ErrorKind::UnknownSpec { spec });
+        assert_eq!(err.span(), Some(53..63));
+
+        let input = "${a!int} ${ b ! bool | bold } ${c!email} ${d!number}";
+        let template = Template::parse(input, opts.validate_key_specs()).unwrap();
+        let args = Args::new()
+            .with("a", -1)
+            .with("b", true)
+            .with("c", "bob@example.com")
+            .with("d", 1.5);
+        assert!(template.apply(&args).is_ok());
+
+        let invalid = [
+            ("a", "1.5"),
+            ("b", "yes"),
+            ("c", "bob@example"),
+            ("c", "@example.com"),
+            ("d", "NaN"),
+        ];
+        for (key, value) in &invalid {
+            let err = template.apply(&args.clone().with(*key, value)).unwrap_err();
+            assert!(
+                matches!(err.kind(), ErrorKind::InvalidValue { key: k, .. } if k == key),
+                "{} = {}",
+                key,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn expressions() {
+        let input = "${count + 1} of ${ (total - count) * 2 } ${name}${@item} ${price / 2}${/}";
+        // without the option, the markers are just keys
+        let template = Template::parse(input, Opts::default().optional_keys()).unwrap();
+        assert_eq!(template.key_count(), 4);
+
+        let template = Template::parse(input, Opts::default().expressions()).unwrap();
+        assert_eq!(template.to_string(), input);
+        assert_eq!(template.key_count(), 1);
+        assert_eq!(
+            template.preview().unwrap(),
+            "«count + 1» of «(total - count) * 2» «name» «price / 2»"
+        );
+
+        let args = Args::new()
+            .with("count", 2)
+            .with("total", 5)
+            .with("name", "bob")
+            .with("item.price", 3);
+        assert_eq!(template.apply(&args).unwrap(), "3 of 6 bob 1.5");
+
+        let err = template
+            .apply(&args.clone().with("count", "two"))
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidValue { key, .. } if key == "count"));
+        assert_eq!(err.span(), Some(0..12));
+
+        let err = template
+            .apply(&Args::new().with("name", "bob").with("item.price", 1))
+            .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MissingKeys {
+                keys: vec!["count".into(), "total".into()]
+            }
+        );
+
+        let err = Template::parse("a ${count +}", Opts::default().expressions()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 2 });
+        assert_eq!(err.span(), Some(2..12));
+    }
+
+    #[test]
+    fn fallbacks() {
+        let input = r#"${name ?? nick ?? "a \"guest\"" | md} ${ id??user.id } ${x ?? "" }"#;
+        let template = Template::parse(input, Opts::default()).unwrap();
+        assert_eq!(template.to_string(), input);
+        assert_eq!(template.keys().collect::<Vec<_>>(), vec!["name", "id", "x"]);
+        assert_eq!(
+            template,
+            Template::parse(
+                r#"${name??nick??"a \"guest\""|md} ${id ?? user.id} ${x??""}"#,
+                Opts::default()
+            )
+            .unwrap()
+        );
+
+        let args = Args::new()
+            .with("name", "")
+            .with("nick", "*bob*")
+            .with("id", "")
+            .with("user.id", 42)
+            .with("x", "");
+        assert_eq!(template.apply(&args).unwrap(), r"\*bob\* 42 ");
+
+        // values for only the fallbacks are still known args
+        let args = Args::new().with("user.id", 1);
+        assert_eq!(template.apply(&args).unwrap(), r#"a "guest" 1 "#);
+
+        let err = template
+            .apply(&Args::new().with("name", "bob"))
+            .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MissingKeys {
+                keys: vec!["id".into()]
+            }
+        );
+
+        for input in &["${a ?? }", "${ ?? b}", r#"${a ?? "b}"#, r#"${a ?? "b"c"}"#] {
+            let err = Template::parse(input, Opts::default()).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                &ErrorKind::UnsupportedSyntax { pos: 0 },
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn allowed_keys() {
+        let opts = Opts::default().allowed_keys(&["user", "count", "nick"]);
+        let input = r#"${user ?? nick ?? "guest"}${?count}${count}${/count}"#;
+        assert!(Template::parse(input, opts.clone()).is_ok());
+        assert!(Template::parse("${USER}", opts.clone().case_insensitive_keys()).is_ok());
+
+        let cases = [
+            ("${user ?? name}", "name", 0),
+            ("${?admin}${/}", "admin", 0),
+            ("a ${count + total}", "total", 2),
+            ("${}", "0", 0),
+            ("${USER}", "USER", 0),
+        ];
+        for (input, key, pos) in &cases {
+            let err = Template::parse(input, opts.clone().expressions()).unwrap_err();
+            let expected = ErrorKind::ForbiddenKey {
+                key: key.to_string(),
+                pos: *pos,
+            };
+            assert_eq!(err.kind(), &expected, "{}", input);
+        }
+    }
 }
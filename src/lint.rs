@@ -0,0 +1,149 @@
+use crate::{Segment, Template};
+use std::ops::Range;
+
+/// A warning about the quality of a template, produced by [`Template::lint`](./struct.Template.html#method.lint)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lint {
+    kind: LintKind,
+    span: Option<Range<usize>>,
+}
+
+impl Lint {
+    /// The kind of lint this is
+    pub fn kind(&self) -> &LintKind {
+        &self.kind
+    }
+
+    /// How severe this lint is
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            LintKind::WhitespaceInKey { .. } => Severity::Warning,
+            LintKind::UnusedDuplicateKeys | LintKind::AdjacentKeys => Severity::Info,
+        }
+    }
+
+    /// The byte range in the template source this lint refers to, if any
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use LintKind::*;
+        match &self.kind {
+            UnusedDuplicateKeys => {
+                f.write_str("duplicate keys are allowed, but no key is repeated")
+            }
+            WhitespaceInKey { key } => write!(f, "key '{}' contains whitespace", key),
+            AdjacentKeys => f.write_str("keys are not separated by any text"),
+        }
+    }
+}
+
+/// The kind of a [`Lint`](./struct.Lint.html)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LintKind {
+    /// `duplicate_keys` is enabled, but each key is only used once
+    UnusedDuplicateKeys,
+
+    /// A key contains whitespace, which is probably a typo
+    WhitespaceInKey { key: String },
+
+    /// There is no literal text between two keys
+    AdjacentKeys,
+}
+
+/// How severe a [`Lint`](./struct.Lint.html) is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something that may be intentional
+    Info,
+    /// Something that is probably a mistake
+    Warning,
+}
+
+impl<'a> Template<'a> {
+    /// Check the template for things that are allowed, but probably not intended
+    ///
+    /// ```
+    /// # use markings::{Template, Opts, LintKind};
+    /// let template = Template::parse("hello ${ name}", Opts::default()).unwrap();
+    /// let lints = template.lint();
+    /// assert_eq!(lints[0].kind(), &LintKind::WhitespaceInKey { key: " name".to_string() });
+    /// assert_eq!(lints[0].span(), Some(6..14));
+    /// ```
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut lints = vec![];
+
+        let mut previous_key = false;
+        for (segment, span) in self.spans() {
+            let name = match segment {
+                Segment::Key { name, .. } => name,
+                _ => {
+                    previous_key = false;
+                    continue;
+                }
+            };
+
+            if name.chars().any(char::is_whitespace) {
+                lints.push(Lint {
+                    kind: LintKind::WhitespaceInKey {
+                        key: name.to_string(),
+                    },
+                    span: Some(span.clone()),
+                });
+            }
+
+            if previous_key {
+                lints.push(Lint {
+                    kind: LintKind::AdjacentKeys,
+                    span: Some(span),
+                });
+            }
+            previous_key = true;
+        }
+
+        if self.opts.duplicate_keys {
+            let keys = crate::State::new(self.keys());
+            if keys.duplicate(&self.opts).is_none() {
+                lints.push(Lint {
+                    kind: LintKind::UnusedDuplicateKeys,
+                    span: None,
+                });
+            }
+        }
+
+        lints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn lint() {
+        let template = Template::parse("${a} ${b}", Opts::default()).unwrap();
+        assert!(template.lint().is_empty());
+
+        let opts = Opts::default().duplicate_keys().build();
+        let template = Template::parse("${a}${b c}", opts).unwrap();
+        let lints = template.lint();
+        let kinds = lints.iter().map(Lint::kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                &LintKind::WhitespaceInKey {
+                    key: "b c".to_string()
+                },
+                &LintKind::AdjacentKeys,
+                &LintKind::UnusedDuplicateKeys,
+            ]
+        );
+        assert_eq!(lints[1].span(), Some(4..10));
+        assert_eq!(lints[0].severity(), Severity::Warning);
+        assert_eq!(lints[2].severity(), Severity::Info);
+    }
+}
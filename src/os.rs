@@ -0,0 +1,209 @@
+use crate::{ArgKey, ArgValues, Args, Error, ErrorKind, RedactedArgs, Result, Template};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+/// Arguments whose values are platform strings, for [`Template::apply_os`](./struct.Template.html#method.apply_os)
+/// and [`Template::apply_path`](./struct.Template.html#method.apply_path)
+///
+/// Values don't have to be UTF-8, like file names on Unix and Windows. The secret values of [`Args`](./struct.Args.html)
+/// it is made from stay secret, and are hidden when it is debug formatted
+#[derive(Clone, Default, PartialEq)]
+pub struct OsArgs<'k> {
+    values: ArgValues<'k, OsString>,
+}
+
+impl<'k> OsArgs<'k> {
+    /// Create a new, empty args
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps a key to a value, which is formatted with `Display`
+    pub fn with(self, key: impl Into<Cow<'k, str>>, val: impl std::fmt::Display) -> Self {
+        self.with_os(key, val.to_string())
+    }
+
+    /// Maps a key to a platform string, like a file name or a `Path`
+    pub fn with_os(mut self, key: impl Into<Cow<'k, str>>, val: impl AsRef<OsStr>) -> Self {
+        let key = ArgKey::from(key.into());
+        self.values.insert(key, val.as_ref().to_os_string(), false);
+        self
+    }
+
+    /// Get the number of arguments
+    pub fn len(&self) -> usize {
+        self.values.mapping.len()
+    }
+
+    /// Whether there are no arguments
+    pub fn is_empty(&self) -> bool {
+        self.values.mapping.is_empty()
+    }
+}
+
+impl std::fmt::Debug for OsArgs<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let values = &self.values;
+        RedactedArgs { values, keys: &[] }.fmt(f)
+    }
+}
+
+impl<'k> From<Args<'k>> for OsArgs<'k> {
+    fn from(args: Args<'k>) -> Self {
+        let values = args.values;
+        let convert = |values: BTreeMap<_, String>| {
+            let values = values.into_iter().map(|(key, val)| (key, val.into()));
+            values.collect()
+        };
+        Self {
+            values: ArgValues {
+                mapping: convert(values.mapping),
+                secrets: values.secrets,
+                overwritten: convert(values.overwritten),
+            },
+        }
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Apply the arguments to the template, returning a platform string
+    ///
+    /// Values that aren't UTF-8 are written without being converted. They are seen as their lossy conversion by
+    /// blocks, fallbacks and filters, so a filter that changes one is an `InvalidValue` error rather than lossy
+    /// output
+    /// ```
+    /// # use markings::{Template, OsArgs, Opts};
+    /// # use std::ffi::OsString;
+    /// let template = Template::parse("${name}.bak", Opts::default()).unwrap();
+    /// let args = OsArgs::new().with_os("name", OsString::from("notes"));
+    /// assert_eq!(template.apply_os(&args).unwrap(), "notes.bak");
+    /// ```
+    pub fn apply_os(&self, args: &OsArgs<'_>) -> Result<OsString> {
+        self.render_os(args, false)
+    }
+
+    /// Apply the arguments to the template, returning a path, like [`Template::apply_os`](#method.apply_os)
+    ///
+    /// The separators of the path are in the template, and each key has to be written as a single component
+    /// of it, so a value like `../..` or `/etc` can't escape the path. A key that isn't a single component is an
+    /// `InvalidValue` error
+    /// ```
+    /// # use markings::{Template, OsArgs, Opts};
+    /// # use std::path::Path;
+    /// let template = Template::parse("/home/${user}/${file}", Opts::default()).unwrap();
+    /// let args = OsArgs::new().with("user", "bob").with_os("file", "notes.txt");
+    /// assert_eq!(template.apply_path(&args).unwrap(), Path::new("/home/bob/notes.txt"));
+    ///
+    /// let args = OsArgs::new().with("user", "bob").with_os("file", "../alice/notes.txt");
+    /// assert!(template.apply_path(&args).is_err());
+    /// ```
+    pub fn apply_path(&self, args: &OsArgs<'_>) -> Result<PathBuf> {
+        self.render_os(args, true).map(PathBuf::from)
+    }
+
+    fn render_os(&self, os_args: &OsArgs<'_>, components: bool) -> Result<OsString> {
+        let values = os_args
+            .values
+            .convert(|val| val.to_string_lossy().into_owned());
+        let args = Args { values };
+
+        let (pieces, len) = self.pieces(&args)?;
+        let mut output = OsString::with_capacity(len);
+        for (piece, key, span) in pieces {
+            let key = match key {
+                Some(key) => key,
+                None => {
+                    output.push(&*piece);
+                    continue;
+                }
+            };
+            let invalid = |spec: &str| {
+                let (key, spec) = (key.to_string(), spec.to_string());
+                Error::from(ErrorKind::InvalidValue { key, spec }).with_span(span.clone())
+            };
+
+            let policy = self.opts.duplicate_args;
+            let val = self
+                .lookup_arg(&args, key)
+                .and_then(|(arg, _)| os_args.values.value(arg, policy))
+                .filter(|val| val.to_str().is_none());
+            let val = match val {
+                Some(val) if val.to_string_lossy() == piece => &**val,
+                Some(..) => return Err(invalid("UTF-8 string")),
+                None => OsStr::new(&*piece),
+            };
+            if components && !is_component(val) {
+                return Err(invalid("path component"));
+            }
+            output.push(val);
+        }
+        Ok(output)
+    }
+}
+
+// whether the value is a single normal part of a path
+fn is_component(val: &OsStr) -> bool {
+    let mut components = Path::new(val).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(component)), None) => component == val,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn os() {
+        let template = Template::parse("${a}-${b}", Opts::default()).unwrap();
+        let args = OsArgs::from(Args::new().with("a", 1).with("b", "two"));
+        assert_eq!(template.apply_os(&args).unwrap(), "1-two");
+        assert!(template.apply_os(&OsArgs::new()).is_err());
+
+        // secrets stay secret
+        let secret = OsArgs::from(Args::new().with("a", 1).with_secret("b", "hunter2"));
+        assert_eq!(format!("{:?}", secret), r#"{"a": "1", "b": <redacted>}"#);
+        assert_eq!(template.apply_os(&secret).unwrap(), "1-hunter2");
+        let secret = secret.with_os("b", "two");
+        assert_eq!(format!("{:?}", secret), r#"{"a": "1", "b": "two"}"#);
+
+        for val in ["", ".", "..", "a/b", "/"] {
+            let args = args.clone().with("b", val);
+            let err = template.apply_path(&args).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                &ErrorKind::InvalidValue {
+                    key: "b".into(),
+                    spec: "path component".into()
+                }
+            );
+            assert_eq!(err.span(), Some(5..9));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xe9");
+        let template = Template::parse("dir/${name}.txt", Opts::default()).unwrap();
+        let args = OsArgs::new().with_os("name", name);
+        let path = template.apply_path(&args).unwrap();
+        assert_eq!(path.as_os_str().as_bytes(), b"dir/caf\xe9.txt");
+
+        let template = Template::parse("${name|title}", Opts::default()).unwrap();
+        let err = template.apply_os(&args).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::InvalidValue {
+                key: "name".into(),
+                spec: "UTF-8 string".into()
+            }
+        );
+    }
+}
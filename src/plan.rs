@@ -0,0 +1,128 @@
+use crate::{Args, Template};
+use std::collections::BTreeMap;
+
+/// What applying some args to a template would do, from [`Template::plan`](./struct.Template.html#method.plan)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApplyPlan {
+    uses: BTreeMap<String, usize>,
+    unused: Vec<String>,
+    unfilled: Vec<String>,
+    len_hint: usize,
+}
+
+impl ApplyPlan {
+    /// How many times an argument would be used, or `0` if it wouldn't be
+    pub fn uses(&self, arg: &str) -> usize {
+        self.uses.get(arg).copied().unwrap_or_default()
+    }
+
+    /// The arguments that would be used, and how many times, sorted by argument
+    pub fn used_args(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
+        self.uses.iter().map(|(arg, uses)| (&**arg, *uses))
+    }
+
+    /// Arguments that don't match any key in the template, sorted
+    pub fn unused_args(&self) -> &[String] {
+        &self.unused
+    }
+
+    /// Keys that have no argument, sorted
+    ///
+    /// This includes the keys of blocks, expressions and fallbacks
+    pub fn unfilled_keys(&self) -> &[String] {
+        &self.unfilled
+    }
+
+    /// Whether every key has an argument
+    pub fn is_complete(&self) -> bool {
+        self.unfilled.is_empty()
+    }
+
+    /// The estimated length of the output, see [`Template::len_hint`](./struct.Template.html#method.len_hint)
+    pub fn len_hint(&self) -> usize {
+        self.len_hint
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Plan applying the arguments to the template, without rendering it
+    ///
+    /// The plan tells which arguments would be used, and how many times, which keys would be left unfilled, and about
+    /// how long the output would be, so missing values can be fetched before applying the template
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let opts = Opts::default().duplicate_keys().optional_keys();
+    /// let template = Template::parse("${name} has ${count} ${name}", opts).unwrap();
+    /// let args = Args::new().with("name", "bob").with("extra", true);
+    ///
+    /// let plan = template.plan(&args);
+    /// assert_eq!(plan.uses("name"), 2);
+    /// assert_eq!(plan.unused_args(), &["extra"]);
+    /// assert_eq!(plan.unfilled_keys(), &["count"]);
+    /// assert!(!plan.is_complete());
+    /// ```
+    pub fn plan(&self, args: &Args<'_>) -> ApplyPlan {
+        let mut plan = ApplyPlan {
+            len_hint: self.len_hint(args),
+            ..ApplyPlan::default()
+        };
+
+        for key in self.names() {
+            match self.lookup_arg(args, key) {
+                Some((arg, _)) => *plan.uses.entry(arg.to_string()).or_default() += 1,
+                None => plan.unfilled.push(key.to_string()),
+            }
+        }
+
+        plan.unused = args
+            .values
+            .mapping
+            .keys()
+            .filter(|arg| !plan.uses.contains_key(&***arg))
+            .map(|arg| arg.to_string())
+            .collect();
+        plan.unused.sort();
+        plan.unfilled.sort();
+        plan.unfilled.dedup();
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn plan() {
+        let opts = Opts::default()
+            .duplicate_keys()
+            .optional_keys()
+            .expressions()
+            .case_insensitive_keys();
+        let input = "${Name ?? nick} ${?admin}(${count + 1})${/} ${name} ${missing}";
+        let template = Template::parse(input, opts).unwrap();
+
+        let args = Args::new()
+            .with("name", "bob")
+            .with("count", 2)
+            .with("other", "");
+        let plan = template.plan(&args);
+        assert_eq!(
+            plan.used_args().collect::<Vec<_>>(),
+            vec![("count", 1), ("name", 2)]
+        );
+        assert_eq!(plan.uses("other"), 0);
+        assert_eq!(plan.unused_args(), &["other"]);
+        assert_eq!(plan.unfilled_keys(), &["admin", "missing", "nick"]);
+        assert_eq!(plan.len_hint(), template.len_hint(&args));
+
+        let args = Args::new()
+            .with("name", "bob")
+            .with("nick", "b")
+            .with("admin", true)
+            .with("count", 2)
+            .with("missing", "");
+        assert!(template.plan(&args).is_complete());
+    }
+}
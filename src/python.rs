@@ -0,0 +1,181 @@
+//! Python bindings, with the `python` feature
+//!
+//! This exposes a `markings` module with `Template` and `Args` classes, which parse and apply
+//! templates exactly like the Rust types do:
+//! ```python
+//! from markings import Args, Template
+//!
+//! template = Template("${greeting}, ${name}!")
+//! assert template.apply({"greeting": "hello", "name": "bob"}) == "hello, bob!"
+//!
+//! args = Args()
+//! args["greeting"] = "bye"
+//! args.set_secret("name", "bob")
+//! assert template.apply(args) == "bye, bob!"
+//! ```
+//!
+//! Errors are raised as `markings.MarkingsError`, a subclass of `ValueError`.
+//!
+//! Build the extension module with `cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib`,
+//! and rename the library to `markings.so` (or `markings.pyd` on Windows). [maturin](https://www.maturin.rs) can also build it.
+use crate::Opts;
+use pyo3::{create_exception, exceptions::PyValueError, prelude::*, types::PyDict};
+
+create_exception!(markings, MarkingsError, PyValueError);
+
+fn to_py_err(err: crate::Error) -> PyErr {
+    MarkingsError::new_err(err.to_string())
+}
+
+/// A parsed template
+#[pyclass(name = "Template", module = "markings", frozen)]
+pub struct PyTemplate(crate::Template<'static>);
+
+#[pymethods]
+impl PyTemplate {
+    /// Parse a template. The keyword arguments are the [`Opts`](../struct.Opts.html) flags
+    #[new]
+    #[pyo3(signature = (
+        input,
+        *,
+        optional_keys = false,
+        duplicate_keys = false,
+        empty_template = false,
+        case_insensitive_keys = false
+    ))]
+    fn new(
+        input: &str,
+        optional_keys: bool,
+        duplicate_keys: bool,
+        empty_template: bool,
+        case_insensitive_keys: bool,
+    ) -> PyResult<Self> {
+        let opts = Opts {
+            optional_keys,
+            duplicate_keys,
+            empty_template,
+            case_insensitive_keys,
+            ..Opts::default()
+        };
+
+        let template = crate::Template::parse(input, opts).map_err(to_py_err)?;
+        Ok(Self(template.into_owned()))
+    }
+
+    /// Apply `Args`, or a `dict` whose values are converted with `str`
+    fn apply(&self, args: &Bound<'_, PyAny>) -> PyResult<String> {
+        let output = match args.downcast::<PyArgs>() {
+            Ok(args) => self.0.apply(&args.borrow().0),
+            Err(..) => self.0.apply(&PyArgs::from_dict(args.downcast()?)?.0),
+        };
+        output.map_err(to_py_err)
+    }
+
+    /// The keys, in the order they appear
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().collect()
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Template({:?})", self.0.to_string())
+    }
+}
+
+/// Arguments for a template
+#[pyclass(name = "Args", module = "markings")]
+#[derive(Default)]
+pub struct PyArgs(crate::Args<'static>);
+
+impl PyArgs {
+    fn from_dict(dict: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let mut args = crate::Args::new();
+        for (key, val) in dict {
+            args = args.with(key.extract::<String>()?, val.str()?);
+        }
+        Ok(Self(args))
+    }
+}
+
+#[pymethods]
+impl PyArgs {
+    /// Create args, optionally from a `dict` whose values are converted with `str`
+    #[new]
+    #[pyo3(signature = (mapping = None))]
+    fn new(mapping: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        mapping.map_or_else(|| Ok(Self::default()), Self::from_dict)
+    }
+
+    /// Map a key to a secret value, which is hidden in the `repr`
+    fn set_secret(&mut self, key: String, val: &Bound<'_, PyAny>) -> PyResult<()> {
+        let args = std::mem::take(&mut self.0);
+        self.0 = args.with_secret(key, val.str()?);
+        Ok(())
+    }
+
+    fn __setitem__(&mut self, key: String, val: &Bound<'_, PyAny>) -> PyResult<()> {
+        let args = std::mem::take(&mut self.0);
+        self.0 = args.with(key, val.str()?);
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Args({:?})", self.0)
+    }
+}
+
+/// The `markings` Python module
+#[pymodule]
+fn markings(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyTemplate>()?;
+    m.add_class::<PyArgs>()?;
+    m.add("MarkingsError", m.py().get_type::<MarkingsError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::ffi::c_str;
+
+    #[test]
+    fn module() {
+        pyo3::append_to_inittab!(markings);
+        pyo3::prepare_freethreaded_python();
+
+        Python::with_gil(|py| {
+            let code = c_str!(
+                r#"
+from markings import Args, MarkingsError, Template
+
+template = Template("${greeting}, ${name}!")
+assert template.keys() == ["greeting", "name"]
+assert str(template) == "${greeting}, ${name}!"
+assert template.apply({"greeting": "hello", "name": 42}) == "hello, 42!"
+
+args = Args({"greeting": "bye"})
+args.set_secret("name", "bob")
+assert len(args) == 2
+assert repr(args) == 'Args({"greeting": "bye", "name": <redacted>})'
+assert template.apply(args) == "bye, bob!"
+
+try:
+    Template("${a}${a}")
+    assert False
+except MarkingsError as err:
+    assert isinstance(err, ValueError)
+
+assert Template("${a}${a}", duplicate_keys=True).apply({"a": 1}) == "11"
+"#
+            );
+            py.run(code, None, None).unwrap();
+        });
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{ArgKey, Args, Result, Template};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Applies a template many times, formatting each value only when it changes
+///
+/// Values are set by key, and are only formatted with `Display` when they aren't equal to the last value of their key.
+/// This avoids formatting expensive values, like big decimal amounts, for every render when they rarely change
+/// ```
+/// # use markings::{Template, Opts, Renderer};
+/// let template = Template::parse("${name}: ${balance}", Opts::default()).unwrap();
+/// let mut renderer = Renderer::new(&template);
+///
+/// renderer.set("name", "bob").set("balance", 1234.5);
+/// assert_eq!(renderer.render().unwrap(), "bob: 1234.5");
+///
+/// // the balance isn't formatted again
+/// renderer.set("name", "alice").set("balance", 1234.5);
+/// assert_eq!(renderer.render().unwrap(), "alice: 1234.5");
+/// ```
+#[derive(Debug)]
+pub struct Renderer<'t, 'a> {
+    template: &'t Template<'a>,
+    args: Args<'static>,
+    values: HashMap<String, Box<dyn Any + Send + Sync>>,
+}
+
+impl<'t, 'a> Renderer<'t, 'a> {
+    /// Create a renderer for the template, without any values
+    pub fn new(template: &'t Template<'a>) -> Self {
+        Self {
+            template,
+            args: Args::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Set the value of a key, formatting it if it isn't equal to its last value
+    pub fn set<V>(&mut self, key: impl Into<String>, val: V) -> &mut Self
+    where
+        V: std::fmt::Display + PartialEq + Send + Sync + 'static,
+    {
+        let key = key.into();
+        let cached = self.values.get(&key).and_then(|last| last.downcast_ref());
+        if cached != Some(&val) {
+            self.args.set(ArgKey::from(key.clone()), val.to_string());
+            self.values.insert(key, Box::new(val));
+        }
+        self
+    }
+
+    /// Remove the value of a key
+    pub fn remove(&mut self, key: &str) -> &mut Self {
+        self.values.remove(key);
+        self.args.remove(key);
+        self
+    }
+
+    /// The formatted values
+    pub fn args(&self) -> &Args<'static> {
+        &self.args
+    }
+
+    /// Apply the values to the template
+    pub fn render(&self) -> Result<String> {
+        self.template.apply(&self.args)
+    }
+}
+
+/// A pool of output buffers, shared between threads, for rendering templates without allocating their output
+///
+/// Each render takes a buffer from the pool, or makes one if the pool is empty, and gives it back when it is done.
+/// Once every thread has a buffer large enough for its outputs, rendering no longer allocates for the output.
+/// Buffers that grew past [`RendererPool::max_capacity`](#method.max_capacity) aren't kept
+/// ```
+/// # use markings::{Template, Args, Opts, RendererPool};
+/// let template = Template::parse("hello ${name}", Opts::default()).unwrap();
+/// let pool = RendererPool::new();
+///
+/// std::thread::scope(|scope| {
+///     for name in ["bob", "alice"] {
+///         let (template, pool) = (&template, &pool);
+///         scope.spawn(move || {
+///             let mut out = vec![];
+///             pool.render_to(template, &Args::new().with("name", name), &mut out).unwrap();
+///             assert_eq!(out, format!("hello {}", name).as_bytes());
+///         });
+///     }
+/// });
+/// assert!(pool.idle() > 0);
+/// ```
+#[derive(Debug)]
+pub struct RendererPool {
+    buffers: Mutex<Vec<String>>,
+    max_buffers: usize,
+    max_capacity: usize,
+}
+
+impl RendererPool {
+    /// Create an empty pool, that keeps up to 64 buffers of up to 64 KiB
+    pub fn new() -> Self {
+        Self {
+            buffers: Mutex::default(),
+            max_buffers: 64,
+            max_capacity: 64 * 1024,
+        }
+    }
+
+    /// Keep up to `limit` idle buffers
+    #[must_use]
+    pub fn max_buffers(mut self, limit: usize) -> Self {
+        self.max_buffers = limit;
+        self
+    }
+
+    /// Don't keep buffers with a capacity over `limit` bytes, so one large output doesn't stay allocated
+    #[must_use]
+    pub fn max_capacity(mut self, limit: usize) -> Self {
+        self.max_capacity = limit;
+        self
+    }
+
+    /// How many buffers are waiting to be used
+    pub fn idle(&self) -> usize {
+        self.buffers().len()
+    }
+
+    /// Apply the args to the template, writing the output to `writer`, like [`Template::render_to`](./struct.Template.html#method.render_to)
+    pub fn render_to(
+        &self,
+        template: &Template<'_>,
+        args: &Args<'_>,
+        mut writer: impl std::io::Write,
+    ) -> Result<()> {
+        self.render_with(template, args, |output| writer.write_all(output.as_bytes()))??;
+        Ok(())
+    }
+
+    /// Apply the args to the template, calling `f` with the output
+    pub fn render_with<T>(
+        &self,
+        template: &Template<'_>,
+        args: &Args<'_>,
+        f: impl FnOnce(&str) -> T,
+    ) -> Result<T> {
+        let mut buffer = self.buffers().pop().unwrap_or_default();
+        let result = template
+            .render_into(args, &mut buffer, |_, _, _| {})
+            .map(|_| f(&buffer));
+
+        buffer.clear();
+        let mut buffers = self.buffers();
+        if buffer.capacity() <= self.max_capacity && buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+        result
+    }
+
+    // a poisoned pool is still a pool of empty buffers
+    fn buffers(&self) -> MutexGuard<'_, Vec<String>> {
+        self.buffers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for RendererPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // counts how many times it was formatted
+    #[derive(Debug, Clone)]
+    struct Counted(i32, Arc<AtomicUsize>);
+
+    impl PartialEq for Counted {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl std::fmt::Display for Counted {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.fmt(f)
+        }
+    }
+
+    #[test]
+    fn memoized() {
+        let template = Template::parse("${a} ${b}", Opts::default()).unwrap();
+        let mut renderer = Renderer::new(&template);
+        let count = Arc::new(AtomicUsize::new(0));
+        let value = |n| Counted(n, Arc::clone(&count));
+
+        renderer.set("a", value(1)).set("b", 1);
+        assert_eq!(renderer.render().unwrap(), "1 1");
+        renderer.set("a", value(1)).set("b", 2);
+        assert_eq!(renderer.render().unwrap(), "1 2");
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        renderer.set("a", value(2));
+        assert_eq!(renderer.render().unwrap(), "2 2");
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+
+        // values of another type are formatted
+        renderer.set("a", "2");
+        renderer.set("a", value(2));
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+
+        renderer.remove("b");
+        assert!(renderer.render().is_err());
+        assert_eq!(renderer.args().len(), 1);
+    }
+
+    #[test]
+    fn pool() {
+        let template = Template::parse("${a}", Opts::default()).unwrap();
+        let pool = RendererPool::new().max_buffers(1).max_capacity(16);
+
+        let len = pool.render_with(&template, &Args::new().with("a", "abc"), str::len);
+        assert_eq!(len.unwrap(), 3);
+        assert_eq!(pool.idle(), 1);
+
+        // too large to keep
+        let mut out = vec![];
+        let args = Args::new().with("a", "a".repeat(32));
+        pool.render_to(&template, &args, &mut out).unwrap();
+        assert_eq!(out.len(), 32);
+        assert_eq!(pool.idle(), 0);
+
+        assert!(pool.render_with(&template, &Args::new(), |_| ()).is_err());
+        assert_eq!(pool.idle(), 1);
+        assert!(pool.buffers().iter().all(String::is_empty));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{Args, ErrorKind, Result, Template};
+use std::future::Future;
+
+/// A source of values that are fetched asynchronously
+///
+/// This is useful when the keys in a template map to things like database lookups or HTTP calls
+///
+/// ```
+/// # use markings::AsyncResolver;
+/// struct Users;
+///
+/// impl AsyncResolver for Users {
+///     async fn resolve(&self, key: &str) -> Option<String> {
+///         match key {
+///             "name" => Some("bob".to_string()),
+///             _ => None,
+///         }
+///     }
+/// }
+/// ```
+pub trait AsyncResolver {
+    /// Resolve the value for `key`, returning `None` if there isn't one
+    fn resolve(&self, key: &str) -> impl Future<Output = Option<String>> + Send;
+}
+
+impl<'a> Template<'a> {
+    /// Apply values from an [`AsyncResolver`](./trait.AsyncResolver.html) to the template
+    ///
+    /// Each distinct key in the template, including the keys of blocks, is resolved once, in order of appearance. Keys that the
+    /// resolver has no value for are a `MissingKeys` error, unless keys are optional, like with [`Template::apply`](./struct.Template.html#method.apply)
+    ///
+    /// This fails with `Sandboxed` if the [`Sandbox`](./struct.Sandbox.html) of the template's options doesn't allow resolvers
+    pub async fn apply_async(&self, resolver: &impl AsyncResolver) -> Result<String> {
+        if !self.opts.allows_resolvers() {
+            let feature = "resolver".into();
+            return Err(ErrorKind::Sandboxed { feature }.into());
+        }
+
+        let mut keys = vec![];
+        for key in self.names() {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+
+        let mut args = Args::new();
+        for key in keys {
+            if let Some(val) = resolver.resolve(key).await {
+                args = args.with(key, val);
+            }
+        }
+        self.apply(&args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = std::pin::pin!(fut);
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        loop {
+            if let std::task::Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    struct Counting(AtomicUsize);
+
+    impl AsyncResolver for Counting {
+        async fn resolve(&self, key: &str) -> Option<String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match key {
+                "unknown" => None,
+                key => Some(key.to_uppercase()),
+            }
+        }
+    }
+
+    #[test]
+    fn apply_async() {
+        let opts = Opts::default().duplicate_keys().optional_keys().build();
+        let template = Template::parse("${a} ${b} ${a} ${unknown}${?b}!${/}", opts).unwrap();
+        let resolver = Counting(AtomicUsize::new(0));
+
+        let output = block_on(template.apply_async(&resolver)).unwrap();
+        assert_eq!(output, "A B A ${unknown}!");
+        assert_eq!(resolver.0.load(Ordering::SeqCst), 3);
+
+        let opts = Opts::default().duplicate_keys().build();
+        let template = Template::parse("${a} ${unknown}", opts).unwrap();
+        let err = block_on(template.apply_async(&resolver)).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &crate::ErrorKind::MissingKeys {
+                keys: vec!["unknown".into()]
+            }
+        );
+    }
+}
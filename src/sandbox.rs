@@ -0,0 +1,280 @@
+use crate::Opts;
+
+/// Options for templates written by untrusted users, applied with [`Opts::sandbox`](./struct.Opts.html#method.sandbox)
+///
+/// A sandbox bundles the limits that keep a template from doing too much, so they are set, and can be audited, in one place:
+/// * limits on the number of keys, the length of keys, how deeply markers nest, the length of the template and the length of the output
+/// * an allow-list of filters, which are the [`Sandbox::FILTERS`](#associatedconstant.FILTERS) by default. Custom filters and `re` have to be allowed by name
+/// * an optional allow-list of keys, see [`Opts::allowed_keys`](./struct.Opts.html#method.allowed_keys)
+/// * no templates extending other templates in a [`TemplateSet`](./struct.TemplateSet.html), unless [`Sandbox::allow_includes`](#method.allow_includes)
+/// * no values from an [`AsyncResolver`](./trait.AsyncResolver.html), unless [`Sandbox::allow_resolvers`](#method.allow_resolvers)
+///
+/// Anything the sandbox doesn't allow is a `Sandboxed` error
+/// ```
+/// # use markings::{Template, Args, Opts, Sandbox, ErrorKind};
+/// let sandbox = Sandbox::new().max_keys(2).allowed_keys(&["name", "count"]);
+/// let opts = Opts::default().sandbox(sandbox);
+///
+/// let template = Template::parse("hi ${name|title}", opts.clone()).unwrap();
+/// assert_eq!(template.apply(&Args::new().with("name", "bob")).unwrap(), "hi Bob");
+///
+/// assert!(Template::parse("${name} ${count} ${name}", opts.clone().duplicate_keys()).is_err());
+/// assert!(Template::parse("${password}", opts.clone()).is_err());
+/// assert!(Template::parse("${name|re:a:b}", opts).is_err());
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[must_use]
+pub struct Sandbox {
+    max_keys: usize,
+    max_key_len: usize,
+    max_depth: usize,
+    max_template_len: usize,
+    max_output_len: usize,
+    allowed_filters: &'static [&'static str],
+    allowed_keys: Option<&'static [&'static str]>,
+    includes: bool,
+    resolvers: bool,
+}
+
+impl Sandbox {
+    /// The filters allowed by default: the colors, the escapes and the text filters
+    pub const FILTERS: &'static [&'static str] = &[
+        "bold",
+        "dim",
+        "italic",
+        "underline",
+        "black",
+        "red",
+        "green",
+        "yellow",
+        "blue",
+        "magenta",
+        "cyan",
+        "white",
+        "md",
+        "sh",
+        "ps",
+        "sql_ident",
+        "sql_str",
+        "truncate",
+        "pad_left",
+        "pad_right",
+        "replace",
+        "capitalize",
+        "title",
+        "slug",
+        "reverse",
+    ];
+
+    /// A sandbox with the default limits
+    ///
+    /// Templates can have up to 32 keys of up to 64 bytes, can nest up to 16 deep, can be up to 4 KiB long, and can
+    /// render up to 16 KiB
+    pub fn new() -> Self {
+        Self {
+            max_keys: 32,
+            max_key_len: 64,
+            max_depth: 16,
+            max_template_len: 4 * 1024,
+            max_output_len: 16 * 1024,
+            allowed_filters: Self::FILTERS,
+            allowed_keys: None,
+            includes: false,
+            resolvers: false,
+        }
+    }
+
+    /// Limit the number of keys, see [`Opts::max_keys`](./struct.Opts.html#method.max_keys)
+    pub fn max_keys(mut self, limit: usize) -> Self {
+        self.max_keys = limit;
+        self
+    }
+
+    /// Limit the length of keys, see [`Opts::max_key_len`](./struct.Opts.html#method.max_key_len)
+    pub fn max_key_len(mut self, limit: usize) -> Self {
+        self.max_key_len = limit;
+        self
+    }
+
+    /// Limit how deeply markers nest, see [`Opts::max_depth`](./struct.Opts.html#method.max_depth)
+    pub fn max_depth(mut self, limit: usize) -> Self {
+        self.max_depth = limit;
+        self
+    }
+
+    /// Limit the length of templates, see [`Opts::max_template_len`](./struct.Opts.html#method.max_template_len)
+    pub fn max_template_len(mut self, limit: usize) -> Self {
+        self.max_template_len = limit;
+        self
+    }
+
+    /// Limit the length of the output, see [`Opts::max_output_len`](./struct.Opts.html#method.max_output_len)
+    pub fn max_output_len(mut self, limit: usize) -> Self {
+        self.max_output_len = limit;
+        self
+    }
+
+    /// Only allow these filters, instead of the [`Sandbox::FILTERS`](#associatedconstant.FILTERS)
+    pub fn allowed_filters(mut self, names: &'static [&'static str]) -> Self {
+        self.allowed_filters = names;
+        self
+    }
+
+    /// Only allow these keys, see [`Opts::allowed_keys`](./struct.Opts.html#method.allowed_keys)
+    pub fn allowed_keys(mut self, keys: &'static [&'static str]) -> Self {
+        self.allowed_keys = Some(keys);
+        self
+    }
+
+    /// Allow templates in a [`TemplateSet`](./struct.TemplateSet.html) to extend other templates
+    pub fn allow_includes(mut self) -> Self {
+        self.includes = true;
+        self
+    }
+
+    /// Allow templates to be applied with an [`AsyncResolver`](./trait.AsyncResolver.html)
+    pub fn allow_resolvers(mut self) -> Self {
+        self.resolvers = true;
+        self
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Opts {
+    /// Apply a [`Sandbox`](./struct.Sandbox.html) to the options
+    ///
+    /// The limits of the sandbox replace larger limits, so they can't be raised by the header of a template, or by a
+    /// preset named in it. The allow-lists replace any that were set before
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        let min =
+            |limit: Option<usize>, max: usize| Some(limit.map_or(max, |limit| limit.min(max)));
+        self.max_keys = min(self.max_keys, sandbox.max_keys);
+        self.max_key_len = min(self.max_key_len, sandbox.max_key_len);
+        self.max_depth = min(self.max_depth, sandbox.max_depth);
+        self.max_template_len = min(self.max_template_len, sandbox.max_template_len);
+        self.max_output_len = min(self.max_output_len, sandbox.max_output_len);
+        self.allowed_filters = Some(sandbox.allowed_filters);
+        self.allowed_keys = sandbox.allowed_keys.or(self.allowed_keys);
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// The [`Sandbox`](./struct.Sandbox.html) the options were made with, if any
+    pub fn sandboxed(&self) -> Option<Sandbox> {
+        self.sandbox
+    }
+
+    // whether templates can extend other templates
+    pub(crate) fn allows_includes(&self) -> bool {
+        self.sandbox.is_none_or(|sandbox| sandbox.includes)
+    }
+
+    // whether templates can be applied with a resolver
+    #[cfg(feature = "async")]
+    pub(crate) fn allows_resolvers(&self) -> bool {
+        self.sandbox.is_none_or(|sandbox| sandbox.resolvers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, ErrorKind, Template, TemplateSet};
+
+    #[test]
+    fn limits() {
+        let sandbox = Sandbox::new()
+            .max_keys(3)
+            .max_output_len(8)
+            .allowed_filters(&["bold"]);
+        let opts = Opts::default().max_keys(2).sandbox(sandbox);
+        assert_eq!(opts.sandboxed(), Some(sandbox));
+
+        // the smaller limit is kept
+        let err = Template::parse("${a}${b}${c}", opts.clone()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TooManyKeys { limit: 2 });
+
+        let template = Template::parse("${a|bold}", opts.clone()).unwrap();
+        let err = template
+            .apply(&Args::new().with("a", "far too long"))
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::OutputTooLarge { limit: 8 });
+
+        // the default filters can't make a value larger than the output can be, even one that is cut short again
+        let source = r#"${a|replace:"a":"aaaaaaaaaa"|truncate:1}"#;
+        let template = Template::parse(source, Opts::default().sandbox(Sandbox::new())).unwrap();
+        let err = template
+            .apply(&Args::new().with("a", "a".repeat(2 * 1024)))
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::OutputTooLarge { limit: 16 * 1024 });
+
+        let err = Template::parse("${a|title}", opts.clone()).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::ForbiddenFilter {
+                name: "title".into()
+            }
+        );
+
+        // expressions can't nest deeper than the sandbox allows
+        let nested = Opts::default()
+            .expressions()
+            .max_depth(100)
+            .sandbox(sandbox.max_depth(3));
+        assert!(Template::parse("${-(-a)}", nested.clone()).is_ok());
+        let err = Template::parse("${-(-(-a))}", nested.clone()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 0 });
+        assert!(
+            Template::parse_with_frontmatter("#! max_depth: 100\n${-(-(-a))}", nested).is_err()
+        );
+
+        // headers can't go past the limits of the sandbox
+        let input = "#! max_keys: 100, max_output_len: 100\n${a}${b}${c}${d}";
+        let err = Template::parse_with_frontmatter(input, opts).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TooManyKeys { limit: 3 });
+    }
+
+    #[test]
+    fn sets() {
+        let opts = Opts::default()
+            .empty_template()
+            .sandbox(Sandbox::new().max_keys(1));
+        let sources = [
+            ("base", "${block body}${/block}"),
+            (
+                "child",
+                "---\nextends: base\n---\n${block body}${a}${/block}",
+            ),
+        ];
+        let err = TemplateSet::from_sources(sources, opts.clone()).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::Sandboxed {
+                feature: "extends".into()
+            }
+        );
+
+        let mut set = TemplateSet::new(opts.clone());
+        set.add("base", "${block body}${/block}").unwrap();
+        assert!(set.extend("child", "base", "").is_err());
+
+        // presets are sandboxed too
+        let sources = [("lenient", "---\npreset: lenient\n---\n${a}${b}")];
+        let err = TemplateSet::from_sources(sources, opts).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TooManyKeys { limit: 1 });
+
+        let opts = Opts::default()
+            .empty_template()
+            .sandbox(Sandbox::new().allow_includes());
+        assert!(TemplateSet::from_sources(
+            [("base", "${a}"), ("child", "---\nextends: base\n---\n")],
+            opts
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{Args, DuplicateArgs, ErrorKind, Result, Template};
+use std::collections::BTreeMap;
+
+/// The kind of value a key in a [`Schema`](./struct.Schema.html) expects
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    /// A whole number, like `-42`
+    Int,
+    /// A decimal number, like `4.5`. Whole numbers are also accepted
+    Float,
+    /// `true` or `false`
+    Bool,
+    /// Any value
+    String,
+    /// A list, formatted like a JSON array: `[1, 2]`
+    List,
+}
+
+impl ValueKind {
+    /// Does the value have this kind?
+    /// ```
+    /// # use markings::ValueKind;
+    /// assert!(ValueKind::Int.matches("42"));
+    /// assert!(!ValueKind::Int.matches("4.2"));
+    /// assert!(ValueKind::List.matches("[1, 2]"));
+    /// ```
+    pub fn matches(self, value: &str) -> bool {
+        match self {
+            ValueKind::Int => value.parse::<i64>().is_ok(),
+            ValueKind::Float => value.parse::<f64>().is_ok_and(f64::is_finite),
+            ValueKind::Bool => matches!(value, "true" | "false"),
+            ValueKind::String => true,
+            ValueKind::List => value.starts_with('[') && value.ends_with(']'),
+        }
+    }
+}
+
+impl std::fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            ValueKind::Int => "int",
+            ValueKind::Float => "float",
+            ValueKind::Bool => "bool",
+            ValueKind::String => "string",
+            ValueKind::List => "list",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// The kinds of values that the keys of a template expect
+///
+/// Every key in the schema needs a value of its kind, see [`Template::apply_validated`](./struct.Template.html#method.apply_validated).
+/// Arguments that aren't in the schema aren't checked
+/// ```
+/// # use markings::{Args, Opts, Schema, Template, ValueKind, ErrorKind};
+/// let schema = Schema::new()
+///     .with("name", ValueKind::String)
+///     .with("count", ValueKind::Int)
+///     .with("admin", ValueKind::Bool);
+///
+/// let template = Template::parse("${name}: ${count}${?admin}!${/}", Opts::default()).unwrap();
+/// let args = Args::new().with("name", "bob").with("count", 3).with("admin", true);
+/// assert_eq!(template.apply_validated(&args, &schema).unwrap(), "bob: 3!");
+///
+/// let args = Args::new().with("name", "bob").with("count", "many");
+/// let err = template.apply_validated(&args, &schema).unwrap_err();
+/// match err.kind() {
+///     ErrorKind::SchemaViolations { violations } => assert_eq!(violations.len(), 2),
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[must_use]
+pub struct Schema {
+    keys: BTreeMap<String, ValueKind>,
+}
+
+impl Schema {
+    /// Create an empty schema
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect a value of `kind` for `key`
+    pub fn with(mut self, key: impl Into<String>, kind: ValueKind) -> Self {
+        self.keys.insert(key.into(), kind);
+        self
+    }
+
+    /// The kind expected for a key
+    pub fn get(&self, key: &str) -> Option<ValueKind> {
+        self.keys.get(key).copied()
+    }
+
+    /// The keys in the schema, and their kinds, sorted by key
+    pub fn iter(&self) -> impl Iterator<Item = (&str, ValueKind)> + '_ {
+        self.keys.iter().map(|(key, kind)| (&**key, *kind))
+    }
+
+    /// Check the args against the schema, returning every violation, sorted by key
+    ///
+    /// Keys are matched exactly, and the last value of an arg that was given more than once is checked.
+    /// [`Template::apply_validated`](./struct.Template.html#method.apply_validated) checks the values the template
+    /// would use instead, with its options
+    pub fn check(&self, args: &Args<'_>) -> Vec<Violation> {
+        self.check_with(|key| args.value(key, DuplicateArgs::LastWins))
+    }
+
+    // checks the value that `lookup` finds for each key
+    fn check_with<'s>(&self, lookup: impl Fn(&str) -> Option<&'s str>) -> Vec<Violation> {
+        self.iter()
+            .filter_map(|(key, expected)| {
+                let found = lookup(key);
+                match found {
+                    Some(value) if expected.matches(value) => None,
+                    _ => Some(Violation {
+                        key: key.to_string(),
+                        expected,
+                        found: found.map(str::to_string),
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    // fails with all of the violations of the values the template would use, if there are any
+    pub(crate) fn validate(&self, template: &Template<'_>, args: &Args<'_>) -> Result<()> {
+        let violations = self.check_with(|key| template.lookup(args, key));
+        if !violations.is_empty() {
+            return Err(ErrorKind::SchemaViolations { violations }.into());
+        }
+        Ok(())
+    }
+}
+
+/// A value that didn't match a [`Schema`](./struct.Schema.html)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Violation {
+    key: String,
+    expected: ValueKind,
+    found: Option<String>,
+}
+
+impl Violation {
+    /// The key of the value
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The kind of value the schema expected
+    pub fn expected(&self) -> ValueKind {
+        self.expected
+    }
+
+    /// The value that was found, or `None` if the key had no value
+    pub fn found(&self) -> Option<&str> {
+        self.found.as_deref()
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.found {
+            Some(found) => write!(
+                f,
+                "'{}' should be a {}, but was '{}'",
+                self.key, self.expected, found
+            ),
+            None => write!(
+                f,
+                "'{}' should be a {}, but is missing",
+                self.key, self.expected
+            ),
+        }
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Check the args against a [`Schema`](./struct.Schema.html), then apply them to the template
+    ///
+    /// The values are looked up like they are when applying, so the options of the template for
+    /// [key case](./struct.Opts.html#method.case_insensitive_keys) and [duplicate args](./struct.Opts.html#method.duplicate_args) apply.
+    /// Every violation is reported at once, in a `SchemaViolations` error
+    pub fn apply_validated(&self, args: &Args<'_>, schema: &Schema) -> Result<String> {
+        schema.validate(self, args)?;
+        self.apply(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn violations() {
+        let schema = Schema::new()
+            .with("a", ValueKind::Int)
+            .with("b", ValueKind::Float)
+            .with("c", ValueKind::List)
+            .with("d", ValueKind::String);
+        let template = Template::parse("${a} ${b} ${c} ${d}", Opts::default()).unwrap();
+
+        let args = Args::new()
+            .with("a", 1)
+            .with("b", 1)
+            .with("c", "[]")
+            .with("d", "");
+        assert_eq!(template.apply_validated(&args, &schema).unwrap(), "1 1 [] ");
+
+        let args = Args::new()
+            .with("a", "1.5")
+            .with("b", "inf")
+            .with("c", "[]");
+        let err = template.apply_validated(&args, &schema).unwrap_err();
+        let expected = vec![
+            Violation {
+                key: "a".into(),
+                expected: ValueKind::Int,
+                found: Some("1.5".into()),
+            },
+            Violation {
+                key: "b".into(),
+                expected: ValueKind::Float,
+                found: Some("inf".into()),
+            },
+            Violation {
+                key: "d".into(),
+                expected: ValueKind::String,
+                found: None,
+            },
+        ];
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::SchemaViolations {
+                violations: expected
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "the args didn't match the schema: 'a' should be a int, but was '1.5', \
+             'b' should be a float, but was 'inf', 'd' should be a string, but is missing"
+        );
+
+        // the value that is applied is the one that is checked
+        let opts = Opts::default().duplicate_args(crate::DuplicateArgs::FirstWins);
+        let template = Template::parse("${a}", opts).unwrap();
+        let schema = Schema::new().with("a", ValueKind::Int);
+        let args = Args::new().with("a", "one").with("a", 1);
+        assert!(template.apply_validated(&args, &schema).is_err());
+        let args = Args::new().with("a", 1).with("a", "one");
+        assert_eq!(template.apply_validated(&args, &schema).unwrap(), "1");
+        assert_eq!(schema.check(&args).len(), 1);
+
+        // and it is found like it is when applying
+        let opts = Opts::default().case_insensitive_keys();
+        let template = Template::parse("${count}", opts).unwrap();
+        let schema = Schema::new().with("Count", ValueKind::Int);
+        let args = Args::new().with("COUNT", 3);
+        assert_eq!(template.apply_validated(&args, &schema).unwrap(), "3");
+        let args = Args::new().with("COUNT", "three");
+        assert!(template.apply_validated(&args, &schema).is_err());
+        assert_eq!(schema.check(&args)[0].found(), None);
+    }
+}
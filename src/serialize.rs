@@ -0,0 +1,46 @@
+use crate::Error;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// Errors can be serialized with [`serde`](https://docs.rs/serde), with the `serde` feature
+///
+/// An error is a struct of its stable `code`, see [`Error::code`](./struct.Error.html#method.code), its `message`,
+/// and its `span` in the template source, which is a struct of a `start` and an `end`, or `None`.
+/// As JSON, that looks like `{"code":"M0005","message":"duplicate keys were found","span":{"start":5,"end":9}}`
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("Error", 3)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.serialize_field("span", &self.span())?;
+        error.end()
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use crate::{Opts, Template};
+    use serde_json::json;
+
+    #[test]
+    fn json() {
+        let err = Template::parse("${a} ${a}", Opts::default()).unwrap_err();
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            json!({
+                "code": "M0005",
+                "message": "duplicate keys were found",
+                "span": { "start": 5, "end": 9 },
+            })
+        );
+
+        let err = Template::parse("hello", Opts::default()).unwrap_err();
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            json!({
+                "code": "M0006",
+                "message": "empty template was found",
+                "span": null,
+            })
+        );
+    }
+}
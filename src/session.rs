@@ -0,0 +1,195 @@
+use crate::{filters, ArgKey, Args, Error, ErrorKind, Result, Segment, SourceMap, Template};
+use std::borrow::Cow;
+
+/// Renders a template again as its args change, only re-rendering the parts of the output that changed
+///
+/// The session keeps the last output and where each key was rendered in it. When the value of an argument changes,
+/// the output of its keys is rendered again and spliced into the last output. Arguments that blocks, expressions
+/// or fallbacks depend on, that are new, or that are used in a `${@key}` block, render the whole template again
+/// ```
+/// # use markings::{Template, Args, Opts, RenderSession};
+/// let template = Template::parse("up ${uptime|pad_left:5}s, ${users} users", Opts::default()).unwrap();
+/// let args = Args::new().with("uptime", 1).with("users", 3);
+///
+/// let mut session = RenderSession::new(&template, args).unwrap();
+/// assert_eq!(session.output(), "up     1s, 3 users");
+///
+/// assert_eq!(session.set("uptime", 2).unwrap(), "up     2s, 3 users");
+/// assert_eq!(session.set("users", 10).unwrap(), "up     2s, 10 users");
+/// ```
+#[derive(Debug)]
+pub struct RenderSession<'t, 'a, 'k> {
+    template: &'t Template<'a>,
+    args: Args<'k>,
+    output: String,
+    map: SourceMap,
+}
+
+impl<'t, 'a, 'k> RenderSession<'t, 'a, 'k> {
+    /// Render the template with the args, starting a session
+    pub fn new(template: &'t Template<'a>, args: Args<'k>) -> Result<Self> {
+        let (output, map) = template.apply_mapped(&args)?;
+        Ok(Self {
+            template,
+            args,
+            output,
+            map,
+        })
+    }
+
+    /// The output of the last render
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// The args of the last render
+    pub fn args(&self) -> &Args<'k> {
+        &self.args
+    }
+
+    /// Change the value of an argument, returning the new output
+    ///
+    /// If rendering fails, the argument keeps its previous value, and the output doesn't change
+    pub fn set(
+        &mut self,
+        key: impl Into<Cow<'k, str>>,
+        val: impl std::fmt::Display,
+    ) -> Result<&str> {
+        let key = key.into();
+        let val = val.to_string();
+        if self.args.values.mapping.get(&*key) == Some(&val) {
+            return Ok(&self.output);
+        }
+
+        let mut args = self.args.clone();
+        args.set(ArgKey::from(key.clone()), val);
+        let splices = self.args.values.mapping.contains_key(&*key) && self.splices(&key);
+        if splices {
+            self.splice(&key, &args)?;
+        } else {
+            let (output, map) = self.template.apply_mapped(&args)?;
+            self.output = output;
+            self.map = map;
+        }
+        self.args = args;
+        Ok(&self.output)
+    }
+
+    // whether the arg is only used by keys, so its keys can be rendered on their own
+    fn splices(&self, arg: &str) -> bool {
+        let template = self.template;
+        let used = |key: &str| template.opts.key_eq(key, arg);
+        template.scopes().is_empty()
+            && !template.inner_keys().any(used)
+            && template.segments.iter().all(|segment| match segment {
+                Segment::Key {
+                    name, fallbacks, ..
+                } => fallbacks.is_empty() || !used(name),
+                Segment::Block { kind, name, .. } => !(kind.uses_key() && used(name)),
+                Segment::Literal(..) | Segment::Expr { .. } => true,
+            })
+    }
+
+    // renders the keys of the arg again, replacing their parts of the output
+    fn splice(&mut self, arg: &str, args: &Args<'_>) -> Result<()> {
+        let template = self.template;
+        let val = match template.lookup(args, arg) {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+
+        let mut output = self.output.clone();
+        let mut map = self.map.clone();
+        let mut shift = 0isize;
+        for mapping in &mut map.mappings {
+            let start = (mapping.output.start as isize + shift) as usize;
+            let end = (mapping.output.end as isize + shift) as usize;
+            mapping.output = start..end;
+
+            let key = match mapping.key.as_deref() {
+                Some(key) if template.opts.key_eq(key, arg) => key,
+                _ => continue,
+            };
+            let segment = template
+                .spans()
+                .find(|(_, span)| *span == mapping.source)
+                .map(|(segment, _)| segment);
+            let (chain, spec) = match segment {
+                Some(Segment::Key { chain, spec, .. }) => (chain, spec),
+                _ => continue,
+            };
+
+            if let Some(spec) = spec.as_deref() {
+                if template.opts.validate_key_specs && !crate::spec_matches(spec, val) {
+                    let (key, spec) = (key.to_string(), spec.to_string());
+                    let err = Error::from(ErrorKind::InvalidValue { key, spec });
+                    return Err(err.with_span(mapping.source.clone()));
+                }
+            }
+            let piece = filters::apply(key, chain, val, &template.opts)
+                .map_err(|err| err.with_span(mapping.source.clone()))?;
+
+            output.replace_range(start..end, &piece);
+            shift += piece.len() as isize - (end - start) as isize;
+            mapping.output = start..start + piece.len();
+        }
+
+        if let Some(limit) = template.opts.max_output_len {
+            if output.len() > limit {
+                return Err(ErrorKind::OutputTooLarge { limit }.into());
+            }
+        }
+        self.output = output;
+        self.map = map;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn splices() {
+        let opts = Opts::default().duplicate_keys().optional_keys();
+        let input = "${a|title} ${b}: ${a}${?b}!${/}";
+        let template = Template::parse(input, opts).unwrap();
+        let args = Args::new().with("a", "x").with("b", "");
+        let mut session = RenderSession::new(&template, args).unwrap();
+        assert_eq!(session.output(), "X : x");
+
+        assert!(session.splices("a"));
+        assert!(!session.splices("b"));
+
+        let cases = [
+            ("a", "long value", "Long Value : long value"),
+            ("a", "", " : "),
+            ("b", "yes", " yes: !"),
+            ("a", "y", "Y yes: y!"),
+            ("c", "unused", "Y yes: y!"),
+            ("b", "", "Y : y"),
+        ];
+        for (key, val, expected) in &cases {
+            assert_eq!(session.set(*key, val).unwrap(), *expected);
+            let full = template.apply_mapped(session.args()).unwrap();
+            assert_eq!((session.output(), &session.map), (&*full.0, &full.1));
+        }
+    }
+
+    #[test]
+    fn failures() {
+        let opts = Opts::default().max_output_len(8).validate_key_specs();
+        let template = Template::parse("${a} ${n!number}", opts).unwrap();
+        let args = Args::new().with("a", "x").with("n", 1);
+        let mut session = RenderSession::new(&template, args).unwrap();
+
+        let err = session.set("a", "too long").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::OutputTooLarge { limit: 8 });
+        let err = session.set("n", "one").unwrap_err();
+        assert_eq!(err.span(), Some(5..16));
+
+        assert_eq!(session.output(), "x 1");
+        assert_eq!(session.args(), &Args::new().with("a", "x").with("n", 1));
+    }
+}
@@ -0,0 +1,963 @@
+//! Named collections of templates
+use crate::{Args, BlockKind, ErrorKind, Opts, Result, Schema, Segment, Template};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A collection of named templates that share the same [`Opts`](./struct.Opts.html)
+///
+/// Templates in a set can *extend* another template in the set. The base template marks regions with
+/// `${block name}default${/block}`, and the child template overrides them with its own regions.
+/// Everything in the child outside of a region is ignored, and regions it doesn't override keep their default.
+///
+/// Inheritance is resolved when the child is added, so applying a child is the same as applying any other template
+/// ```
+/// # use markings::{Args, Opts, TemplateSet};
+/// let mut set = TemplateSet::new(Opts::default().optional_keys());
+/// set.add("email", "${block header}Hello ${name},${/block}\n${block body}${/block}\nBye").unwrap();
+/// set.extend("welcome", "email", "${block body}Welcome to ${site}!${/block}").unwrap();
+///
+/// let args = Args::new().with("name", "bob").with("site", "example.com");
+/// assert_eq!(set.apply("welcome", &args).unwrap(), "Hello bob,\nWelcome to example.com!\nBye");
+/// ```
+///
+/// The set counts lookups and renders, see [`TemplateSet::stats`](#method.stats)
+///
+/// A set can have a *version*, and migrations that upgrade stored templates from older versions when they
+/// are added, see [`TemplateSet::add_versioned`](#method.add_versioned). Loaded files name their version in their header
+///
+/// Templates can be parsed with other options than the set's, by naming a *preset*, see [`TemplateSet::add_preset`](#method.add_preset)
+///
+/// A template can have a [`Schema`](./struct.Schema.html) for its args, see [`TemplateSet::set_schema`](#method.set_schema)
+#[derive(Clone, Debug, Default)]
+pub struct TemplateSet<'a> {
+    templates: BTreeMap<String, Template<'a>>,
+    opts: Opts,
+    counters: Counters,
+    version: u32,
+    migrations: Migrations,
+    presets: BTreeMap<String, Opts>,
+    schemas: BTreeMap<String, Schema>,
+}
+
+impl<'a> TemplateSet<'a> {
+    /// Create an empty set, whose templates are parsed with `opts`
+    pub fn new(opts: Opts) -> Self {
+        Self {
+            templates: BTreeMap::new(),
+            opts,
+            counters: Counters::default(),
+            version: 0,
+            migrations: Migrations::default(),
+            presets: BTreeMap::new(),
+            schemas: BTreeMap::new(),
+        }
+    }
+
+    /// Set the version of the templates in the set. This is `0` by default
+    pub fn versioned(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The version of the templates in the set
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Add a migration that upgrades the source of a template from version `from` to `from + 1`
+    ///
+    /// This replaces any migration from the same version
+    pub fn migration(
+        &mut self,
+        from: u32,
+        migrate: impl Fn(&str) -> Result<String> + Send + Sync + 'static,
+    ) {
+        self.migrations.0.insert(from, Arc::new(migrate));
+    }
+
+    /// Parse a template stored at `version`, and add it to the set
+    ///
+    /// The source is migrated to the version of the set first. This returns the version each migration
+    /// upgraded from, in the order they ran. It fails with `UnsupportedVersion` if the template is newer
+    /// than the set, or a migration is missing
+    /// ```
+    /// # use markings::{Args, Opts, TemplateSet};
+    /// let mut set = TemplateSet::new(Opts::default()).versioned(2);
+    /// // version 1 renamed `${user}` to `${name}`
+    /// set.migration(0, |source| Ok(source.replace("${user}", "${name}")));
+    /// // version 2 replaced `{{key}}` with `${key}`
+    /// set.migration(1, |source| Ok(source.replace("{{", "${").replace("}}", "}")));
+    ///
+    /// let ran = set.add_versioned("greeting", "hello {{greeting}}, ${user}", 0).unwrap();
+    /// assert_eq!(ran, vec![0, 1]);
+    /// assert_eq!(set.get("greeting").unwrap().to_string(), "hello ${greeting}, ${name}");
+    ///
+    /// let ran = set.add_versioned("farewell", "bye ${name}", 2).unwrap();
+    /// assert!(ran.is_empty());
+    /// ```
+    pub fn add_versioned(
+        &mut self,
+        name: impl Into<String>,
+        input: &'a str,
+        version: u32,
+    ) -> Result<Vec<u32>> {
+        if version == self.version {
+            return self.add(name, input).map(|_| vec![]);
+        }
+
+        let source = self.migrate(input, version)?;
+        let template = Template::parse(&source, self.opts.clone())?.into_owned();
+        self.templates.insert(name.into(), template);
+        Ok((version..self.version).collect())
+    }
+
+    // upgrades a source stored at `version` to the version of the set
+    fn migrate(&self, input: &str, version: u32) -> Result<String> {
+        if version > self.version {
+            return Err(ErrorKind::UnsupportedVersion { version }.into());
+        }
+        let mut source = input.to_string();
+        for from in version..self.version {
+            let migrate = self
+                .migrations
+                .0
+                .get(&from)
+                .ok_or(ErrorKind::UnsupportedVersion { version: from })?;
+            source = migrate(&source)?;
+        }
+        Ok(source)
+    }
+
+    /// Parse a template and add it to the set, replacing any template with the same name
+    pub fn add(&mut self, name: impl Into<String>, input: &'a str) -> Result<()> {
+        let template = Template::parse(input, self.opts.clone())?;
+        self.templates.insert(name.into(), template);
+        Ok(())
+    }
+
+    /// Add a named preset of options, replacing any preset with the same name
+    ///
+    /// The presets `strict`, `lenient` and `shell_like` are always available, and are the same as
+    /// [`Opts::strict`](./struct.Opts.html#method.strict), [`Opts::lenient`](./struct.Opts.html#method.lenient)
+    /// and [`Opts::shell_like`](./struct.Opts.html#method.shell_like) unless they are replaced
+    /// ```
+    /// # use markings::{Args, Opts, Escape, TemplateSet};
+    /// let mut set = TemplateSet::new(Opts::default());
+    /// set.add_preset("chat", Opts::lenient().escape(Escape::Markdown));
+    ///
+    /// set.add_with_preset("greeting", "chat", "hello ${name}").unwrap();
+    /// set.add_with_preset("farewell", "lenient", "bye").unwrap();
+    ///
+    /// let args = Args::new().with("name", "*bob*");
+    /// assert_eq!(set.apply("greeting", &args).unwrap(), r"hello \*bob\*");
+    /// ```
+    pub fn add_preset(&mut self, name: impl Into<String>, opts: Opts) {
+        self.presets.insert(name.into(), opts);
+    }
+
+    /// Get a preset of options by name
+    pub fn preset(&self, name: &str) -> Option<Opts> {
+        if let Some(opts) = self.presets.get(name) {
+            return Some(opts.clone());
+        }
+        match name {
+            "strict" => Some(Opts::strict()),
+            "lenient" => Some(Opts::lenient()),
+            "shell_like" => Some(Opts::shell_like()),
+            _ => None,
+        }
+    }
+
+    /// Parse a template with the options of a preset, and add it to the set
+    ///
+    /// This fails with `UnknownPreset` if there is no preset with that name
+    pub fn add_with_preset(
+        &mut self,
+        name: impl Into<String>,
+        preset: &str,
+        input: &'a str,
+    ) -> Result<()> {
+        let opts = self
+            .preset(preset)
+            .ok_or_else(|| ErrorKind::UnknownPreset {
+                name: preset.to_string(),
+            })?;
+        let template = Template::parse(input, opts)?;
+        self.templates.insert(name.into(), template);
+        Ok(())
+    }
+
+    /// Parse a template that extends the `base` template, and add it to the set
+    ///
+    /// This fails with `UnknownTemplate` if there is no `base` template in the set, and with `Sandboxed` if the
+    /// [`Sandbox`](./struct.Sandbox.html) of the set's options doesn't allow includes
+    pub fn extend(&mut self, name: impl Into<String>, base: &str, input: &'a str) -> Result<()> {
+        if !self.opts.allows_includes() {
+            let feature = "extends".into();
+            return Err(ErrorKind::Sandboxed { feature }.into());
+        }
+        let base = self
+            .templates
+            .get(base)
+            .ok_or_else(|| ErrorKind::UnknownTemplate {
+                name: base.to_string(),
+            })?;
+
+        // the child is only made of regions, so it doesn't need any keys
+        let mut opts = self.opts.clone();
+        opts.empty_template = true;
+        let child = Template::parse(input, opts)?;
+
+        let template = inherit(base, &child, self.opts.clone())?;
+        self.templates.insert(name.into(), template);
+        Ok(())
+    }
+
+    /// Get a template by name
+    ///
+    /// This counts as a hit or a miss in the [stats](#method.stats)
+    pub fn get(&self, name: &str) -> Option<&Template<'a>> {
+        let template = self.templates.get(name);
+        let counter = match template {
+            Some(..) => &self.counters.hits,
+            None => &self.counters.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        template
+    }
+
+    /// Apply the arguments to a template in the set
+    ///
+    /// This fails with `UnknownTemplate` if there is no template with that name
+    pub fn apply(&self, name: &str, args: &Args<'_>) -> Result<String> {
+        let output = self
+            .get(name)
+            .ok_or_else(|| ErrorKind::UnknownTemplate {
+                name: name.to_string(),
+            })?
+            .apply(args)?;
+
+        self.counters.renders.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes
+            .fetch_add(output.len() as u64, Ordering::Relaxed);
+        Ok(output)
+    }
+
+    /// Set the [`Schema`](./struct.Schema.html) of the args for a template, used by [`TemplateSet::apply_validated`](#method.apply_validated)
+    ///
+    /// The template doesn't have to be in the set yet
+    /// ```
+    /// # use markings::{Args, Opts, Schema, TemplateSet, ValueKind};
+    /// let mut set = TemplateSet::new(Opts::default());
+    /// set.add("cart", "${count} items").unwrap();
+    /// set.set_schema("cart", Schema::new().with("count", ValueKind::Int));
+    ///
+    /// assert_eq!(set.apply_validated("cart", &Args::new().with("count", 3)).unwrap(), "3 items");
+    /// assert!(set.apply_validated("cart", &Args::new().with("count", "three")).is_err());
+    /// ```
+    pub fn set_schema(&mut self, name: impl Into<String>, schema: Schema) {
+        self.schemas.insert(name.into(), schema);
+    }
+
+    /// Get the schema for a template
+    pub fn schema(&self, name: &str) -> Option<&Schema> {
+        self.schemas.get(name)
+    }
+
+    /// Check the arguments against the schema of a template, then apply them to it
+    ///
+    /// Templates without a schema are applied like [`TemplateSet::apply`](#method.apply).
+    /// This fails with `SchemaViolations` if the arguments don't match the schema
+    pub fn apply_validated(&self, name: &str, args: &Args<'_>) -> Result<String> {
+        if let Some(schema) = self.schemas.get(name) {
+            if let Some(template) = self.templates.get(name) {
+                schema.validate(template, args)?;
+            }
+        }
+        self.apply(name, args)
+    }
+
+    /// Counters for how the set has been used
+    ///
+    /// The counters are shared by every thread using the set, and start over for a clone of the set
+    /// ```
+    /// # use markings::{Args, Opts, TemplateSet};
+    /// let mut set = TemplateSet::new(Opts::default());
+    /// set.add("greeting", "hello ${name}").unwrap();
+    ///
+    /// set.apply("greeting", &Args::new().with("name", "bob")).unwrap();
+    /// set.apply("farewell", &Args::new()).unwrap_err();
+    ///
+    /// let stats = set.stats();
+    /// assert_eq!((stats.hits(), stats.misses()), (1, 1));
+    /// assert_eq!(stats.renders(), 1);
+    /// assert_eq!(stats.bytes(), 9);
+    /// ```
+    pub fn stats(&self) -> SetStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        SetStats {
+            hits: load(&self.counters.hits),
+            misses: load(&self.counters.misses),
+            renders: load(&self.counters.renders),
+            bytes: load(&self.counters.bytes),
+        }
+    }
+
+    /// Reset the [stats](#method.stats) to zero
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+
+    /// The names of the templates in the set, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.templates.keys().map(|name| &**name)
+    }
+}
+
+impl TemplateSet<'static> {
+    /// Load every `*.tpl` file in a directory into a set, named by the file name without the extension
+    ///
+    /// A file can start with a header between `---` lines, with one option per line. These options are
+    /// enabled for that file, on top of `opts`:
+    /// * `optional_keys`, `duplicate_keys`, `empty_template`, `case_insensitive_keys`, `bare_dollar_keys`, `percent_keys` and `trim_key_whitespace`
+    /// * `max_output_len: N`, `max_template_len: N`, `max_keys: N`, `max_key_len: N`, `max_depth: N` and `arg_uses: N`
+    /// * `extends: name`, which [extends](#method.extend) the template from `name.tpl`
+    /// * `preset: name`, which replaces the options with a [preset](#method.add_preset), so it should come first
+    /// * `version: N`, the version the file was written for. The template after the header is migrated to the version
+    ///   of the set, like [`TemplateSet::add_versioned`](#method.add_versioned). Files without one are at the version of the set
+    ///
+    /// ```text
+    /// ---
+    /// extends: email
+    /// optional_keys
+    /// ---
+    /// ${block body}Welcome to ${site}!${/block}
+    /// ```
+    ///
+    /// Unknown options, and headers that aren't closed, are an `UnsupportedSyntax` error. Extending a template
+    /// that isn't in the directory is an `UnknownTemplate` error, and one that extends the file back is an
+    /// `ExtendsCycle` error. Using
+    /// a preset that doesn't exist is an `UnknownPreset` error. Only the presets that are always available can be used
+    /// here, and only files without a version, or at version `0`, can be loaded. [`TemplateSet::add_dir`](#method.add_dir)
+    /// can use the presets and migrations of a set
+    pub fn from_dir(path: impl AsRef<Path>, opts: Opts) -> Result<Self> {
+        let mut set = Self::new(opts);
+        set.add_dir(path)?;
+        Ok(set)
+    }
+
+    /// Load every `*.tpl` file in a directory into the set, like [`TemplateSet::from_dir`](#method.from_dir)
+    ///
+    /// The files are added like [`TemplateSet::add_sources`](#method.add_sources)
+    /// ```no_run
+    /// # use markings::{Opts, TemplateSet};
+    /// let mut set = TemplateSet::new(Opts::default()).versioned(1);
+    /// // files with `version: 0` in their header used `${user}`
+    /// set.migration(0, |source| Ok(source.replace("${user}", "${name}")));
+    /// set.add_dir("templates").unwrap();
+    /// ```
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let mut sources = vec![];
+        for entry in std::fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.extension() != Some("tpl".as_ref()) || !path.is_file() {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                sources.push((name.to_string(), std::fs::read_to_string(&path)?));
+            }
+        }
+        self.add_sources(sources)
+    }
+
+    /// Create a set from the sources of named templates, like the files of [`TemplateSet::from_dir`](#method.from_dir)
+    ///
+    /// Each source can start with the same header as a file. See [`include_templates!`](./macro.include_templates.html)
+    /// to embed the files of a directory into the binary
+    pub fn from_sources<N, S>(sources: impl IntoIterator<Item = (N, S)>, opts: Opts) -> Result<Self>
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        let mut set = Self::new(opts);
+        set.add_sources(sources)?;
+        Ok(set)
+    }
+
+    /// Add the sources of named templates, like [`TemplateSet::from_sources`](#method.from_sources)
+    ///
+    /// This replaces any templates with the same names. The sources can extend templates that are already
+    /// in the set, and use its presets. If any of the sources fails to load, the set is left as it was
+    pub fn add_sources<N, S>(&mut self, sources: impl IntoIterator<Item = (N, S)>) -> Result<()>
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        let sources = sources
+            .into_iter()
+            .map(|(name, source)| (name.into(), source.into()))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut loaded = BTreeMap::new();
+        for name in sources.keys() {
+            self.load(name, &sources, &mut loaded, &mut vec![])?;
+        }
+        self.templates.extend(loaded);
+        Ok(())
+    }
+
+    // loads a template from the sources, after the template it extends. `loading` are the templates waiting on
+    // this one. templates that aren't in the sources are extended from the set
+    fn load<'s>(
+        &self,
+        name: &'s str,
+        sources: &'s BTreeMap<String, String>,
+        loaded: &mut BTreeMap<String, Template<'static>>,
+        loading: &mut Vec<&'s str>,
+    ) -> Result<()> {
+        if loaded.contains_key(name) {
+            return Ok(());
+        }
+        let source = match sources.get(name) {
+            Some(source) => source,
+            None if self.templates.contains_key(name) => return Ok(()),
+            None => {
+                let name = name.to_string();
+                return Err(ErrorKind::UnknownTemplate { name }.into());
+            }
+        };
+        if let Some(pos) = loading.iter().position(|&loading| loading == name) {
+            let names = loading[pos..].iter().chain(Some(&name));
+            let names = names.map(|name| name.to_string()).collect();
+            return Err(ErrorKind::ExtendsCycle { names }.into());
+        }
+
+        let (header, input) = Header::parse(source, self)?;
+        let migrated;
+        let input = match header.version {
+            Some(version) if version != self.version => {
+                migrated = self.migrate(input, version)?;
+                &*migrated
+            }
+            _ => input,
+        };
+        let template = match header.extends {
+            Some(base) => {
+                loading.push(name);
+                self.load(base, sources, loaded, loading)?;
+                loading.pop();
+
+                let mut opts = header.opts.clone();
+                opts.empty_template = true;
+                let child = Template::parse(input, opts)?.into_owned();
+                let base = loaded.get(base).unwrap_or_else(|| &self.templates[base]);
+                inherit(base, &child, header.opts)?
+            }
+            None => Template::parse(input, header.opts)?.into_owned(),
+        };
+        loaded.insert(name.to_string(), template);
+        Ok(())
+    }
+}
+
+/// Embed template files into the binary, and create a [`TemplateSet`](./struct.TemplateSet.html) from them
+///
+/// The files are `name.tpl` in a directory relative to the crate being built, and are read with `include_str!`,
+/// so there is no I/O at runtime. They are parsed when the macro is evaluated, like [`TemplateSet::from_sources`](./struct.TemplateSet.html#method.from_sources),
+/// and the same headers can be used. The [`Opts`](./struct.Opts.html) are optional, and are the default otherwise
+///
+/// A directory can't be listed at compile time, so each name has to be given
+/// ```
+/// # use markings::{include_templates, Args, Opts};
+/// let set = include_templates!("tests/templates", ["base", "greeting"], Opts::default()).unwrap();
+/// let args = Args::new().with("name", "bob");
+/// assert_eq!(set.apply("greeting", &args).unwrap(), "<hello bob>");
+/// ```
+#[macro_export]
+macro_rules! include_templates {
+    ($dir:literal, [$($name:literal),* $(,)?] $(,)?) => {
+        $crate::include_templates!($dir, [$($name),*], $crate::Opts::default())
+    };
+    ($dir:literal, [$($name:literal),* $(,)?], $opts:expr $(,)?) => {
+        $crate::TemplateSet::from_sources(
+            [$((
+                $name,
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dir, "/", $name, ".tpl")),
+            )),*],
+            $opts,
+        )
+    };
+}
+
+// the front matter of a template file
+struct Header<'s> {
+    opts: Opts,
+    extends: Option<&'s str>,
+    version: Option<u32>,
+}
+
+impl<'s> Header<'s> {
+    // the header of `source`, and the template after it
+    fn parse(source: &'s str, set: &TemplateSet<'_>) -> Result<(Self, &'s str)> {
+        let mut opts = set.opts.clone();
+        let (mut extends, mut version) = (None, None);
+        let lines = match source.strip_prefix("---") {
+            Some(rest) if rest.starts_with('\n') || rest.starts_with("\r\n") => {
+                source.split_inclusive('\n').skip(1)
+            }
+            _ => {
+                return Ok((
+                    Self {
+                        opts,
+                        extends,
+                        version,
+                    },
+                    source,
+                ))
+            }
+        };
+
+        let mut pos = source.find('\n').unwrap_or_default() + 1;
+        for line in lines {
+            let start = pos;
+            pos += line.len();
+            let line = line.trim();
+            if line == "---" {
+                return Ok((
+                    Self {
+                        opts,
+                        extends,
+                        version,
+                    },
+                    &source[pos..],
+                ));
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), Some(value.trim())),
+                None => (line, None),
+            };
+            let unsupported = || ErrorKind::UnsupportedSyntax { pos: start };
+            match (key, value) {
+                ("extends", Some(..)) if !set.opts.allows_includes() => {
+                    return Err(ErrorKind::Sandboxed {
+                        feature: "extends".into(),
+                    }
+                    .into())
+                }
+                ("extends", Some(base)) if !base.is_empty() => extends = Some(base),
+                ("version", Some(value)) => {
+                    version = Some(value.parse().map_err(|_| unsupported())?)
+                }
+                ("preset", Some(name)) => {
+                    opts = set.preset(name).ok_or_else(|| ErrorKind::UnknownPreset {
+                        name: name.to_string(),
+                    })?;
+                    if let Some(sandbox) = set.opts.sandboxed() {
+                        opts = opts.sandbox(sandbox);
+                    }
+                }
+                _ => opts = opts.with_option(key, value).ok_or_else(unsupported)?,
+            }
+        }
+
+        // the header was never closed
+        Err(ErrorKind::UnsupportedSyntax { pos: 0 }.into())
+    }
+}
+
+/// A snapshot of the counters of a [`TemplateSet`](./struct.TemplateSet.html), from [`TemplateSet::stats`](./struct.TemplateSet.html#method.stats)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SetStats {
+    hits: u64,
+    misses: u64,
+    renders: u64,
+    bytes: u64,
+}
+
+impl SetStats {
+    /// How many times a template was found by name
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// How many times a template wasn't found by name
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// How many templates were applied successfully
+    pub fn renders(&self) -> u64 {
+        self.renders
+    }
+
+    /// The total length, in bytes, of the output of every render
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+type Migrate = Arc<dyn Fn(&str) -> Result<String> + Send + Sync>;
+
+// migrations, by the version they upgrade from
+#[derive(Clone, Default)]
+struct Migrations(BTreeMap<u32, Migrate>);
+
+impl std::fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    renders: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Counters {
+    fn reset(&self) {
+        for counter in [&self.hits, &self.misses, &self.renders, &self.bytes] {
+            counter.store(0, Ordering::Relaxed)
+        }
+    }
+}
+
+// a cloned set counts its own usage
+impl Clone for Counters {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+// the `base` template, with its regions overridden by the regions of `child`
+fn inherit<'a>(base: &Template<'a>, child: &Template<'a>, opts: Opts) -> Result<Template<'a>> {
+    let mut overrides = BTreeMap::new();
+    let mut pos = 0;
+    while pos < child.segments.len() {
+        if let Segment::Block {
+            kind: BlockKind::Region,
+            name,
+            ..
+        } = &child.segments[pos]
+        {
+            let end = block_end(&child.segments, pos);
+            overrides.insert(&**name, &child.segments[pos + 1..end]);
+            pos = end;
+        }
+        pos += 1;
+    }
+
+    let mut template = Template::empty(opts);
+    let mut pos = 0;
+    while pos < base.segments.len() {
+        let segment = &base.segments[pos];
+        template.push(segment.clone());
+        pos += 1;
+
+        let body = match segment {
+            Segment::Block {
+                kind: BlockKind::Region,
+                name,
+                ..
+            } => overrides.get(&**name),
+            _ => None,
+        };
+        if let Some(body) = body {
+            for segment in body.iter() {
+                template.push(segment.clone());
+            }
+            // skip the default, but keep the end of the region so it can be overridden again
+            pos = block_end(&base.segments, pos - 1);
+        }
+    }
+
+    template.renumber();
+    template.validate()?;
+    Ok(template)
+}
+
+// the index of the end of the block that opens at `start`
+fn block_end(segments: &[Segment<'_>], start: usize) -> usize {
+    let mut depth = 0;
+    for (pos, segment) in segments.iter().enumerate().skip(start) {
+        match segment {
+            Segment::Block { kind, .. } if kind.opens() => depth += 1,
+            Segment::Block {
+                kind: BlockKind::End,
+                ..
+            } => {
+                depth -= 1;
+                if depth == 0 {
+                    return pos;
+                }
+            }
+            _ => {}
+        }
+    }
+    // templates are validated, so every block has an end
+    segments.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extend() {
+        let mut set = TemplateSet::new(Opts::default());
+        set.add(
+            "base",
+            "<${block title}${title}${/block}>${block body}[${block inner}inner${/block}]${/block}",
+        )
+        .unwrap();
+
+        set.extend("title", "base", "ignored ${block title}${name}!${/block}")
+            .unwrap();
+        assert_eq!(
+            set.get("title").unwrap().to_string(),
+            "<${block title}${name}!${/block}>${block body}[${block inner}inner${/block}]${/block}"
+        );
+        assert_eq!(
+            set.apply("title", &Args::new().with("name", "bob"))
+                .unwrap(),
+            "<bob!>[inner]"
+        );
+
+        // children can be extended again, and nested regions can be overridden
+        set.extend("inner", "title", "${block inner}${?x}x${/}${/block}")
+            .unwrap();
+        let args = Args::new().with("name", "bob").with("x", true);
+        assert_eq!(set.apply("inner", &args).unwrap(), "<bob!>[x]");
+
+        let err = set.extend("a", "missing", "").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::UnknownTemplate {
+                name: "missing".into()
+            }
+        );
+        let err = set.apply("missing", &Args::new()).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::UnknownTemplate {
+                name: "missing".into()
+            }
+        );
+
+        let err = set
+            .extend("dupe", "base", "${block body}${title}${/block}")
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::DuplicateKeys);
+
+        assert_eq!(
+            set.names().collect::<Vec<_>>(),
+            vec!["base", "inner", "title"]
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut set = TemplateSet::new(Opts::default());
+        set.add("a", "${x}!").unwrap();
+        assert_eq!(set.stats(), SetStats::default());
+
+        set.apply("a", &Args::new().with("x", "hello")).unwrap();
+        set.apply("a", &Args::new().with("y", 1)).unwrap_err();
+        set.apply("b", &Args::new()).unwrap_err();
+        assert!(set.get("a").is_some());
+
+        let stats = set.stats();
+        assert_eq!((stats.hits(), stats.misses()), (3, 1));
+        assert_eq!((stats.renders(), stats.bytes()), (1, 6));
+
+        assert_eq!(set.clone().stats(), SetStats::default());
+        set.reset_stats();
+        assert_eq!(set.stats(), SetStats::default());
+    }
+
+    #[test]
+    fn migrations() {
+        let mut set = TemplateSet::new(Opts::default()).versioned(3);
+        set.migration(0, |source| Ok(source.replace("user", "name")));
+        set.migration(2, |source| {
+            Template::parse(source, Opts::default())?
+                .replace_key_name("name", "user.name")
+                .map(|template| template.to_string())
+        });
+
+        let err = set.add_versioned("a", "${user}", 0).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedVersion { version: 1 });
+        assert!(set.get("a").is_none());
+
+        set.migration(1, |source| Ok(format!("hello {}", source)));
+        assert_eq!(set.add_versioned("a", "${user}", 0).unwrap(), vec![0, 1, 2]);
+        assert_eq!(set.get("a").unwrap().to_string(), "hello ${user.name}");
+        assert_eq!(set.add_versioned("b", "${name}", 2).unwrap(), vec![2]);
+        assert_eq!(set.get("b").unwrap().to_string(), "${user.name}");
+
+        let err = set.add_versioned("c", "${name}", 4).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedVersion { version: 4 });
+
+        // errors from a migration are returned as is
+        let err = set.add_versioned("d", "${name", 2).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::MismatchedBraces { .. }));
+
+        // loaded sources are migrated from the version in their header
+        set.add_sources(vec![
+            ("e", "---\nversion: 2\n---\n${name}"),
+            ("f", "---\nversion: 3\n---\n${name}"),
+            ("g", "${name}"),
+        ])
+        .unwrap();
+        assert_eq!(set.get("e").unwrap().to_string(), "${user.name}");
+        assert_eq!(set.get("f").unwrap().to_string(), "${name}");
+        assert_eq!(set.get("g").unwrap().to_string(), "${name}");
+
+        let err = set
+            .add_sources(vec![("h", "---\nversion: 4\n---\n${name}")])
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedVersion { version: 4 });
+        let err = set
+            .add_sources(vec![("h", "---\nversion: new\n---\n${name}")])
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 4 });
+    }
+
+    #[test]
+    fn from_dir() {
+        let dir = std::env::temp_dir().join(format!("markings-from-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, source: &str| std::fs::write(dir.join(name), source).unwrap();
+
+        write("base.tpl", "<${block body}${title}${/block}>");
+        write(
+            "child.tpl",
+            "---\nextends: base\noptional_keys\n---\n${block body}hi ${name}${/block}",
+        );
+        write("limited.tpl", "---\r\nmax_keys: 1\r\n---\r\n${a}");
+        write("ignored.txt", "${");
+
+        let set = TemplateSet::from_dir(&dir, Opts::default()).unwrap();
+        assert_eq!(
+            set.names().collect::<Vec<_>>(),
+            vec!["base", "child", "limited"]
+        );
+        let args = Args::new().with("name", "bob").with("extra", 1);
+        assert_eq!(set.apply("child", &args).unwrap(), "<hi bob>");
+        assert_eq!(set.get("limited").unwrap().to_string(), "${a}");
+
+        write("limited.tpl", "---\nmax_keys\n---\n${a}");
+        let err = TemplateSet::from_dir(&dir, Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 4 });
+
+        write("limited.tpl", "---\nextends: child\n");
+        let err = TemplateSet::from_dir(&dir, Opts::default()).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnsupportedSyntax { pos: 0 });
+
+        write("limited.tpl", "---\nextends: limited\n---\n");
+        let err = TemplateSet::from_dir(&dir, Opts::default()).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::ExtendsCycle {
+                names: vec!["limited".into(), "limited".into()]
+            }
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_templates() {
+        let set = crate::include_templates!("tests/templates", ["base", "greeting"]).unwrap();
+        assert_eq!(set.names().collect::<Vec<_>>(), vec!["base", "greeting"]);
+        assert_eq!(
+            set.get("greeting").unwrap().to_string(),
+            "<${block body}hello ${name}${/block}>"
+        );
+    }
+
+    #[test]
+    fn presets() {
+        let mut set = TemplateSet::new(Opts::default());
+        set.add_preset("keyless", Opts::default().empty_template());
+        assert_eq!(set.preset("lenient"), Some(Opts::lenient()));
+        assert_eq!(set.preset("missing"), None);
+
+        let err = set.add_with_preset("a", "missing", "hi").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::UnknownPreset {
+                name: "missing".into()
+            }
+        );
+
+        set.add_sources(vec![
+            ("a", "---\npreset: keyless\n---\nhi"),
+            ("b", "---\npreset: shell_like\nmax_keys: 1\n---\necho $x"),
+        ])
+        .unwrap();
+        assert_eq!(set.apply("a", &Args::new()).unwrap(), "hi");
+        assert_eq!(
+            set.apply("b", &Args::new().with("x", "a b")).unwrap(),
+            "echo 'a b'"
+        );
+
+        // presets can be replaced
+        set.add_preset("lenient", Opts::default());
+        let err = set
+            .add_sources(vec![("c", "---\npreset: lenient\n---\nhi")])
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::EmptyTemplate);
+    }
+
+    #[test]
+    fn add_sources() {
+        let mut set = TemplateSet::new(Opts::default().empty_template());
+        set.add_sources(vec![("a", "a"), ("b", "b")]).unwrap();
+
+        // nothing is replaced unless every source loads
+        let err = set
+            .add_sources(vec![("a", "new a"), ("b", "${b"), ("c", "c")])
+            .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::MismatchedBraces { open: 1, close: 0 }
+        );
+        assert_eq!(set.names().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(set.apply("a", &Args::new()).unwrap(), "a");
+
+        // sources can extend the templates they replace
+        set.add_sources(vec![("b", "${block body}b${/block}")])
+            .unwrap();
+        set.add_sources(vec![("c", "---\nextends: b\n---\n${block body}c${/block}")])
+            .unwrap();
+        assert_eq!(set.apply("c", &Args::new()).unwrap(), "c");
+
+        let err = set
+            .add_sources(vec![
+                ("x", "---\nextends: y\n---\n"),
+                ("y", "---\nextends: z\n---\n"),
+                ("z", "---\nextends: x\n---\n"),
+            ])
+            .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::ExtendsCycle {
+                names: vec!["x".into(), "y".into(), "z".into(), "x".into()]
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "templates extend each other: x -> y -> z -> x"
+        );
+    }
+}
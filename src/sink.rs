@@ -0,0 +1,196 @@
+use crate::{Args, Result, Template};
+use std::convert::Infallible;
+
+/// Where the output of a template is written, with [`Template::render_sink`](./struct.Template.html#method.render_sink)
+///
+/// It is implemented for `String` and `Vec<u8>`, and for any [`std::fmt::Write`] with [`FmtSink`](./struct.FmtSink.html)
+/// and any [`std::io::Write`] with [`IoSink`](./struct.IoSink.html). Other sinks can count, hash or stream the output:
+/// ```
+/// # use markings::{Template, Args, Opts, Sink};
+/// struct Count(usize);
+///
+/// impl Sink for Count {
+///     type Error = std::convert::Infallible;
+///     fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+///         self.0 += s.len();
+///         Ok(())
+///     }
+/// }
+///
+/// let template = Template::parse("hello ${name}", Opts::default()).unwrap();
+/// let mut count = Count(0);
+/// template.render_sink(&Args::new().with("name", "bob"), &mut count).unwrap();
+/// assert_eq!(count.0, 9);
+/// ```
+pub trait Sink {
+    /// The error of a failed write
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Write a part of the output
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error>;
+
+    /// Prepare for `additional` more bytes of output. This does nothing by default
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+}
+
+impl Sink for String {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.push_str(s);
+        Ok(())
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        String::reserve(self, additional)
+    }
+}
+
+impl Sink for Vec<u8> {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional)
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    type Error = S::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        (**self).write_str(s)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        (**self).reserve(additional)
+    }
+}
+
+/// A [`Sink`](./trait.Sink.html) for a [`std::fmt::Write`], like a `Formatter`
+#[derive(Debug, Default)]
+pub struct FmtSink<W>(pub W);
+
+impl<W: std::fmt::Write> Sink for FmtSink<W> {
+    type Error = std::fmt::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.0.write_str(s)
+    }
+}
+
+/// A [`Sink`](./trait.Sink.html) for a [`std::io::Write`], like a file or a socket
+///
+/// Each part of the output is written on its own, so a writer that isn't buffered should be wrapped in a `BufWriter`
+#[derive(Debug, Default)]
+pub struct IoSink<W>(pub W);
+
+impl<W: std::io::Write> Sink for IoSink<W> {
+    type Error = std::io::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.0.write_all(s.as_bytes())
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Apply the arguments to the template, writing each part of the output to a [`Sink`](./trait.Sink.html)
+    ///
+    /// Nothing is written if applying fails before rendering, like with missing keys. A sink that fails to write
+    /// is an `Io` error, with the sink's error as its source
+    /// ```
+    /// # use markings::{Template, Args, Opts, IoSink};
+    /// let template = Template::parse("hello ${name}", Opts::default()).unwrap();
+    /// let mut out = IoSink(vec![]);
+    /// template.render_sink(&Args::new().with("name", "bob"), &mut out).unwrap();
+    /// assert_eq!(out.0, b"hello bob");
+    /// ```
+    pub fn render_sink(&self, args: &Args<'_>, mut sink: impl Sink) -> Result<()> {
+        self.render_into(args, &mut sink, |_, _, _| {})
+    }
+
+    /// Hash the output of applying the arguments to the template, without building the output
+    ///
+    /// Equal outputs have equal hashes, so this can tell whether rendering again would change anything.
+    /// The hash is the 64-bit FNV-1a of the output, which doesn't change between runs or versions of the crate,
+    /// so it can be stored. It isn't a cryptographic hash
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("${count} new messages", Opts::default()).unwrap();
+    /// let hash = template.render_hash(&Args::new().with("count", 3)).unwrap();
+    /// assert_eq!(hash, template.render_hash(&Args::new().with("count", "3")).unwrap());
+    /// assert_ne!(hash, template.render_hash(&Args::new().with("count", 4)).unwrap());
+    /// ```
+    pub fn render_hash(&self, args: &Args<'_>) -> Result<u64> {
+        let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+        self.render_into(args, &mut hasher, |_, _, _| {})?;
+        Ok(hasher.0)
+    }
+}
+
+// the 64-bit FNV-1a hash of everything written
+struct Fnv(u64);
+
+impl Sink for Fnv {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        for &byte in s.as_bytes() {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, Opts};
+
+    #[test]
+    fn sinks() {
+        let template = Template::parse("${a} and ${b}", Opts::default()).unwrap();
+        let args = Args::new().with("a", 1).with("b", "ü");
+
+        let mut string = String::from("> ");
+        template.render_sink(&args, &mut string).unwrap();
+        assert_eq!(string, "> 1 and ü");
+
+        let mut bytes = vec![];
+        template.render_sink(&args, &mut bytes).unwrap();
+        assert_eq!(bytes, "1 and ü".as_bytes());
+
+        let mut fmt = FmtSink(String::new());
+        template.render_sink(&args, &mut fmt).unwrap();
+        assert_eq!(fmt.0, "1 and ü");
+
+        let mut buf = [0; 4];
+        let err = template
+            .render_sink(&args, IoSink(&mut buf[..]))
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::Io);
+        assert_eq!(&buf, b"1 an");
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn hash() {
+        let template = Template::parse("${a}${b}", Opts::default()).unwrap();
+        let hash = |a, b| template.render_hash(&Args::new().with("a", a).with("b", b));
+
+        // the hash of the output, however it was split
+        assert_eq!(hash("ab", "c").unwrap(), hash("a", "bc").unwrap());
+        assert_ne!(hash("ab", "c").unwrap(), hash("a", "c").unwrap());
+        assert_eq!(hash("", "").unwrap(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash("a", "").unwrap(), 0xaf63_dc4c_8601_ec8c);
+
+        assert!(template.render_hash(&Args::new()).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{Args, Result, Template};
+use std::ops::Range;
+
+/// Relates the ranges of an output to the parts of the template they were rendered from
+///
+/// See [`Template::apply_mapped`](./struct.Template.html#method.apply_mapped)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    pub(crate) mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Every rendered part of the output, in order
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// The part of the output at a byte offset of it, if there is one
+    pub fn at(&self, offset: usize) -> Option<&Mapping> {
+        let index = self
+            .mappings
+            .partition_point(|mapping| mapping.output.end <= offset);
+        self.mappings
+            .get(index)
+            .filter(|mapping| mapping.output.contains(&offset))
+    }
+}
+
+/// A part of an output, in a [`SourceMap`](./struct.SourceMap.html)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub(crate) output: Range<usize>,
+    pub(crate) source: Range<usize>,
+    pub(crate) key: Option<String>,
+}
+
+impl Mapping {
+    /// The byte range of the output
+    pub fn output(&self) -> Range<usize> {
+        self.output.clone()
+    }
+
+    /// The byte range of the template source it was rendered from, like literal text or a key's marker
+    pub fn source(&self) -> Range<usize> {
+        self.source.clone()
+    }
+
+    /// The key whose value was written
+    ///
+    /// This is `None` for literal text, for expressions, and for keys without a value, which are written as their marker
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Apply the arguments to the template, also returning a [`SourceMap`](./struct.SourceMap.html) of the output
+    ///
+    /// The source map relates each part of the output to the literal text or key of the template it came from,
+    /// so tools can find the source of any part of a rendered template
+    /// ```
+    /// # use markings::{Template, Args, Opts};
+    /// let template = Template::parse("hello ${name}!", Opts::default()).unwrap();
+    /// let (output, map) = template.apply_mapped(&Args::new().with("name", "bob")).unwrap();
+    /// assert_eq!(output, "hello bob!");
+    ///
+    /// let mapping = map.at(7).unwrap();
+    /// assert_eq!(mapping.key(), Some("name"));
+    /// assert_eq!(mapping.output(), 6..9);
+    /// assert_eq!(mapping.source(), 6..13);
+    /// ```
+    pub fn apply_mapped(&self, args: &Args<'_>) -> Result<(String, SourceMap)> {
+        let (pieces, len) = self.pieces(args)?;
+        let mut output = String::with_capacity(len);
+        let mut map = SourceMap::default();
+        for (piece, key, source) in pieces {
+            let start = output.len();
+            output.push_str(&piece);
+            map.mappings.push(Mapping {
+                output: start..output.len(),
+                source,
+                key: key.map(str::to_string),
+            });
+        }
+        Ok((output, map))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn mappings() {
+        let opts = Opts::default().optional_keys().expressions();
+        let input = "${?show}a ${name|title}${/}${missing} ${n * 2}";
+        let template = Template::parse(input, opts).unwrap();
+
+        let args = Args::new().with("show", true).with("name", "").with("n", 2);
+        let (output, map) = template.apply_mapped(&args).unwrap();
+        assert_eq!(output, "a ${missing} 4");
+
+        let mappings = map
+            .mappings()
+            .iter()
+            .map(|mapping| (mapping.output(), mapping.source(), mapping.key()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            mappings,
+            vec![
+                (0..2, 8..10, None),
+                (2..2, 10..23, Some("name")),
+                (2..12, 27..37, None),
+                (12..13, 37..38, None),
+                (13..14, 38..46, None),
+            ]
+        );
+
+        // empty parts are skipped
+        assert_eq!(map.at(2).unwrap().source(), 27..37);
+        assert_eq!(map.at(13).unwrap().source(), 38..46);
+        assert!(map.at(14).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+//! [proptest](https://docs.rs/proptest) strategies for templates, with the `testutil` feature
+//!
+//! These generate valid inputs, so code that composes templates can be property tested:
+//! ```
+//! # use markings::{Template, testutil};
+//! use proptest::test_runner::TestRunner;
+//!
+//! let strategy = (testutil::template_with_args(), testutil::opts());
+//! TestRunner::default()
+//!     .run(&strategy, |((input, args), opts)| {
+//!         let template = Template::parse(&input, opts).unwrap();
+//!         assert!(template.apply(&args).is_ok());
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+use crate::{Args, Opts};
+use proptest::prelude::*;
+
+/// A key, like `name` or `user_2`
+pub fn key() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9_]{0,7}"
+}
+
+/// Literal text, without any `$`, `{` or `}`
+pub fn literal() -> impl Strategy<Value = String> {
+    "[^${}]{0,8}"
+}
+
+/// The source of a template that parses with any of the [`opts`]
+///
+/// Its keys are unique, even when compared case insensitively
+pub fn template() -> impl Strategy<Value = String> {
+    template_keys().prop_flat_map(|keys| {
+        let literals = prop::collection::vec(literal(), keys.len() + 1);
+        (Just(keys), literals).prop_map(|(keys, literals)| render(&keys, &literals))
+    })
+}
+
+/// The source of a template from [`template`], and [`Args`](../struct.Args.html) for each of its keys
+pub fn template_with_args() -> impl Strategy<Value = (String, Args<'static>)> {
+    template_keys().prop_flat_map(|keys| {
+        let literals = prop::collection::vec(literal(), keys.len() + 1);
+        (literals, args_for(keys.clone()))
+            .prop_map(move |(literals, args)| (render(&keys, &literals), args))
+    })
+}
+
+/// [`Args`](../struct.Args.html) with a value for each of the `keys`
+pub fn args_for(keys: Vec<String>) -> impl Strategy<Value = Args<'static>> {
+    let values = prop::collection::vec(any::<String>(), keys.len());
+    values.prop_map(move |values| keys.iter().cloned().zip(values).collect())
+}
+
+/// [`Opts`](../struct.Opts.html) with any combination of the options that don't reject a [`template`]
+pub fn opts() -> impl Strategy<Value = Opts> {
+    prop::array::uniform6(any::<bool>()).prop_map(|flags| {
+        let options = [
+            Opts::optional_keys,
+            Opts::duplicate_keys,
+            Opts::empty_template,
+            Opts::case_insensitive_keys,
+            Opts::bare_dollar_keys,
+            Opts::trim_key_whitespace,
+        ];
+        options
+            .iter()
+            .zip(flags.iter())
+            .filter(|(_, &flag)| flag)
+            .fold(Opts::default(), |opts, (option, _)| option(opts))
+            .build()
+    })
+}
+
+// unique keys, in any order
+fn template_keys() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::btree_set(key(), 1..6)
+        .prop_map(|keys| keys.into_iter().collect::<Vec<_>>())
+        .prop_shuffle()
+}
+
+// `literals` has one more element than `keys`
+fn render(keys: &[String], literals: &[String]) -> String {
+    let mut input = literals[0].clone();
+    for (key, literal) in keys.iter().zip(&literals[1..]) {
+        input.push_str("${");
+        input.push_str(key);
+        input.push('}');
+        input.push_str(literal);
+    }
+    input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Template;
+
+    proptest! {
+        #[test]
+        fn round_trip(input in template(), opts in opts()) {
+            let template = Template::parse(&input, opts.clone()).unwrap();
+            let output = template.to_string();
+            prop_assert_eq!(&output, &input);
+            prop_assert_eq!(Template::parse(&output, opts).unwrap(), template);
+        }
+
+        #[test]
+        fn apply((input, args) in template_with_args(), opts in opts()) {
+            let template = Template::parse(&input, opts).unwrap();
+            prop_assert!(template.apply(&args).is_ok());
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{Args, Result, Template};
+use std::time::Instant;
+use tracing::field::Empty;
+
+// runs `parse` in a `parse` span, with the length of the input, and the number of keys and how long it took once parsed
+pub(crate) fn parse<'a>(
+    input: &str,
+    parse: impl FnOnce() -> Result<Template<'a>>,
+) -> Result<Template<'a>> {
+    let span = tracing::debug_span!(
+        "parse",
+        len = input.len(),
+        keys = Empty,
+        duration_us = Empty
+    )
+    .entered();
+
+    let start = Instant::now();
+    let result = parse();
+    span.record("duration_us", start.elapsed().as_micros() as u64);
+    match &result {
+        Ok(template) => {
+            span.record("keys", template.keys().count());
+        }
+        Err(err) => tracing::debug!(error = %err, "failed to parse template"),
+    }
+    result
+}
+
+// runs `apply` in an `apply` span, with the number of keys and args, and the length of the output and how long it took
+//
+// keys without a value are reported as events while applying
+pub(crate) fn apply<T>(
+    template: &Template<'_>,
+    args: &Args<'_>,
+    apply: impl FnOnce() -> Result<(T, usize)>,
+) -> Result<(T, usize)> {
+    let span = tracing::debug_span!(
+        "apply",
+        keys = template.keys().count(),
+        args = args.len(),
+        output_len = Empty,
+        duration_us = Empty
+    )
+    .entered();
+
+    let start = Instant::now();
+    let result = apply();
+    span.record("duration_us", start.elapsed().as_micros() as u64);
+    match &result {
+        Ok((_, len)) => {
+            span.record("output_len", len);
+        }
+        Err(err) => tracing::debug!(error = %err, "failed to apply template"),
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Args, Opts, Template};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // records the fields of spans and events as `name field=value` lines
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Line<'a>(&'a mut String);
+
+    impl Visit for Line<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            // durations change between runs
+            if field.name() != "duration_us" {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut line = span.metadata().name().to_string();
+            span.record(&mut Line(&mut line));
+            let mut lines = self.0.lock().unwrap();
+            lines.push(line);
+            Id::from_u64(lines.len() as u64)
+        }
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut lines = self.0.lock().unwrap();
+            values.record(&mut Line(&mut lines[span.into_u64() as usize - 1]));
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut line = String::from("event");
+            event.record(&mut Line(&mut line));
+            self.0.lock().unwrap().push(line);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn spans() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let opts = Opts::default().optional_keys();
+            let template = Template::parse("${greeting}, ${name}", opts.clone()).unwrap();
+            template.apply(&Args::new().with("name", "bob")).unwrap();
+            Template::parse("${name", opts).unwrap_err();
+        });
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "parse len=20 keys=2",
+                "apply keys=2 args=1 output_len=16",
+                r#"event message=key has no value key="greeting""#,
+                "parse len=6",
+                "event message=failed to parse template error=found 1 open braces, and 0 closed braces. a mistmatch",
+            ]
+        );
+    }
+}
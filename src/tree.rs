@@ -0,0 +1,235 @@
+use crate::{Args, Error, Opts, Result, Template};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// Render every file in a directory as a template, writing the outputs to another directory
+///
+/// This is the same as [`TreeRenderer::render`](./struct.TreeRenderer.html#method.render) without
+/// rendering file names
+/// ```no_run
+/// # use markings::{Args, Opts, render_tree};
+/// let args = Args::new().with("project", "hello").with("author", "bob");
+/// let written = render_tree("templates/crate", "hello", &args, Opts::default()).unwrap();
+/// println!("created {} files", written.len());
+/// ```
+pub fn render_tree(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    args: &Args<'_>,
+    opts: Opts,
+) -> Result<Vec<PathBuf>> {
+    TreeRenderer::new(opts).render(src, dst, args)
+}
+
+/// Renders the files of a directory as templates, like a project scaffold
+///
+/// Every file is parsed with the options, allowing files without keys, and its output is written to the same
+/// path in the destination directory. Files that aren't UTF-8, like images, are copied as they are
+#[derive(Clone, Debug)]
+pub struct TreeRenderer {
+    opts: Opts,
+    file_names: bool,
+}
+
+impl TreeRenderer {
+    /// Create a renderer that parses files with the options
+    pub fn new(opts: Opts) -> Self {
+        Self {
+            opts,
+            file_names: false,
+        }
+    }
+
+    /// Also render the names of files and directories, so `${name}.rs` can be written as `hello.rs`
+    ///
+    /// A name that renders to an empty name, `.`, `..` or a name with a path separator is an `Io` error
+    #[must_use]
+    pub fn file_names(mut self) -> Self {
+        self.file_names = true;
+        self
+    }
+
+    /// Render the files in `src` with the args, writing them to `dst`, and return the paths that were written
+    ///
+    /// Directories are walked in order of their names, and missing directories are created. Symbolic links aren't
+    /// followed, so they are skipped. The first error stops rendering, and the files written before it are kept
+    ///
+    /// `dst` can't be `src` or inside of it, which is an `Io` error
+    /// ```
+    /// # use markings::{Args, Opts, TreeRenderer};
+    /// # let root = std::env::temp_dir().join(format!("markings-tree-doc-{}", std::process::id()));
+    /// # let (src, dst) = (root.join("src"), root.join("dst"));
+    /// # std::fs::create_dir_all(src.join("src")).unwrap();
+    /// std::fs::write(src.join("src/${name}.rs"), "// the ${name} module").unwrap();
+    ///
+    /// let args = Args::new().with("name", "hello");
+    /// let written = TreeRenderer::new(Opts::default()).file_names().render(&src, &dst, &args).unwrap();
+    /// assert_eq!(written, vec![dst.join("src/hello.rs")]);
+    /// assert_eq!(std::fs::read_to_string(&written[0]).unwrap(), "// the hello module");
+    /// # std::fs::remove_dir_all(&root).unwrap();
+    /// ```
+    pub fn render(
+        &self,
+        src: impl AsRef<Path>,
+        dst: impl AsRef<Path>,
+        args: &Args<'_>,
+    ) -> Result<Vec<PathBuf>> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        if resolve(dst)?.starts_with(src.canonicalize()?) {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{:?} is inside of {:?}", dst, src),
+            );
+            return Err(Error::io(err));
+        }
+
+        let mut written = vec![];
+        self.render_dir(src, dst, args, &mut written)?;
+        Ok(written)
+    }
+
+    fn render_dir(
+        &self,
+        src: &Path,
+        dst: &Path,
+        args: &Args<'_>,
+        written: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let mut entries = std::fs::read_dir(src)?
+            .map(|entry| entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+        std::fs::create_dir_all(dst)?;
+
+        for (path, kind) in entries {
+            if kind.is_symlink() {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default();
+            let dst = dst.join(self.file_name(name, args)?);
+            if kind.is_dir() {
+                self.render_dir(&path, &dst, args, written)?;
+                continue;
+            }
+
+            match std::fs::read_to_string(&path) {
+                Ok(source) => std::fs::write(&dst, self.apply(&source, args)?)?,
+                Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                    std::fs::copy(&path, &dst)?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+            written.push(dst);
+        }
+        Ok(())
+    }
+
+    fn file_name(&self, name: &OsStr, args: &Args<'_>) -> Result<OsString> {
+        let name = match name.to_str() {
+            Some(name) if self.file_names => name,
+            _ => return Ok(name.to_os_string()),
+        };
+
+        let output = self.apply(name, args)?;
+        if matches!(&*output, "" | "." | "..") || output.chars().any(std::path::is_separator) {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{:?} rendered to the file name {:?}", name, output),
+            );
+            return Err(Error::io(err));
+        }
+        Ok(output.into())
+    }
+
+    fn apply(&self, source: &str, args: &Args<'_>) -> Result<String> {
+        let mut opts = self.opts.clone();
+        opts.empty_template = true;
+        Template::parse(source, opts)?.apply(args)
+    }
+}
+
+// the absolute path of `path`, which doesn't have to exist yet, with the links of the part that exists resolved
+fn resolve(path: &Path) -> Result<PathBuf> {
+    let mut rest = vec![];
+    let mut existing = path;
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => return Ok(resolved.join(rest.into_iter().rev().collect::<PathBuf>())),
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            Err(err) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    rest.push(name);
+                    existing = match parent.as_os_str().is_empty() {
+                        true => Path::new("."),
+                        false => parent,
+                    };
+                }
+                _ => return Err(err.into()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn render() {
+        let root = std::env::temp_dir().join(format!("markings-tree-{}", std::process::id()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(src.join("${dir}")).unwrap();
+        let write = |name: &str, data: &[u8]| std::fs::write(src.join(name), data).unwrap();
+
+        write("README", b"# ${name}");
+        write("${dir}/${name}.txt", b"plain");
+        write("${dir}/image.bin", b"\xff${name}");
+
+        let args = Args::new().with("name", "hello").with("dir", "docs");
+        let written = render_tree(&src, &dst, &args, Opts::default()).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                dst.join("${dir}/${name}.txt"),
+                dst.join("${dir}/image.bin"),
+                dst.join("README"),
+            ]
+        );
+        let read = |name: &str| std::fs::read(dst.join(name)).unwrap();
+        assert_eq!(read("README"), b"# hello");
+        assert_eq!(read("${dir}/image.bin"), b"\xff${name}");
+
+        let dst = root.join("named");
+        let renderer = TreeRenderer::new(Opts::default()).file_names();
+        let written = renderer.render(&src, &dst, &args).unwrap();
+        assert_eq!(written[0], dst.join("docs/hello.txt"));
+
+        let err = renderer
+            .render(&src, &dst, &args.clone().with("dir", "../up"))
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::Io);
+
+        // the output can't be written inside of the templates, which it would be rendered from again
+        for inside in [&src, &src.join("new/dir")] {
+            let err = renderer.render(&src, inside, &args).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::Io);
+        }
+        assert!(!src.join("new").exists());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&src, src.join("${dir}/loop")).unwrap();
+            let dst = root.join("linked");
+            renderer.render(&src, &dst, &args).unwrap();
+            assert!(!dst.join("docs/loop").exists());
+            std::fs::remove_file(src.join("${dir}/loop")).unwrap();
+        }
+
+        write("README", b"# ${missing}");
+        let err = renderer.render(&src, &dst, &args).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::MissingKeys { .. }));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
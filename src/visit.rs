@@ -0,0 +1,115 @@
+use crate::{BlockKind, Segment, Template};
+use std::ops::Range;
+
+/// A visitor over the parsed parts of a template, used with [`Template::visit`](./struct.Template.html#method.visit)
+///
+/// Each part is visited in order, with the byte range of the template source it was parsed from.
+/// Every method does nothing by default, so only the interesting parts need to be implemented.
+/// ```
+/// # use markings::{Template, Opts, TemplateVisitor, BlockKind};
+/// # use std::ops::Range;
+/// // lists the keys used by conditional blocks
+/// #[derive(Default)]
+/// struct Conditions(Vec<String>);
+///
+/// impl TemplateVisitor for Conditions {
+///     fn visit_block(&mut self, kind: BlockKind, name: &str, _: Range<usize>) {
+///         if let BlockKind::If | BlockKind::ElseIf = kind {
+///             self.0.push(name.to_string());
+///         }
+///     }
+/// }
+///
+/// let template = Template::parse("${?admin}!${:?mod}?${/} ${name}", Opts::default()).unwrap();
+/// let mut conditions = Conditions::default();
+/// template.visit(&mut conditions);
+/// assert_eq!(conditions.0, vec!["admin", "mod"]);
+/// ```
+pub trait TemplateVisitor {
+    /// Visit literal text
+    fn visit_literal(&mut self, text: &str, span: Range<usize>) {
+        let _ = (text, span);
+    }
+
+    /// Visit a key, and the filters applied to it
+    ///
+    /// Anonymous keys have the name of their position, like `0`
+    fn visit_key(&mut self, name: &str, filters: &[&str], span: Range<usize>) {
+        let _ = (name, filters, span);
+    }
+
+    /// Visit a block marker. `name` is empty for markers without a name, like `${:else}` and `${/}`
+    fn visit_block(&mut self, kind: BlockKind, name: &str, span: Range<usize>) {
+        let _ = (kind, name, span);
+    }
+
+    /// Visit an expression, like `${count + 1}`, and the keys it uses
+    ///
+    /// See [`Opts::expressions`](./struct.Opts.html#method.expressions)
+    fn visit_expr(&mut self, expr: &str, keys: &[&str], span: Range<usize>) {
+        let _ = (expr, keys, span);
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Visit each part of the template, in order
+    ///
+    /// See [`TemplateVisitor`](./trait.TemplateVisitor.html)
+    pub fn visit(&self, visitor: &mut impl TemplateVisitor) {
+        for (segment, span) in self.spans() {
+            match segment {
+                Segment::Literal(literal) => visitor.visit_literal(literal, span),
+                Segment::Key { name, filters, .. } => {
+                    let filters = filters.iter().map(|filter| &**filter).collect::<Vec<_>>();
+                    visitor.visit_key(name, &filters, span)
+                }
+                Segment::Block { kind, name, .. } => visitor.visit_block(*kind, name, span),
+                Segment::Expr { expr, .. } => {
+                    let keys = expr.keys().collect::<Vec<_>>();
+                    visitor.visit_expr(expr.as_str(), &keys, span)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Opts;
+
+    #[test]
+    fn visit() {
+        #[derive(Default)]
+        struct Parts(Vec<String>);
+
+        impl TemplateVisitor for Parts {
+            fn visit_literal(&mut self, text: &str, span: Range<usize>) {
+                self.0.push(format!("{:?} {:?}", text, span));
+            }
+            fn visit_key(&mut self, name: &str, filters: &[&str], span: Range<usize>) {
+                self.0
+                    .push(format!("key {} {:?} {:?}", name, filters, span));
+            }
+            fn visit_block(&mut self, kind: BlockKind, name: &str, span: Range<usize>) {
+                self.0.push(format!("{:?} {} {:?}", kind, name, span));
+            }
+        }
+
+        let template =
+            Template::parse("hi ${@u}${name|red|bold}${/} ${}", Opts::default()).unwrap();
+        let mut parts = Parts::default();
+        template.visit(&mut parts);
+        assert_eq!(
+            parts.0,
+            vec![
+                r#""hi " 0..3"#,
+                "With u 3..8",
+                r#"key name ["red", "bold"] 8..24"#,
+                "End  24..28",
+                r#"" " 28..29"#,
+                "key 0 [] 29..32",
+            ]
+        );
+    }
+}
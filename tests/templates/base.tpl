@@ -0,0 +1 @@
+<${block body}${title}${/block}>
\ No newline at end of file
@@ -0,0 +1,4 @@
+---
+extends: base
+---
+${block body}hello ${name}${/block}
\ No newline at end of file